@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub const AUDIT_LOG_PATH: &str = "/var/log/loki-master/audit.log";
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+const KEEP_ROTATED: usize = 3;
+/// The log names other users' processes, so only root may read it.
+const LOG_MODE: u32 = 0o600;
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+/// Identity of the process on the other end of the socket.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Peer {
+    pub uid: Option<u32>,
+    pub pid: Option<i32>,
+    pub exe: Option<String>,
+}
+
+impl Peer {
+    pub fn from_stream(stream: &tokio::net::UnixStream) -> Peer {
+        match stream.peer_cred() {
+            Ok(cred) => {
+                let pid = cred.pid();
+                let exe = pid.and_then(|p| {
+                    std::fs::read_link(format!("/proc/{p}/exe"))
+                        .ok()
+                        .map(|p| p.to_string_lossy().into_owned())
+                });
+                Peer { uid: Some(cred.uid()), pid, exe }
+            }
+            Err(e) => {
+                eprintln!("failed to read peer credentials: {e}");
+                Peer::default()
+            }
+        }
+    }
+}
+
+/// One line of the audit log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// Milliseconds since the Unix epoch when the request was received.
+    pub ts_ms: u64,
+    #[serde(flatten)]
+    pub peer: Peer,
+    pub cmd: String,
+    pub params: serde_json::Value,
+    pub success: bool,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Filter for `History` requests. All fields are optional.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Query {
+    #[serde(default)]
+    pub since_ms: Option<u64>,
+    #[serde(default)]
+    pub until_ms: Option<u64>,
+    #[serde(default)]
+    pub uid: Option<u32>,
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    fn matches(&self, e: &Entry) -> bool {
+        self.since_ms.is_none_or(|s| e.ts_ms >= s)
+            && self.until_ms.is_none_or(|u| e.ts_ms <= u)
+            && self.uid.is_none_or(|u| e.peer.uid == Some(u))
+            && self.command.as_deref().is_none_or(|c| e.cmd == c)
+    }
+}
+
+/// Append-only JSON-lines log of every request the daemon handles, rotated
+/// by size into `<path>.1`..`<path>.N`.
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    lock: Mutex<()>,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn rotated(path: &Path, idx: usize) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(format!(".{idx}"));
+    PathBuf::from(s)
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> AuditLog {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                eprintln!("failed to create {}: {e}", dir.display());
+            }
+        }
+        // Logs written by older versions were created with the umask.
+        for file in (1..=KEEP_ROTATED).map(|i| rotated(&path, i)).chain([path.clone()]) {
+            if let Err(e) =
+                std::fs::set_permissions(&file, std::fs::Permissions::from_mode(LOG_MODE))
+            {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    eprintln!("failed to restrict {}: {e}", file.display());
+                }
+            }
+        }
+        AuditLog { path, max_bytes: MAX_LOG_BYTES, lock: Mutex::new(()) }
+    }
+
+    pub async fn record(&self, entry: &Entry) {
+        let line = match serde_json::to_string(entry) {
+            Ok(l) => l + "\n",
+            Err(e) => {
+                eprintln!("failed to serialize audit entry: {e}");
+                return;
+            }
+        };
+        let _guard = self.lock.lock().await;
+        if let Err(e) = self.rotate_if_needed().await {
+            eprintln!("failed to rotate {}: {e}", self.path.display());
+        }
+        let res = async {
+            let mut f = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(LOG_MODE)
+                .open(&self.path)
+                .await?;
+            f.write_all(line.as_bytes()).await
+        }
+        .await;
+        if let Err(e) = res {
+            eprintln!("failed to write {}: {e}", self.path.display());
+            eprint!("audit: {line}");
+        }
+    }
+
+    async fn rotate_if_needed(&self) -> std::io::Result<()> {
+        match tokio::fs::metadata(&self.path).await {
+            Ok(m) if m.len() >= self.max_bytes => {}
+            _ => return Ok(()),
+        }
+        for idx in (1..KEEP_ROTATED).rev() {
+            let from = rotated(&self.path, idx);
+            if tokio::fs::metadata(&from).await.is_ok() {
+                tokio::fs::rename(&from, rotated(&self.path, idx + 1)).await?;
+            }
+        }
+        // Renaming keeps the file's mode, so rotated logs stay private.
+        tokio::fs::rename(&self.path, rotated(&self.path, 1)).await
+    }
+
+    /// Return the newest entries matching `query`, oldest first.
+    pub async fn query(&self, query: &Query) -> Vec<Entry> {
+        let _guard = self.lock.lock().await;
+        let mut files: Vec<PathBuf> = (1..=KEEP_ROTATED)
+            .rev()
+            .map(|i| rotated(&self.path, i))
+            .collect();
+        files.push(self.path.clone());
+
+        let mut out = Vec::new();
+        for file in files {
+            let Ok(text) = tokio::fs::read_to_string(&file).await else {
+                continue;
+            };
+            out.extend(
+                text.lines()
+                    .filter_map(|l| serde_json::from_str::<Entry>(l).ok())
+                    .filter(|e| query.matches(e)),
+            );
+        }
+        let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+        if out.len() > limit {
+            out.drain(..out.len() - limit);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::TempRoot;
+
+    fn entry(ts_ms: u64, uid: u32, cmd: &str) -> Entry {
+        Entry {
+            ts_ms,
+            peer: Peer { uid: Some(uid), pid: None, exe: None },
+            cmd: cmd.into(),
+            params: serde_json::json!({ "value": ts_ms }),
+            success: true,
+            error: None,
+            duration_ms: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn rotates_at_the_size_cap_and_filters_queries() {
+        let tmp = TempRoot::new("audit");
+        let path = tmp.root.join("log/audit.log");
+        let log = AuditLog { max_bytes: 300, ..AuditLog::new(&path) };
+        for ts in 1..=20 {
+            let (uid, cmd) = if ts % 2 == 0 { (0, "set_tdp") } else { (1000, "set_brightness") };
+            log.record(&entry(ts, uid, cmd)).await;
+        }
+
+        for file in (1..=KEEP_ROTATED).map(|i| rotated(&path, i)).chain([path.clone()]) {
+            let meta = std::fs::metadata(&file).unwrap();
+            assert_eq!(meta.permissions().mode() & 0o777, LOG_MODE, "{}", file.display());
+        }
+        assert!(!rotated(&path, KEEP_ROTATED + 1).exists());
+
+        // The oldest entries rotated out; the rest come back oldest first.
+        let all: Vec<u64> = log.query(&Query::default()).await.iter().map(|e| e.ts_ms).collect();
+        assert!(all.len() < 20);
+        assert_eq!(all.last(), Some(&20));
+        assert!(all.windows(2).all(|w| w[0] < w[1]));
+
+        let query = Query { uid: Some(1000), since_ms: Some(15), ..Query::default() };
+        let user: Vec<u64> = log.query(&query).await.iter().map(|e| e.ts_ms).collect();
+        assert_eq!(user, vec![15, 17, 19]);
+
+        let query = Query { command: Some("set_tdp".into()), limit: Some(2), ..Query::default() };
+        let tdp: Vec<u64> = log.query(&query).await.iter().map(|e| e.ts_ms).collect();
+        assert_eq!(tdp, vec![18, 20]);
+    }
+}
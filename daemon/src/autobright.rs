@@ -0,0 +1,385 @@
+//! Ambient-light auto brightness. An IIO illuminance sensor is sampled,
+//! smoothed and mapped to a brightness percentage through a lux curve.
+//! Manual changes made while it is on are folded back into the curve once the
+//! user settles on a value, so it learns their preference.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::persist::SettingsFile;
+use crate::sysfs::read_parse;
+use crate::Daemon;
+
+pub const STATE_PATH: &str = "/var/lib/loki-master/auto-brightness.json";
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+/// Weight of a new sample in the moving average of log10(lux + 1).
+const SMOOTHING: f64 = 0.25;
+/// Smallest change, in percent, worth fading to.
+const HYSTERESIS: f64 = 3.0;
+const FADE: Duration = Duration::from_secs(1);
+/// A manual adjustment replaces curve points within this many decades of
+/// the current light level.
+const LEARN_RADIUS: f64 = 0.25;
+/// How long a manual brightness must stay put before it is learned, so a
+/// slider drag teaches the curve only the value it ends on.
+const LEARN_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    pub lux: f64,
+    pub percent: f64,
+}
+
+pub fn default_curve() -> Vec<CurvePoint> {
+    [(0.0, 10.0), (10.0, 25.0), (100.0, 45.0), (1000.0, 75.0), (10000.0, 100.0)]
+        .into_iter()
+        .map(|(lux, percent)| CurvePoint { lux, percent })
+        .collect()
+}
+
+fn log_lux(lux: f64) -> f64 {
+    (lux.max(0.0) + 1.0).log10()
+}
+
+/// Brightness for `lux`, interpolated linearly in log-lux between points.
+pub fn evaluate(curve: &[CurvePoint], lux: f64) -> f64 {
+    let x = log_lux(lux);
+    let (Some(first), Some(last)) = (curve.first(), curve.last()) else {
+        return 50.0;
+    };
+    if x <= log_lux(first.lux) {
+        return first.percent;
+    }
+    for pair in curve.windows(2) {
+        let (x0, x1) = (log_lux(pair[0].lux), log_lux(pair[1].lux));
+        if x <= x1 {
+            let t = if x1 > x0 { (x - x0) / (x1 - x0) } else { 1.0 };
+            return pair[0].percent + t * (pair[1].percent - pair[0].percent);
+        }
+    }
+    last.percent
+}
+
+/// Make `percent` the curve's answer at `lux`. Nearby points are replaced
+/// and the rest are clamped so brightness never falls as light rises.
+pub fn learn(curve: &mut Vec<CurvePoint>, lux: f64, percent: f64) {
+    let x = log_lux(lux);
+    curve.retain(|p| (log_lux(p.lux) - x).abs() >= LEARN_RADIUS);
+    for p in curve.iter_mut() {
+        if p.lux < lux {
+            p.percent = p.percent.min(percent);
+        } else {
+            p.percent = p.percent.max(percent);
+        }
+    }
+    let at = curve.partition_point(|p| p.lux < lux);
+    curve.insert(at, CurvePoint { lux, percent });
+}
+
+/// First IIO device with an illuminance channel.
+pub fn find_sensor(root: &Path) -> Option<PathBuf> {
+    let mut devices: Vec<PathBuf> = std::fs::read_dir(root.join("bus/iio/devices"))
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .collect();
+    devices.sort();
+    devices.into_iter().find(|d| read_lux(d).is_some())
+}
+
+/// Illuminance in lux from `in_illuminance_input`, or from `_raw` with the
+/// channel's `_offset` and `_scale`.
+pub fn read_lux(device: &Path) -> Option<f64> {
+    for prefix in ["in_illuminance", "in_illuminance0"] {
+        if let Some(lux) = read_parse::<f64>(device.join(format!("{prefix}_input"))) {
+            return Some(lux);
+        }
+        if let Some(raw) = read_parse::<f64>(device.join(format!("{prefix}_raw"))) {
+            let offset: f64 = read_parse(device.join(format!("{prefix}_offset"))).unwrap_or(0.0);
+            let scale: f64 = read_parse(device.join(format!("{prefix}_scale"))).unwrap_or(1.0);
+            return Some((raw + offset) * scale);
+        }
+    }
+    None
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Saved {
+    enabled: bool,
+    curve: Vec<CurvePoint>,
+}
+
+/// Reported by `get_auto_brightness`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Status {
+    pub enabled: bool,
+    /// IIO device name, e.g. `iio:device0`.
+    pub sensor: Option<String>,
+    /// Smoothed light level.
+    pub lux: Option<f64>,
+    pub curve: Vec<CurvePoint>,
+}
+
+struct Inner {
+    enabled: bool,
+    curve: Vec<CurvePoint>,
+    /// Moving average of log10(lux + 1).
+    smoothed: Option<f64>,
+    /// Last percentage applied or chosen by the user.
+    applied: Option<f64>,
+    /// Manual percentage not yet learned, and when it was set.
+    pending: Option<(f64, Instant)>,
+}
+
+impl Inner {
+    fn saved(&self) -> Saved {
+        Saved { enabled: self.enabled, curve: self.curve.clone() }
+    }
+}
+
+pub struct Controller {
+    inner: Mutex<Inner>,
+    file: SettingsFile,
+    /// Client fades in progress. Auto adjustments wait for them, since a new
+    /// fade would cancel the user's.
+    manual_fades: AtomicUsize,
+}
+
+/// Held while a client's brightness fade runs.
+pub struct ManualFade<'a>(&'a Controller);
+
+impl Drop for ManualFade<'_> {
+    fn drop(&mut self) {
+        self.0.manual_fades.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Controller {
+    /// Load the saved curve and switch from `path`, if there is one.
+    pub fn new(path: PathBuf) -> Controller {
+        let file = SettingsFile::new(path);
+        let saved = file.load::<Saved>().filter(|s| !s.curve.is_empty());
+        let (enabled, curve) = saved.map_or((false, default_curve()), |s| (s.enabled, s.curve));
+        Controller {
+            inner: Mutex::new(Inner {
+                enabled,
+                curve,
+                smoothed: None,
+                applied: None,
+                pending: None,
+            }),
+            file,
+            manual_fades: AtomicUsize::new(0),
+        }
+    }
+
+    /// Controller persisted at `LOKI_AUTO_BRIGHTNESS_STATE`.
+    pub fn from_env() -> Controller {
+        let path = std::env::var_os("LOKI_AUTO_BRIGHTNESS_STATE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(STATE_PATH));
+        Controller::new(path)
+    }
+
+    pub fn status(&self, root: &Path) -> Status {
+        let inner = self.inner.lock().unwrap();
+        Status {
+            enabled: inner.enabled,
+            sensor: find_sensor(root)
+                .and_then(|d| d.file_name().map(|n| n.to_string_lossy().into_owned())),
+            lux: inner.smoothed.map(|x| 10f64.powf(x) - 1.0),
+            curve: inner.curve.clone(),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.enabled = enabled;
+        // Apply the curve straight away rather than waiting for the light
+        // to change.
+        inner.applied = None;
+        inner.pending = None;
+        self.file.save(&inner.saved());
+    }
+
+    pub fn reset_curve(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.curve = default_curve();
+        inner.applied = None;
+        inner.pending = None;
+        self.file.save(&inner.saved());
+    }
+
+    /// Pause auto adjustments until the returned guard is dropped.
+    pub fn manual_fade(&self) -> ManualFade<'_> {
+        self.manual_fades.fetch_add(1, Ordering::SeqCst);
+        ManualFade(self)
+    }
+
+    /// A client set brightness by hand. While auto mode is on, the value is
+    /// learned once it has stood for [`LEARN_DELAY`].
+    pub fn manual_adjustment(&self, percent: f64) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.enabled || inner.smoothed.is_none() {
+            return;
+        }
+        inner.applied = Some(percent);
+        inner.pending = Some((percent, Instant::now()));
+    }
+
+    /// Teach the curve a manual adjustment that has settled by `now`.
+    fn settle(&self, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        let (Some((percent, at)), Some(x)) = (inner.pending, inner.smoothed) else {
+            return;
+        };
+        if now.saturating_duration_since(at) < LEARN_DELAY {
+            return;
+        }
+        inner.pending = None;
+        learn(&mut inner.curve, 10f64.powf(x) - 1.0, percent);
+        self.file.save(&inner.saved());
+    }
+
+    /// Feed a sensor reading. Returns the percentage to fade to when it
+    /// differs enough from the last one applied and no client fade is
+    /// running or waiting to be learned.
+    fn sample(&self, lux: f64) -> Option<f64> {
+        let mut inner = self.inner.lock().unwrap();
+        let x = log_lux(lux);
+        let smoothed = inner.smoothed.map_or(x, |s| s + SMOOTHING * (x - s));
+        inner.smoothed = Some(smoothed);
+        if !inner.enabled || inner.pending.is_some() || self.manual_fades.load(Ordering::SeqCst) > 0
+        {
+            return None;
+        }
+        let target = evaluate(&inner.curve, 10f64.powf(smoothed) - 1.0);
+        if inner.applied.is_some_and(|a| (a - target).abs() < HYSTERESIS) {
+            return None;
+        }
+        inner.applied = Some(target);
+        Some(target)
+    }
+}
+
+/// Sample the light sensor for the life of the daemon and fade the backlight
+/// when auto brightness is on.
+pub async fn run(daemon: Arc<Daemon>) {
+    let root = &daemon.root;
+    let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut sensor = None;
+    loop {
+        ticker.tick().await;
+        daemon.auto_brightness.settle(Instant::now());
+        if sensor.is_none() {
+            sensor = find_sensor(root);
+        }
+        let Some(lux) = sensor.as_deref().and_then(read_lux) else {
+            sensor = None;
+            continue;
+        };
+        if let Some(percent) = daemon.auto_brightness.sample(lux) {
+            let resp = daemon.fade_brightness(percent, FADE).await;
+            if !resp.success {
+                eprintln!("auto brightness: {}", resp.error.unwrap_or_default());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::TempRoot;
+
+    /// Add an IIO device with `files` to the fake tree under `iio`.
+    fn device(iio: &TempRoot, name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = iio.dir(Path::new("bus/iio/devices").join(name));
+        for (file, value) in files {
+            iio.write(dir.join(file), format!("{value}\n"));
+        }
+        dir
+    }
+
+    #[test]
+    fn finds_the_illuminance_sensor_and_scales_raw_readings() {
+        let iio = TempRoot::new("iio-scaled");
+        device(&iio, "iio:device0", &[("in_accel_x_raw", "12")]);
+        let sensor = device(
+            &iio,
+            "iio:device1",
+            &[
+                ("in_illuminance_raw", "200"),
+                ("in_illuminance_offset", "10"),
+                ("in_illuminance_scale", "0.5"),
+            ],
+        );
+        assert_eq!(find_sensor(&iio.root), Some(sensor.clone()));
+        assert_eq!(read_lux(&sensor), Some(105.0));
+
+        // A processed reading wins over the raw one.
+        iio.write(sensor.join("in_illuminance_input"), "42.5\n");
+        assert_eq!(read_lux(&sensor), Some(42.5));
+    }
+
+    #[test]
+    fn no_sensor_without_an_illuminance_channel() {
+        let iio = TempRoot::new("iio-none");
+        device(&iio, "iio:device0", &[("in_accel_x_raw", "12")]);
+        assert_eq!(find_sensor(&iio.root), None);
+    }
+
+    #[tokio::test]
+    async fn client_fades_pause_auto_adjustments() {
+        let iio = TempRoot::new("iio-pause");
+        let controller = Controller::new(iio.root.join("auto-brightness.json"));
+        controller.set_enabled(true);
+
+        let fade = controller.manual_fade();
+        assert_eq!(controller.sample(1000.0), None);
+        drop(fade);
+        // The light level kept being followed while paused.
+        assert_eq!(controller.sample(1000.0), Some(75.0));
+        assert_eq!(controller.sample(1000.0), None);
+    }
+
+    #[tokio::test]
+    async fn learns_only_the_settled_manual_value() {
+        let tmp = TempRoot::new("iio-learn");
+        let path = tmp.root.join("auto-brightness.json");
+        let controller = Controller::new(path.clone());
+        controller.set_enabled(true);
+        controller.sample(100.0);
+
+        let start = Instant::now();
+        for percent in [50.0, 60.0, 70.0, 80.0] {
+            controller.manual_adjustment(percent);
+        }
+        controller.settle(start);
+        // Nothing is learned mid-drag, and auto mode holds off meanwhile.
+        assert_eq!(evaluate(&controller.status(&tmp.root).curve, 100.0), 45.0);
+        assert_eq!(controller.sample(100.0), None);
+
+        controller.settle(start + LEARN_DELAY + Duration::from_millis(100));
+        let curve = controller.status(&tmp.root).curve;
+        assert_eq!(evaluate(&curve, 100.0), 80.0);
+        assert!(!curve.iter().any(|p| [50.0, 60.0, 70.0].contains(&p.percent)));
+
+        controller.file.flush().await;
+        let saved = Controller::new(path).status(&tmp.root).curve;
+        assert_eq!(saved.len(), curve.len());
+        assert!((evaluate(&saved, 100.0) - 80.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn learning_keeps_the_curve_rising() {
+        let mut curve = default_curve();
+        learn(&mut curve, 100.0, 80.0);
+        assert_eq!(evaluate(&curve, 100.0), 80.0);
+        assert!(curve.windows(2).all(|p| p[0].lux < p[1].lux && p[0].percent <= p[1].percent));
+    }
+}
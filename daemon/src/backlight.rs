@@ -0,0 +1,114 @@
+//! Backlight device selection. Laptops and handhelds often expose more than
+//! one interface to the same panel; the kernel's preference order is
+//! firmware, then platform, then raw.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::ops::OpError;
+use crate::sysfs::{class_entries, read_parse, read_trimmed};
+
+/// Names the backlight device to use, overriding the type preference.
+pub const DEVICE_ENV: &str = "LOKI_BACKLIGHT";
+
+fn type_rank(kind: Option<&str>) -> u8 {
+    match kind {
+        Some("firmware") => 0,
+        Some("platform") => 1,
+        Some("raw") => 2,
+        _ => 3,
+    }
+}
+
+/// The configured device if it exists, otherwise the best-ranked one by
+/// `type` (ties broken by name).
+pub fn device(root: &Path) -> Option<PathBuf> {
+    let devices = class_entries(root, "backlight");
+    if let Some(name) = std::env::var_os(DEVICE_ENV) {
+        match devices.iter().find(|d| d.file_name() == Some(name.as_os_str())) {
+            Some(dev) => return Some(dev.clone()),
+            None => eprintln!("backlight {:?} from {DEVICE_ENV} not found", name),
+        }
+    }
+    devices
+        .into_iter()
+        .min_by_key(|d| type_rank(read_trimmed(d.join("type")).as_deref()))
+}
+
+/// Exponent of the perceptual curve between slider position and raw level.
+pub const GAMMA: f64 = 2.2;
+/// Lowest level the percentage control goes to, as a percentage of
+/// `max_brightness`, so 0% dims the panel without turning it off.
+pub const MIN_PERCENT_ENV: &str = "LOKI_BACKLIGHT_MIN_PERCENT";
+const DEFAULT_MIN_PERCENT: f64 = 2.0;
+/// How long a percentage change fades when the request gives no duration.
+pub const DEFAULT_TRANSITION: Duration = Duration::from_millis(250);
+pub const MAX_TRANSITION: Duration = Duration::from_secs(5);
+
+/// Maps perceptual percentages (0-100) to raw levels between the floor and
+/// `max_brightness`.
+#[derive(Clone, Copy, Debug)]
+pub struct Curve {
+    pub floor: u32,
+    pub max: u32,
+}
+
+impl Curve {
+    pub fn new(max: u32) -> Curve {
+        let min_percent = std::env::var(MIN_PERCENT_ENV)
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(DEFAULT_MIN_PERCENT)
+            .clamp(0.0, 100.0);
+        let floor = ((max as f64 * min_percent / 100.0).round() as u32).clamp(1, max.max(1));
+        Curve { floor, max }
+    }
+
+    pub fn raw(&self, percent: f64) -> u32 {
+        let x = (percent / 100.0).clamp(0.0, 1.0);
+        let span = self.max.saturating_sub(self.floor) as f64;
+        self.floor + (span * x.powf(GAMMA)).round() as u32
+    }
+
+    pub fn percent(&self, raw: u32) -> f64 {
+        let span = self.max.saturating_sub(self.floor) as f64;
+        if span == 0.0 {
+            return 100.0;
+        }
+        let x = (raw.saturating_sub(self.floor) as f64 / span).min(1.0);
+        x.powf(1.0 / GAMMA) * 100.0
+    }
+}
+
+/// A fade from the current level to a target percentage.
+pub struct Transition {
+    pub path: String,
+    pub curve: Curve,
+    pub from: f64,
+    pub to: f64,
+}
+
+impl Transition {
+    /// Raw level `t` (0-1) of the way through, interpolated in perceptual
+    /// space so the fade looks even.
+    pub fn level(&self, t: f64) -> u32 {
+        self.curve.raw(self.from + (self.to - self.from) * t.clamp(0.0, 1.0))
+    }
+}
+
+pub fn transition(root: &Path, percent: f64) -> Result<Transition, OpError> {
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(OpError::invalid("brightness must be between 0 and 100%"));
+    }
+    let dev = device(root).ok_or_else(|| OpError::missing("backlight"))?;
+    let max: u32 = read_parse(dev.join("max_brightness"))
+        .ok_or_else(|| OpError::missing("backlight max_brightness"))?;
+    let curve = Curve::new(max);
+    let current: u32 = read_parse(dev.join("brightness")).unwrap_or(curve.max);
+    Ok(Transition {
+        path: dev.join("brightness").to_string_lossy().into_owned(),
+        curve,
+        from: curve.percent(current),
+        to: percent,
+    })
+}
@@ -0,0 +1,129 @@
+//! Command-line client for the loki-master daemon.
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SOCK_PATH: &str = "/run/loki-master.sock";
+
+/// Columns of a telemetry sample, in export order.
+const SAMPLE_COLUMNS: &[&str] = &[
+    "ts_ms",
+    "cpu_temp_c",
+    "fan_rpm",
+    "fan_pwm",
+    "package_power_w",
+    "tdp_limit_w",
+    "battery_percent",
+    "battery_power_w",
+    "cpu_mhz",
+    "gpu_mhz",
+];
+
+const USAGE: &str = "\
+usage: lokictl history export [--format csv|json] [--since MS] [--until MS]
+                              [--last DURATION] [--output FILE]
+
+DURATION is a number with an s, m or h suffix, e.g. 30m.";
+
+fn request(val: Value) -> Result<Value, String> {
+    let mut stream =
+        UnixStream::connect(SOCK_PATH).map_err(|e| format!("connect {SOCK_PATH}: {e}"))?;
+    stream
+        .write_all((val.to_string() + "\n").as_bytes())
+        .map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    let resp: Value = serde_json::from_str(&line).map_err(|e| format!("bad response: {e}"))?;
+    if resp["success"].as_bool() != Some(true) {
+        return Err(resp["error"].as_str().unwrap_or("request failed").to_string());
+    }
+    Ok(resp["data"].clone())
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (num, unit) = s.split_at(s.len().checked_sub(1)?);
+    let n: u64 = num.parse().ok()?;
+    match unit {
+        "s" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_secs(n * 60)),
+        "h" => Some(Duration::from_secs(n * 3600)),
+        _ => None,
+    }
+}
+
+fn csv_field(v: &Value) -> String {
+    match v {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn to_csv(samples: &[Value]) -> String {
+    let mut out = SAMPLE_COLUMNS.join(",") + "\n";
+    for s in samples {
+        let row: Vec<String> = SAMPLE_COLUMNS.iter().map(|c| csv_field(&s[*c])).collect();
+        out += &row.join(",");
+        out.push('\n');
+    }
+    out
+}
+
+fn history_export(args: &[String]) -> Result<(), String> {
+    let mut format = "csv".to_string();
+    let mut since_ms: Option<u64> = None;
+    let mut until_ms: Option<u64> = None;
+    let mut output: Option<String> = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().cloned().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--format" => format = value()?,
+            "--since" => since_ms = Some(value()?.parse().map_err(|_| "bad --since")?),
+            "--until" => until_ms = Some(value()?.parse().map_err(|_| "bad --until")?),
+            "--last" => {
+                let d = parse_duration(&value()?).ok_or("bad --last")?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|e| e.to_string())?;
+                since_ms = Some(now.saturating_sub(d).as_millis() as u64);
+            }
+            "--output" | "-o" => output = Some(value()?),
+            other => return Err(format!("unknown option {other}\n{USAGE}")),
+        }
+    }
+
+    let data = request(json!({"cmd": "telemetry", "since_ms": since_ms, "until_ms": until_ms}))?;
+    let samples = data.as_array().cloned().unwrap_or_default();
+    let text = match format.as_str() {
+        "csv" => to_csv(&samples),
+        "json" => serde_json::to_string_pretty(&samples).map_err(|e| e.to_string())? + "\n",
+        other => return Err(format!("unknown format {other}")),
+    };
+    match output {
+        Some(path) => std::fs::write(&path, text).map_err(|e| format!("write {path}: {e}")),
+        None => std::io::stdout()
+            .write_all(text.as_bytes())
+            .map_err(|e| e.to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["history", "export", ..] => history_export(&args[2..]),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{autobright, cores, cpufreq};
+use crate::state::{amdgpu_cards, FAN_HWMON, RGB_LED};
+use crate::sysfs::{class_entries, find_hwmon, read_trimmed};
+
+/// Features detected on this device, advertised in the `hello` response so
+/// clients can hide controls that would do nothing.
+#[derive(Clone, Debug, Serialize)]
+pub struct Capabilities {
+    pub backlight: bool,
+    pub fan_control: bool,
+    /// LED class devices with an RGB `multi_intensity` attribute.
+    pub rgb_zones: Vec<String>,
+    /// Program used to apply TDP limits, if one is installed.
+    pub tdp_backend: Option<String>,
+    pub charge_limit: bool,
+    /// cpufreq policies are present (governor, boost and limits).
+    pub cpufreq: bool,
+    /// CPUs other than CPU0 can be taken offline.
+    pub cpu_hotplug: bool,
+    /// SMT can be switched at runtime.
+    pub smt: bool,
+    /// amdgpu cards with power management controls.
+    pub gpus: Vec<String>,
+    /// An IIO ambient light sensor for auto brightness.
+    pub light_sensor: bool,
+}
+
+/// Search `PATH` for an executable called `name`.
+pub fn find_program(name: &str) -> Option<String> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|p| p.is_file())
+        .map(|p| p.to_string_lossy().into_owned())
+}
+
+pub fn detect(root: &Path) -> Capabilities {
+    let fan_control = find_hwmon(root, FAN_HWMON).is_some_and(|p| p.join("pwm1").exists());
+    let mut rgb_zones: Vec<String> = class_entries(root, "leds")
+        .into_iter()
+        .filter(|p| p.join("multi_intensity").exists())
+        .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .collect();
+    // Keep the zone the panel drives first.
+    rgb_zones.sort_by_key(|z| z != RGB_LED);
+    let charge_limit = class_entries(root, "power_supply").into_iter().any(|p| {
+        read_trimmed(p.join("type")).as_deref() == Some("Battery")
+            && p.join("charge_control_end_threshold").exists()
+    });
+    Capabilities {
+        backlight: !class_entries(root, "backlight").is_empty(),
+        fan_control,
+        rgb_zones,
+        tdp_backend: find_program("ryzenadj").map(|_| "ryzenadj".to_string()),
+        charge_limit,
+        cpufreq: !cpufreq::policies(root).is_empty(),
+        cpu_hotplug: cores::read(root).is_some_and(|c| c.present.len() > 1),
+        smt: matches!(
+            read_trimmed(root.join("devices/system/cpu/smt/control")).as_deref(),
+            Some("on" | "off")
+        ),
+        gpus: amdgpu_cards(root)
+            .iter()
+            .filter_map(|c| c.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .collect(),
+        light_sensor: autobright::find_sensor(root).is_some(),
+    }
+}
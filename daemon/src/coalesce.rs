@@ -0,0 +1,253 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use crate::protocol::Response;
+use crate::runner::{program_name, Op, Runner};
+
+/// How long a write waits for newer values before it is applied.
+pub const DEBOUNCE: Duration = Duration::from_millis(30);
+
+/// Programs whose invocations are coalesced, with the minimum spacing
+/// between two runs of the same target.
+const RATE_LIMITED_PROGRAMS: &[(&str, Duration)] = &[("ryzenadj", Duration::from_millis(250))];
+
+#[derive(Default)]
+struct Slot {
+    pending: Option<Op>,
+    waiters: Vec<oneshot::Sender<Response>>,
+    merged: u64,
+    scheduled: bool,
+    last_run: Option<Instant>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Stats {
+    /// Operations actually performed.
+    pub executed: u64,
+    /// Requests that were superseded by a newer value for the same target.
+    pub merged: u64,
+}
+
+/// Folds rapid writes to the same target into a single operation
+/// (last value wins) and spaces out expensive commands.
+pub struct Coalescer<R: Runner> {
+    runner: Arc<R>,
+    debounce: Duration,
+    slots: Mutex<HashMap<String, Slot>>,
+    executed: AtomicU64,
+    merged: AtomicU64,
+}
+
+/// The coalescing key and minimum run interval for `op`, or `None` when the
+/// operation must run exactly as often as it is requested.
+fn coalesce_key(op: &Op) -> Option<(String, Duration)> {
+    match op {
+        // A sequence replaces a pending write or sequence as a whole.
+        Op::Write { path, .. } | Op::WriteSequence { path, .. } => {
+            Some((format!("write:{path}"), Duration::ZERO))
+        }
+        Op::Run { program, args, .. } => {
+            let name = program_name(program);
+            let (_, interval) = RATE_LIMITED_PROGRAMS.iter().find(|(p, _)| *p == name)?;
+            // Options select the target, their values are what gets merged,
+            // whether given as `--flag value` or `--flag=value`.
+            let flags: Vec<&str> = args
+                .iter()
+                .filter(|a| a.starts_with('-'))
+                .map(|a| a.split('=').next().unwrap_or(a))
+                .collect();
+            Some((format!("run:{name}:{}", flags.join(" ")), *interval))
+        }
+    }
+}
+
+impl<R: Runner> Coalescer<R> {
+    pub fn new(runner: Arc<R>, debounce: Duration) -> Coalescer<R> {
+        Coalescer {
+            runner,
+            debounce,
+            slots: Mutex::new(HashMap::new()),
+            executed: AtomicU64::new(0),
+            merged: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            executed: self.executed.load(Ordering::Relaxed),
+            merged: self.merged.load(Ordering::Relaxed),
+        }
+    }
+
+    pub async fn submit(self: &Arc<Self>, op: Op) -> Response {
+        let Some((key, interval)) = coalesce_key(&op) else {
+            self.executed.fetch_add(1, Ordering::Relaxed);
+            return self.runner.execute(&op).await;
+        };
+        let (tx, rx) = oneshot::channel();
+        let spawn = {
+            let mut slots = self.slots.lock().unwrap();
+            let slot = slots.entry(key.clone()).or_default();
+            if slot.pending.replace(op).is_some() {
+                slot.merged += 1;
+                self.merged.fetch_add(1, Ordering::Relaxed);
+            }
+            slot.waiters.push(tx);
+            !std::mem::replace(&mut slot.scheduled, true)
+        };
+        if spawn {
+            tokio::spawn(self.clone().flush(key, interval));
+        }
+        rx.await
+            .unwrap_or_else(|_| Response::err("coalesced operation was dropped"))
+    }
+
+    async fn flush(self: Arc<Self>, key: String, interval: Duration) {
+        loop {
+            let wait = {
+                let slots = self.slots.lock().unwrap();
+                let ready_at = slots[&key].last_run.map(|t| t + interval);
+                let until_ready =
+                    ready_at.map_or(Duration::ZERO, |t| t.saturating_duration_since(Instant::now()));
+                self.debounce.max(until_ready)
+            };
+            tokio::time::sleep(wait).await;
+
+            let (op, waiters, merged) = {
+                let mut slots = self.slots.lock().unwrap();
+                let slot = slots.get_mut(&key).unwrap();
+                let Some(op) = slot.pending.take() else {
+                    slot.scheduled = false;
+                    return;
+                };
+                (op, std::mem::take(&mut slot.waiters), std::mem::take(&mut slot.merged))
+            };
+
+            let mut resp = self.runner.execute(&op).await;
+            self.executed.fetch_add(1, Ordering::Relaxed);
+            if merged > 0 {
+                resp.merged = Some(merged);
+            }
+            for w in waiters {
+                let _ = w.send(resp.clone());
+            }
+
+            let mut slots = self.slots.lock().unwrap();
+            let slot = slots.get_mut(&key).unwrap();
+            slot.last_run = Some(Instant::now());
+            if slot.pending.is_none() {
+                slot.scheduled = false;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::BoxFuture;
+
+    /// Records what it was asked to do, and when, instead of touching the
+    /// system.
+    #[derive(Default)]
+    struct RecordingRunner {
+        ops: Mutex<Vec<(Op, Instant)>>,
+    }
+
+    impl Runner for RecordingRunner {
+        fn execute<'a>(&'a self, op: &'a Op) -> BoxFuture<'a, Response> {
+            self.ops.lock().unwrap().push((op.clone(), Instant::now()));
+            Box::pin(async { Response::ok() })
+        }
+    }
+
+    fn coalescer() -> (Arc<RecordingRunner>, Arc<Coalescer<RecordingRunner>>) {
+        let runner = Arc::new(RecordingRunner::default());
+        (runner.clone(), Arc::new(Coalescer::new(runner, DEBOUNCE)))
+    }
+
+    fn write(path: &str, value: &str) -> Op {
+        Op::Write { path: path.into(), value: value.into() }
+    }
+
+    fn ryzenadj(args: &[&str]) -> Op {
+        Op::Run {
+            program: "/usr/bin/ryzenadj".into(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            timeout: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn rapid_writes_to_one_path_keep_the_last_value() {
+        let (runner, coalescer) = coalescer();
+        let (a, b, c) = tokio::join!(
+            coalescer.submit(write("/sys/x", "1")),
+            coalescer.submit(write("/sys/x", "2")),
+            coalescer.submit(write("/sys/x", "3")),
+        );
+        assert!(a.success && b.success && c.success);
+        assert_eq!(c.merged, Some(2));
+
+        let ops = runner.ops.lock().unwrap();
+        assert_eq!(ops.len(), 1);
+        assert!(matches!(&ops[0].0, Op::Write { value, .. } if value == "3"));
+        let stats = coalescer.stats();
+        assert_eq!((stats.executed, stats.merged), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn writes_to_different_paths_all_run() {
+        let (runner, coalescer) = coalescer();
+        tokio::join!(
+            coalescer.submit(write("/sys/x", "1")),
+            coalescer.submit(write("/sys/y", "2")),
+        );
+        assert_eq!(runner.ops.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_newer_sequence_replaces_a_pending_one_whole() {
+        let (runner, coalescer) = coalescer();
+        let sequence = |min: u32, max: u32| Op::WriteSequence {
+            path: "/sys/od".into(),
+            values: vec![format!("s 0 {min}"), format!("s 1 {max}"), "c".into()],
+        };
+        tokio::join!(coalescer.submit(sequence(500, 1000)), coalescer.submit(sequence(600, 1200)));
+
+        let ops = runner.ops.lock().unwrap();
+        assert_eq!(ops.len(), 1);
+        assert!(matches!(&ops[0].0, Op::WriteSequence { values, .. }
+            if *values == ["s 0 600", "s 1 1200", "c"]));
+    }
+
+    #[tokio::test]
+    async fn ryzenadj_runs_are_spaced_out() {
+        let (runner, coalescer) = coalescer();
+        coalescer.submit(ryzenadj(&["--stapm-limit=15000"])).await;
+        coalescer.submit(ryzenadj(&["--stapm-limit=20000"])).await;
+
+        let ops = runner.ops.lock().unwrap();
+        assert_eq!(ops.len(), 2);
+        assert!(ops[1].1 - ops[0].1 >= Duration::from_millis(250));
+    }
+
+    #[test]
+    fn ryzenadj_values_do_not_change_the_key() {
+        let key = |args: &[&str]| coalesce_key(&ryzenadj(args)).map(|(k, _)| k);
+        assert_eq!(key(&["--stapm-limit=15000"]), key(&["--stapm-limit=20000"]));
+        assert_eq!(key(&["--stapm-limit=15000"]), key(&["--stapm-limit", "20000"]));
+        assert_ne!(key(&["--stapm-limit=15000"]), key(&["--fast-limit=15000"]));
+    }
+
+    #[test]
+    fn other_programs_are_not_coalesced() {
+        let op = Op::Run { program: "rfkill".into(), args: vec![], timeout: None };
+        assert!(coalesce_key(&op).is_none());
+    }
+}
@@ -0,0 +1,236 @@
+//! SMT and CPU hotplug. CPU0 cannot be taken offline and is never touched.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::ops::OpError;
+use crate::runner::Op;
+use crate::sysfs::read_trimmed;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CpuCores {
+    /// Contents of `smt/control`: `on`, `off`, `forceoff` or `notsupported`.
+    pub smt: Option<String>,
+    /// Logical CPUs present in the system.
+    pub present: Vec<u32>,
+    pub online: Vec<u32>,
+    /// CPUs that can be brought online: all present ones, minus the sibling
+    /// threads that SMT being off keeps offline.
+    pub available: Vec<u32>,
+}
+
+fn cpu_dir(root: &Path) -> PathBuf {
+    root.join("devices/system/cpu")
+}
+
+/// Parse a kernel CPU list such as `0-3,6,8-9`.
+pub fn parse_cpu_list(text: &str) -> Vec<u32> {
+    let mut cpus = Vec::new();
+    for part in text.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((a, b)) => {
+                if let (Ok(a), Ok(b)) = (a.parse::<u32>(), b.parse::<u32>()) {
+                    cpus.extend(a..=b);
+                }
+            }
+            None => cpus.extend(part.parse::<u32>().ok()),
+        }
+    }
+    cpus
+}
+
+/// Whether offline `cpu` is a second thread of its core, which the kernel
+/// refuses to online while SMT is off. Offline CPUs usually have no
+/// `topology` directory; without one the CPU is assumed to be a sibling.
+fn is_sibling_thread(dir: &Path, cpu: u32) -> bool {
+    match read_trimmed(dir.join(format!("cpu{cpu}/topology/thread_siblings_list"))) {
+        Some(list) => parse_cpu_list(&list).into_iter().min().is_some_and(|first| first != cpu),
+        None => true,
+    }
+}
+
+/// Present and online CPUs, or `None` without CPU hotplug support.
+pub fn read(root: &Path) -> Option<CpuCores> {
+    let dir = cpu_dir(root);
+    let present = parse_cpu_list(&read_trimmed(dir.join("present"))?);
+    let smt = read_trimmed(dir.join("smt/control"));
+    let online = read_trimmed(dir.join("online"))
+        .map(|s| parse_cpu_list(&s))
+        .unwrap_or_else(|| present.clone());
+    let smt_off = matches!(smt.as_deref(), Some("off" | "forceoff"));
+    let available = present
+        .iter()
+        .copied()
+        .filter(|&c| !smt_off || c == 0 || online.contains(&c) || !is_sibling_thread(&dir, c))
+        .collect();
+    Some(CpuCores { smt, present, online, available })
+}
+
+pub fn set_smt(root: &Path, enabled: bool) -> Result<Vec<Op>, OpError> {
+    let control = cpu_dir(root).join("smt/control");
+    match read_trimmed(&control).as_deref() {
+        None | Some("notsupported") | Some("notimplemented") => {
+            return Err(OpError::missing("SMT control"))
+        }
+        Some("forceoff") => {
+            return Err(OpError::invalid("SMT is disabled on the kernel command line"))
+        }
+        Some(_) => {}
+    }
+    let value = if enabled { "on" } else { "off" };
+    Ok(vec![Op::Write { path: control.to_string_lossy().into_owned(), value: value.into() }])
+}
+
+fn online_op(root: &Path, cpu: u32, online: bool) -> Op {
+    let path = cpu_dir(root).join(format!("cpu{cpu}/online"));
+    Op::Write { path: path.to_string_lossy().into_owned(), value: u8::from(online).to_string() }
+}
+
+pub fn set_cpu_online(root: &Path, cpu: u32, online: bool) -> Result<Vec<Op>, OpError> {
+    if cpu == 0 {
+        return Err(OpError::invalid("CPU0 cannot be taken offline"));
+    }
+    let cores = read(root).ok_or_else(|| OpError::missing("CPU hotplug"))?;
+    if !cores.present.contains(&cpu) {
+        return Err(OpError::invalid(format!("CPU{cpu} is not present")));
+    }
+    if online && !cores.available.contains(&cpu) {
+        return Err(OpError::invalid(format!("CPU{cpu} is an SMT sibling and SMT is off")));
+    }
+    Ok(vec![online_op(root, cpu, online)])
+}
+
+/// Keep the `count` lowest-numbered available CPUs online and offline the
+/// rest. CPUs are onlined before others are offlined so the count never dips
+/// below what was asked for.
+pub fn set_online_count(root: &Path, count: u32) -> Result<Vec<Op>, OpError> {
+    let cores = read(root).ok_or_else(|| OpError::missing("CPU hotplug"))?;
+    let total = cores.available.len() as u32;
+    if count == 0 || count > total {
+        return Err(OpError::invalid(format!("CPU count must be between 1 and {total}")));
+    }
+    let mut available = cores.available.clone();
+    available.sort_unstable();
+    let (keep, drop) = available.split_at(count as usize);
+    let mut ops: Vec<Op> = keep
+        .iter()
+        .filter(|&&c| c != 0 && !cores.online.contains(&c))
+        .map(|&c| online_op(root, c, true))
+        .collect();
+    ops.extend(
+        drop.iter()
+            .filter(|&&c| c != 0 && cores.online.contains(&c))
+            .map(|&c| online_op(root, c, false)),
+    );
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::TempRoot;
+
+    /// A fake `devices/system/cpu` tree with four CPUs, two cores of two
+    /// threads each (0/2 and 1/3), under a fresh temporary root.
+    fn fake_cpus(name: &str, smt: &str, online: &[u32]) -> TempRoot {
+        let cpus = TempRoot::new(&format!("cores-{name}"));
+        let dir = cpu_dir(&cpus.root);
+        cpus.write(dir.join("present"), "0-3\n");
+        cpus.write(dir.join("smt/control"), format!("{smt}\n"));
+        let list: Vec<String> = online.iter().map(u32::to_string).collect();
+        cpus.write(dir.join("online"), list.join(","));
+        for cpu in 0..4 {
+            let cpu_dir = dir.join(format!("cpu{cpu}"));
+            cpus.write(cpu_dir.join("online"), if online.contains(&cpu) { "1" } else { "0" });
+            // Like the kernel, only online CPUs have a topology.
+            if online.contains(&cpu) {
+                let siblings = if cpu % 2 == 0 { "0,2" } else { "1,3" };
+                cpus.write(cpu_dir.join("topology/thread_siblings_list"), siblings);
+            }
+        }
+        cpus
+    }
+
+    fn online_path(root: &Path, cpu: u32) -> String {
+        cpu_dir(root).join(format!("cpu{cpu}/online")).to_string_lossy().into_owned()
+    }
+
+    fn writes(ops: &[Op]) -> Vec<(String, String)> {
+        ops.iter()
+            .map(|op| match op {
+                Op::Write { path, value } => (path.clone(), value.clone()),
+                _ => panic!("unexpected op: {op:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_cpu_lists() {
+        assert_eq!(parse_cpu_list("0-3,6,8-9\n"), vec![0, 1, 2, 3, 6, 8, 9]);
+        assert_eq!(parse_cpu_list(""), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn reads_present_and_online_cpus() {
+        let cpus = fake_cpus("read", "on", &[0, 1, 2]);
+        let cores = read(&cpus.root).unwrap();
+        assert_eq!(cores.smt.as_deref(), Some("on"));
+        assert_eq!(cores.present, vec![0, 1, 2, 3]);
+        assert_eq!(cores.online, vec![0, 1, 2]);
+        assert_eq!(cores.available, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn onlines_before_offlining() {
+        let cpus = fake_cpus("count", "on", &[0, 2, 3]);
+        let ops = set_online_count(&cpus.root, 2).unwrap();
+        assert_eq!(
+            writes(&ops),
+            vec![
+                (online_path(&cpus.root, 1), "1".into()),
+                (online_path(&cpus.root, 2), "0".into()),
+                (online_path(&cpus.root, 3), "0".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn never_touches_cpu0() {
+        let cpus = fake_cpus("cpu0", "on", &[0, 1, 2, 3]);
+        assert!(writes(&set_online_count(&cpus.root, 1).unwrap())
+            .iter()
+            .all(|(path, _)| *path != online_path(&cpus.root, 0)));
+        assert!(set_cpu_online(&cpus.root, 0, false).is_err());
+    }
+
+    #[test]
+    fn skips_sibling_threads_while_smt_is_off() {
+        let cpus = fake_cpus("smt-off", "off", &[0, 1]);
+        let cores = read(&cpus.root).unwrap();
+        assert_eq!(cores.available, vec![0, 1]);
+        assert!(set_online_count(&cpus.root, 2).unwrap().is_empty());
+        assert!(set_online_count(&cpus.root, 3).is_err());
+        assert!(set_cpu_online(&cpus.root, 2, true).is_err());
+        assert_eq!(
+            writes(&set_online_count(&cpus.root, 1).unwrap()),
+            vec![(online_path(&cpus.root, 1), "0".into())]
+        );
+    }
+
+    #[test]
+    fn keeps_an_offlined_first_thread_available() {
+        // CPU1 was taken offline by hand, but its topology is still known.
+        let cpus = fake_cpus("primary", "off", &[0, 1]);
+        cpus.write(cpu_dir(&cpus.root).join("online"), "0");
+        assert_eq!(read(&cpus.root).unwrap().available, vec![0, 1]);
+    }
+
+    #[test]
+    fn smt_control_follows_the_kernel() {
+        let cpus = fake_cpus("smt", "on", &[0, 1, 2, 3]);
+        assert_eq!(writes(&set_smt(&cpus.root, false).unwrap())[0].1, "off");
+
+        let forced = fake_cpus("forceoff", "forceoff", &[0, 1]);
+        assert!(set_smt(&forced.root, true).is_err());
+    }
+}
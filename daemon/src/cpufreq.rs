@@ -0,0 +1,305 @@
+//! cpufreq policy control: governor, amd-pstate EPP, boost and frequency
+//! limits, applied identically to every policy.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::ops::OpError;
+use crate::runner::Op;
+use crate::sysfs::{read_parse, read_trimmed};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CpuFreq {
+    pub governor: Option<String>,
+    pub available_governors: Vec<String>,
+    /// `energy_performance_preference`, only with amd-pstate-epp and similar.
+    pub epp: Option<String>,
+    pub available_epp: Vec<String>,
+    pub boost: Option<bool>,
+    pub min_khz: Option<u32>,
+    pub max_khz: Option<u32>,
+    /// Hardware limits from `cpuinfo_{min,max}_freq`.
+    pub hw_min_khz: Option<u32>,
+    pub hw_max_khz: Option<u32>,
+}
+
+fn cpufreq_dir(root: &Path) -> PathBuf {
+    root.join("devices/system/cpu/cpufreq")
+}
+
+/// All `policyN` directories, sorted by N.
+pub fn policies(root: &Path) -> Vec<PathBuf> {
+    let mut policies: Vec<(u32, PathBuf)> = std::fs::read_dir(cpufreq_dir(root))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| {
+            let n = e.file_name().to_str()?.strip_prefix("policy")?.parse().ok()?;
+            Some((n, e.path()))
+        })
+        .collect();
+    policies.sort();
+    policies.into_iter().map(|(_, p)| p).collect()
+}
+
+fn words(path: PathBuf) -> Vec<String> {
+    read_trimmed(path)
+        .map(|s| s.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Global boost switch, or the per-policy one on newer amd-pstate kernels.
+fn boost_files(root: &Path) -> Vec<PathBuf> {
+    let global = cpufreq_dir(root).join("boost");
+    if global.exists() {
+        return vec![global];
+    }
+    policies(root)
+        .into_iter()
+        .map(|p| p.join("boost"))
+        .filter(|p| p.exists())
+        .collect()
+}
+
+/// Current settings, read from the first policy.
+pub fn read(root: &Path) -> Option<CpuFreq> {
+    let policy = policies(root).into_iter().next()?;
+    Some(CpuFreq {
+        governor: read_trimmed(policy.join("scaling_governor")),
+        available_governors: words(policy.join("scaling_available_governors")),
+        epp: read_trimmed(policy.join("energy_performance_preference")),
+        available_epp: words(policy.join("energy_performance_available_preferences")),
+        boost: boost_files(root)
+            .first()
+            .and_then(read_parse::<u8>)
+            .map(|b| b != 0),
+        min_khz: read_parse(policy.join("scaling_min_freq")),
+        max_khz: read_parse(policy.join("scaling_max_freq")),
+        hw_min_khz: read_parse(policy.join("cpuinfo_min_freq")),
+        hw_max_khz: read_parse(policy.join("cpuinfo_max_freq")),
+    })
+}
+
+fn write(path: PathBuf, value: impl ToString) -> Op {
+    Op::Write { path: path.to_string_lossy().into_owned(), value: value.to_string() }
+}
+
+fn require_policies(root: &Path) -> Result<Vec<PathBuf>, OpError> {
+    let policies = policies(root);
+    if policies.is_empty() {
+        return Err(OpError::missing("cpufreq policies"));
+    }
+    Ok(policies)
+}
+
+pub fn set_governor(root: &Path, governor: &str) -> Result<Vec<Op>, OpError> {
+    let policies = require_policies(root)?;
+    let mut ops = Vec::new();
+    for policy in policies {
+        let available = words(policy.join("scaling_available_governors"));
+        if !available.iter().any(|g| g == governor) {
+            return Err(OpError::invalid(format!(
+                "governor {governor:?} not available (have: {})",
+                available.join(", ")
+            )));
+        }
+        ops.push(write(policy.join("scaling_governor"), governor));
+    }
+    Ok(ops)
+}
+
+pub fn set_epp(root: &Path, preference: &str) -> Result<Vec<Op>, OpError> {
+    let policies = require_policies(root)?;
+    let mut ops = Vec::new();
+    for policy in policies {
+        let available = words(policy.join("energy_performance_available_preferences"));
+        if available.is_empty() {
+            return Err(OpError::missing("energy performance preference control"));
+        }
+        if !available.iter().any(|p| p == preference) {
+            return Err(OpError::invalid(format!(
+                "EPP {preference:?} not available (have: {})",
+                available.join(", ")
+            )));
+        }
+        ops.push(write(policy.join("energy_performance_preference"), preference));
+    }
+    Ok(ops)
+}
+
+pub fn set_boost(root: &Path, enabled: bool) -> Result<Vec<Op>, OpError> {
+    let files = boost_files(root);
+    if files.is_empty() {
+        return Err(OpError::missing("CPU boost control"));
+    }
+    Ok(files.into_iter().map(|f| write(f, u8::from(enabled))).collect())
+}
+
+/// Set scaling limits on every policy. Each value must lie inside the
+/// policy's hardware range, and the writes are ordered so the kernel never
+/// sees min > max.
+pub fn set_freq_limits(root: &Path, min_khz: u32, max_khz: u32) -> Result<Vec<Op>, OpError> {
+    if min_khz > max_khz {
+        return Err(OpError::invalid("minimum frequency is above maximum"));
+    }
+    let policies = require_policies(root)?;
+    let mut ops = Vec::new();
+    for policy in policies {
+        let hw_min: u32 = read_parse(policy.join("cpuinfo_min_freq")).unwrap_or(0);
+        let hw_max: u32 = read_parse(policy.join("cpuinfo_max_freq")).unwrap_or(u32::MAX);
+        if min_khz < hw_min || max_khz > hw_max {
+            return Err(OpError::invalid(format!(
+                "frequency range must be within {hw_min}-{hw_max} kHz"
+            )));
+        }
+        let cur_max: u32 = read_parse(policy.join("scaling_max_freq")).unwrap_or(hw_max);
+        let min_op = write(policy.join("scaling_min_freq"), min_khz);
+        let max_op = write(policy.join("scaling_max_freq"), max_khz);
+        if min_khz > cur_max {
+            ops.extend([max_op, min_op]);
+        } else {
+            ops.extend([min_op, max_op]);
+        }
+    }
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorKind;
+    use crate::testfs::TempRoot;
+
+    /// Policies 0, 2 and 10 under amd-pstate-epp, limited to 1.6-3.0 GHz of
+    /// a 400 MHz-4.7 GHz range.
+    fn fake_policies(name: &str) -> TempRoot {
+        let cpus = TempRoot::new(&format!("cpufreq-{name}"));
+        for n in [0, 2, 10] {
+            let policy = cpufreq_dir(&cpus.root).join(format!("policy{n}"));
+            for (file, value) in [
+                ("scaling_governor", "powersave"),
+                ("scaling_available_governors", "performance powersave"),
+                ("energy_performance_preference", "balance_performance"),
+                (
+                    "energy_performance_available_preferences",
+                    "default performance balance_performance balance_power power",
+                ),
+                ("scaling_min_freq", "1600000"),
+                ("scaling_max_freq", "3000000"),
+                ("cpuinfo_min_freq", "400000"),
+                ("cpuinfo_max_freq", "4700000"),
+                ("boost", "1"),
+            ] {
+                cpus.write(policy.join(file), format!("{value}\n"));
+            }
+        }
+        cpus
+    }
+
+    fn writes(ops: &[Op]) -> Vec<(String, String)> {
+        ops.iter()
+            .map(|op| match op {
+                Op::Write { path, value } => {
+                    let policy = path.rsplit('/').nth(1).unwrap_or_default();
+                    let file = path.rsplit('/').next().unwrap_or_default();
+                    (format!("{policy}/{file}"), value.clone())
+                }
+                _ => panic!("unexpected op: {op:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_the_first_policy_in_numeric_order() {
+        let cpus = fake_policies("read");
+        let names: Vec<_> = policies(&cpus.root)
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["policy0", "policy2", "policy10"]);
+
+        let freq = read(&cpus.root).unwrap();
+        assert_eq!(freq.governor.as_deref(), Some("powersave"));
+        assert_eq!(freq.available_governors, ["performance", "powersave"]);
+        assert_eq!(freq.available_epp.len(), 5);
+        assert_eq!(freq.boost, Some(true));
+        assert_eq!((freq.min_khz, freq.max_khz), (Some(1600000), Some(3000000)));
+        assert_eq!((freq.hw_min_khz, freq.hw_max_khz), (Some(400000), Some(4700000)));
+    }
+
+    #[test]
+    fn governors_and_preferences_must_be_available() {
+        let cpus = fake_policies("governor");
+        assert_eq!(
+            writes(&set_governor(&cpus.root, "performance").unwrap()),
+            [
+                ("policy0/scaling_governor".into(), "performance".into()),
+                ("policy2/scaling_governor".into(), "performance".into()),
+                ("policy10/scaling_governor".into(), "performance".into()),
+            ]
+        );
+        let err = set_governor(&cpus.root, "schedutil").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidArgument);
+        assert!(err.message.contains("performance, powersave"), "{}", err.message);
+
+        assert_eq!(set_epp(&cpus.root, "power").unwrap().len(), 3);
+        let err = set_epp(&cpus.root, "turbo").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidArgument);
+
+        // acpi-cpufreq has no EPP at all.
+        let plain = TempRoot::new("cpufreq-no-epp");
+        plain.write(
+            cpufreq_dir(&plain.root).join("policy0/scaling_available_governors"),
+            "schedutil\n",
+        );
+        assert_eq!(set_epp(&plain.root, "power").unwrap_err().kind, ErrorKind::NotFound);
+        assert!(set_boost(&plain.root, true).is_err());
+    }
+
+    #[test]
+    fn boost_prefers_the_global_switch() {
+        let cpus = fake_policies("boost");
+        assert_eq!(set_boost(&cpus.root, false).unwrap().len(), 3);
+        cpus.write(cpufreq_dir(&cpus.root).join("boost"), "1\n");
+        assert_eq!(
+            writes(&set_boost(&cpus.root, false).unwrap()),
+            [("cpufreq/boost".into(), "0".into())]
+        );
+    }
+
+    #[test]
+    fn frequency_limits_are_checked_and_ordered() {
+        let cpus = fake_policies("limits");
+        let err = set_freq_limits(&cpus.root, 3000000, 2000000).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidArgument);
+        let err = set_freq_limits(&cpus.root, 200000, 2000000).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidArgument);
+        assert!(set_freq_limits(&cpus.root, 1000000, 5000000).is_err());
+
+        // Lowering both ends keeps min below the current max throughout.
+        let ops = writes(&set_freq_limits(&cpus.root, 800000, 2000000).unwrap());
+        assert_eq!(
+            ops[..2],
+            [
+                ("policy0/scaling_min_freq".into(), "800000".into()),
+                ("policy0/scaling_max_freq".into(), "2000000".into()),
+            ]
+        );
+        // Raising min past the current max of 3 GHz raises max first.
+        let ops = writes(&set_freq_limits(&cpus.root, 3500000, 4000000).unwrap());
+        assert_eq!(ops.len(), 6);
+        assert_eq!(
+            ops[..2],
+            [
+                ("policy0/scaling_max_freq".into(), "4000000".into()),
+                ("policy0/scaling_min_freq".into(), "3500000".into()),
+            ]
+        );
+
+        let empty = TempRoot::new("cpufreq-none");
+        assert_eq!(
+            set_freq_limits(&empty.root, 800000, 2000000).unwrap_err().kind,
+            ErrorKind::NotFound
+        );
+    }
+}
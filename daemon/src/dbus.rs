@@ -0,0 +1,629 @@
+//! System bus front end. Exposes the same typed operations as the socket,
+//! plus state as properties that emit `PropertiesChanged` when the hardware
+//! changes. Methods go through [`Daemon::handle`], so they are validated and
+//! audited like socket requests.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use zbus::message::Header;
+use zbus::zvariant::OwnedValue;
+use zbus::{fdo, interface, Connection};
+
+use crate::audit::Peer;
+use crate::effects::{self, Kind};
+use crate::protocol::{ErrorKind, Request, Response};
+use crate::state::{self, State};
+use crate::Daemon;
+
+pub const BUS_NAME: &str = "org.loki.MasterControl";
+pub const OBJECT_PATH: &str = "/org/loki/MasterControl";
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct Service {
+    daemon: Arc<Daemon>,
+    state: Mutex<State>,
+}
+
+async fn peer_of(conn: &Connection, hdr: &Header<'_>) -> Peer {
+    let Some(sender) = hdr.sender() else {
+        return Peer::default();
+    };
+    let creds = match fdo::DBusProxy::new(conn).await {
+        Ok(proxy) => proxy.get_connection_credentials(sender.clone().into()).await.ok(),
+        Err(_) => None,
+    };
+    let Some(creds) = creds else {
+        return Peer::default();
+    };
+    let pid = creds.process_id().map(|p| p as i32);
+    Peer {
+        uid: creds.unix_user_id(),
+        pid,
+        exe: pid.and_then(|p| {
+            std::fs::read_link(format!("/proc/{p}/exe"))
+                .ok()
+                .map(|p| p.to_string_lossy().into_owned())
+        }),
+    }
+}
+
+/// The response's data, or its error as the closest D-Bus error.
+fn to_fdo(resp: Response) -> fdo::Result<Option<serde_json::Value>> {
+    if resp.success {
+        return Ok(resp.data);
+    }
+    let msg = resp.error.unwrap_or_default();
+    Err(match resp.error_kind {
+        Some(ErrorKind::InvalidArgument) => fdo::Error::InvalidArgs(msg),
+        Some(ErrorKind::NotFound) => fdo::Error::FileNotFound(msg),
+        Some(ErrorKind::PermissionDenied) => fdo::Error::AccessDenied(msg),
+        Some(ErrorKind::Timeout) => fdo::Error::TimedOut(msg),
+        _ => fdo::Error::Failed(msg),
+    })
+}
+
+/// D-Bus has no optional arguments; an empty card name picks the first
+/// amdgpu card, like an omitted `card` on the socket.
+fn optional_card(card: String) -> Option<String> {
+    Some(card).filter(|c| !c.is_empty())
+}
+
+/// Value of `key` in an `a{sv}` argument, if given.
+fn option<T: TryFrom<OwnedValue>>(
+    options: &HashMap<String, OwnedValue>,
+    key: &str,
+) -> fdo::Result<Option<T>> {
+    let Some(value) = options.get(key) else {
+        return Ok(None);
+    };
+    value
+        .try_clone()
+        .ok()
+        .and_then(|v| T::try_from(v).ok())
+        .map(Some)
+        .ok_or_else(|| fdo::Error::InvalidArgs(format!("{key} has the wrong type")))
+}
+
+/// `SetRgbEffect`'s settings; keys that are left out keep their value.
+fn effect_update(options: HashMap<String, OwnedValue>) -> fdo::Result<effects::Update> {
+    if let Some(key) = options
+        .keys()
+        .find(|k| !["effect", "speed", "colors", "brightness", "fps"].contains(&k.as_str()))
+    {
+        return Err(fdo::Error::InvalidArgs(format!("unknown setting {key:?}")));
+    }
+    let effect = option::<String>(&options, "effect")?
+        .map(|name| {
+            serde_json::from_value::<Kind>(serde_json::Value::String(name.clone()))
+                .map_err(|_| fdo::Error::InvalidArgs(format!("unknown effect {name:?}")))
+        })
+        .transpose()?;
+    let colors = option::<Vec<(u8, u8, u8)>>(&options, "colors")?
+        .map(|colors| colors.into_iter().map(|(r, g, b)| [r, g, b]).collect());
+    Ok(effects::Update {
+        effect,
+        speed: option(&options, "speed")?,
+        colors,
+        brightness: option(&options, "brightness")?,
+        fps: option(&options, "fps")?,
+    })
+}
+
+impl Service {
+    async fn call(&self, conn: &Connection, hdr: &Header<'_>, req: Request) -> fdo::Result<()> {
+        self.call_json(conn, hdr, req).await.map(|_| ())
+    }
+
+    /// Like [`Self::call`], returning the response's data as JSON.
+    async fn call_json(
+        &self,
+        conn: &Connection,
+        hdr: &Header<'_>,
+        req: Request,
+    ) -> fdo::Result<String> {
+        let peer = peer_of(conn, hdr).await;
+        let data = to_fdo(self.daemon.handle(req, peer).await)?;
+        serde_json::to_string(&data.unwrap_or_default())
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+}
+
+#[interface(name = "org.loki.MasterControl1")]
+impl Service {
+    async fn set_brightness(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        value: u32,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetBrightness { value }).await
+    }
+
+    /// Fade to `percent` over `transition_ms`, capped like the socket's.
+    async fn set_brightness_percent(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        percent: f64,
+        transition_ms: u64,
+    ) -> fdo::Result<()> {
+        let req = Request::SetBrightnessPercent { percent, transition_ms: Some(transition_ms) };
+        self.call(conn, &hdr, req).await
+    }
+
+    async fn set_auto_brightness(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        enabled: bool,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetAutoBrightness { enabled }).await
+    }
+
+    async fn reset_auto_brightness(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::ResetAutoBrightness).await
+    }
+
+    /// The socket's `get_auto_brightness` data as JSON.
+    async fn get_auto_brightness(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+    ) -> fdo::Result<String> {
+        self.call_json(conn, &hdr, Request::GetAutoBrightness).await
+    }
+
+    async fn set_tdp(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        watts: u32,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetTdp { watts }).await
+    }
+
+    async fn set_fan_mode(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        mode: String,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetFanMode { mode }).await
+    }
+
+    async fn set_fan_pwm(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        pwm: u8,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetFanPwm { pwm }).await
+    }
+
+    async fn set_rgb(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        mode: u8,
+        brightness: u8,
+        color: (u8, u8, u8),
+    ) -> fdo::Result<()> {
+        let (r, g, b) = color;
+        let req = Request::SetRgb { mode, brightness, color: [r, g, b] };
+        self.call(conn, &hdr, req).await
+    }
+
+    /// Change the software effect with any of `effect` (s), `speed` (d),
+    /// `colors` (a(yyy)), `brightness` (y) and `fps` (u). Returns the
+    /// resulting effect as JSON.
+    async fn set_rgb_effect(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        settings: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<String> {
+        let effects::Update { effect, speed, colors, brightness, fps } = effect_update(settings)?;
+        let req = Request::SetRgbEffect { effect, speed, colors, brightness, fps };
+        self.call_json(conn, &hdr, req).await
+    }
+
+    /// The running software effect as JSON.
+    async fn get_rgb_effect(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+    ) -> fdo::Result<String> {
+        self.call_json(conn, &hdr, Request::GetRgbEffect).await
+    }
+
+    async fn set_cpu_governor(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        governor: String,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetCpuGovernor { governor }).await
+    }
+
+    async fn set_cpu_epp(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        preference: String,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetCpuEpp { preference }).await
+    }
+
+    async fn set_cpu_boost(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        enabled: bool,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetCpuBoost { enabled }).await
+    }
+
+    async fn set_cpu_freq_limits(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        min_khz: u32,
+        max_khz: u32,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetCpuFreqLimits { min_khz, max_khz }).await
+    }
+
+    async fn set_smt(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        enabled: bool,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetSmt { enabled }).await
+    }
+
+    async fn set_cpu_online(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        cpu: u32,
+        online: bool,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetCpuOnline { cpu, online }).await
+    }
+
+    async fn set_online_cpus(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        count: u32,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetOnlineCpus { count }).await
+    }
+
+    async fn set_gpu_performance_level(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        level: String,
+        card: String,
+    ) -> fdo::Result<()> {
+        let req = Request::SetGpuPerformanceLevel { level, card: optional_card(card) };
+        self.call(conn, &hdr, req).await
+    }
+
+    async fn set_gpu_power_profile(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        profile: String,
+        card: String,
+    ) -> fdo::Result<()> {
+        let req = Request::SetGpuPowerProfile { profile, card: optional_card(card) };
+        self.call(conn, &hdr, req).await
+    }
+
+    async fn set_gpu_clock_limits(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        min_mhz: u32,
+        max_mhz: u32,
+        card: String,
+    ) -> fdo::Result<()> {
+        let req = Request::SetGpuClockLimits { min_mhz, max_mhz, card: optional_card(card) };
+        self.call(conn, &hdr, req).await
+    }
+
+    /// Soft-block or unblock radios of a kernel type, or `all`.
+    async fn set_rfkill(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: Header<'_>,
+        kind: String,
+        blocked: bool,
+    ) -> fdo::Result<()> {
+        self.call(conn, &hdr, Request::SetRfkill { kind, blocked }).await
+    }
+
+    /// Full state as the JSON document returned by the socket's `get_state`.
+    async fn get_state(&self) -> fdo::Result<String> {
+        serde_json::to_string(&*self.state.lock().await)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    #[zbus(property)]
+    async fn brightness(&self) -> u32 {
+        self.state.lock().await.backlight.as_ref().map_or(0, |b| b.brightness)
+    }
+
+    #[zbus(property)]
+    async fn max_brightness(&self) -> u32 {
+        self.state.lock().await.backlight.as_ref().map_or(0, |b| b.max_brightness)
+    }
+
+    /// Sustained power limit in watts, 0 when unknown.
+    #[zbus(property)]
+    async fn tdp_limit(&self) -> f64 {
+        let state = self.state.lock().await;
+        state.tdp.as_ref().and_then(|t| t.stapm_limit).unwrap_or(0.0) as f64
+    }
+
+    /// `auto`, `manual`, or empty without fan control.
+    #[zbus(property)]
+    async fn fan_mode(&self) -> String {
+        self.state.lock().await.fan.as_ref().map(|f| f.mode.clone()).unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    async fn fan_pwm(&self) -> u8 {
+        self.state.lock().await.fan.as_ref().and_then(|f| f.pwm).unwrap_or(0)
+    }
+
+    #[zbus(property)]
+    async fn fan_rpm(&self) -> u32 {
+        self.state.lock().await.fan.as_ref().and_then(|f| f.rpm).unwrap_or(0)
+    }
+
+    /// Degrees Celsius keyed by `sensor/label`.
+    #[zbus(property)]
+    async fn temperatures(&self) -> HashMap<String, f64> {
+        self.state
+            .lock()
+            .await
+            .temperatures
+            .iter()
+            .map(|t| (format!("{}/{}", t.sensor, t.label), t.celsius as f64))
+            .collect()
+    }
+
+    /// `(mode, brightness, red, green, blue)`.
+    #[zbus(property)]
+    async fn rgb(&self) -> (u8, u8, u8, u8, u8) {
+        let state = self.state.lock().await;
+        let Some(rgb) = state.rgb.as_ref() else {
+            return (0, 0, 0, 0, 0);
+        };
+        let [r, g, b] = rgb.color.unwrap_or_default();
+        (rgb.mode.unwrap_or(0), rgb.brightness.unwrap_or(0), r, g, b)
+    }
+}
+
+fn connect() -> zbus::Result<zbus::connection::Builder<'static>> {
+    // Tests and development setups can point the service at a private bus.
+    match std::env::var("LOKI_DBUS_ADDRESS") {
+        Ok(addr) => zbus::connection::Builder::address(addr.as_str()),
+        Err(_) => zbus::connection::Builder::system(),
+    }
+}
+
+/// Claim [`BUS_NAME`] and keep the exported properties in sync with the
+/// hardware until the connection drops.
+pub async fn serve(daemon: Arc<Daemon>) -> zbus::Result<()> {
+    serve_on(daemon, connect()?).await
+}
+
+async fn serve_on(daemon: Arc<Daemon>, bus: zbus::connection::Builder<'_>) -> zbus::Result<()> {
+    let root = daemon.root.clone();
+    let service = Service { daemon, state: Mutex::new(state::collect(&root).await) };
+    let conn = bus.name(BUS_NAME)?.serve_at(OBJECT_PATH, service)?.build().await?;
+    let iface_ref = conn.object_server().interface::<_, Service>(OBJECT_PATH).await?;
+
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let new = state::collect(&root).await;
+        let iface = iface_ref.get().await;
+        let old = std::mem::replace(&mut *iface.state.lock().await, new.clone());
+        let emitter = iface_ref.signal_emitter();
+
+        // A failed emit only loses that notification; keep serving.
+        let mut emitted = Vec::new();
+        let brightness = |s: &State| s.backlight.as_ref().map(|b| (b.brightness, b.max_brightness));
+        if brightness(&old) != brightness(&new) {
+            emitted.push(iface.brightness_changed(emitter).await);
+            emitted.push(iface.max_brightness_changed(emitter).await);
+        }
+        let tdp = |s: &State| s.tdp.as_ref().and_then(|t| t.stapm_limit);
+        if tdp(&old) != tdp(&new) {
+            emitted.push(iface.tdp_limit_changed(emitter).await);
+        }
+        let fan = |s: &State| s.fan.as_ref().map(|f| (f.mode.clone(), f.pwm, f.rpm));
+        if fan(&old) != fan(&new) {
+            emitted.push(iface.fan_mode_changed(emitter).await);
+            emitted.push(iface.fan_pwm_changed(emitter).await);
+            emitted.push(iface.fan_rpm_changed(emitter).await);
+        }
+        let temps = |s: &State| s.temperatures.iter().map(|t| t.celsius).collect::<Vec<_>>();
+        if temps(&old) != temps(&new) {
+            emitted.push(iface.temperatures_changed(emitter).await);
+        }
+        let rgb = |s: &State| s.rgb.as_ref().map(|r| (r.mode, r.brightness, r.color));
+        if rgb(&old) != rgb(&new) {
+            emitted.push(iface.rgb_changed(emitter).await);
+        }
+        for e in emitted.into_iter().filter_map(Result::err) {
+            eprintln!("failed to emit a D-Bus property change: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::TempRoot;
+    use futures_util::StreamExt;
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use zbus::zvariant::Value;
+
+    /// A `dbus-daemon` on a socket in a temporary directory, killed on drop.
+    struct PrivateBus {
+        child: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        /// `None` when `dbus-daemon` isn't installed.
+        fn start(dir: &Path) -> Option<PrivateBus> {
+            let socket = dir.join("bus");
+            let config = dir.join("bus.conf");
+            std::fs::write(
+                &config,
+                format!(
+                    r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:path={}</listen>
+  <policy context="default">
+    <allow send_destination="*"/>
+    <allow receive_sender="*"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#,
+                    socket.display()
+                ),
+            )
+            .unwrap();
+            let child = Command::new("dbus-daemon")
+                .arg(format!("--config-file={}", config.display()))
+                .arg("--nofork")
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let bus = PrivateBus { child, address: format!("unix:path={}", socket.display()) };
+            for _ in 0..100 {
+                if socket.exists() {
+                    return Some(bus);
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            None
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_properties_and_methods_on_a_private_bus() {
+        let tmp = TempRoot::new("dbus");
+        let dir = &tmp.root;
+        let Some(bus) = PrivateBus::start(dir) else {
+            eprintln!("dbus-daemon not available; skipping");
+            return;
+        };
+        let panel = tmp.dir("sys/class/backlight/panel0");
+        for (name, value) in [("brightness", "100"), ("max_brightness", "255"), ("type", "raw")] {
+            tmp.write(panel.join(name), value);
+        }
+
+        let daemon = Arc::new(crate::Daemon::for_tests(dir, &dir.join("sys")));
+        let service = tokio::spawn(serve_on(
+            daemon,
+            zbus::connection::Builder::address(bus.address.as_str()).unwrap(),
+        ));
+        let conn = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let dbus = fdo::DBusProxy::new(&conn).await.unwrap();
+        let name: zbus::names::BusName = BUS_NAME.try_into().unwrap();
+        for _ in 0..100 {
+            if dbus.name_has_owner(name.clone()).await.unwrap() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let props = fdo::PropertiesProxy::builder(&conn)
+            .destination(BUS_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let iface = "org.loki.MasterControl1".try_into().unwrap();
+        let max: u32 = props.get(iface, "MaxBrightness").await.unwrap().try_into().unwrap();
+        assert_eq!(max, 255);
+
+        let proxy = zbus::Proxy::new(&conn, BUS_NAME, OBJECT_PATH, "org.loki.MasterControl1")
+            .await
+            .unwrap();
+        // Missing hardware is reported as not found.
+        let err = proxy.call_method("SetRgb", &(1u8, 255u8, (255u8, 0u8, 0u8))).await.unwrap_err();
+        assert!(
+            matches!(&err, zbus::Error::MethodError(name, _, _)
+                if name.as_str() == "org.freedesktop.DBus.Error.FileNotFound"),
+            "{err}"
+        );
+
+        // Settings left out of the a{sv} keep their value; bad ones are refused.
+        let effect: String = proxy
+            .call("SetRgbEffect", &(HashMap::from([("speed", Value::from(2.0))]),))
+            .await
+            .unwrap();
+        let effect: effects::Effect = serde_json::from_str(&effect).unwrap();
+        assert_eq!((effect.effect, effect.speed), (Kind::Off, 2.0));
+        let err = proxy
+            .call_method("SetRgbEffect", &(HashMap::from([("speed", Value::from("fast"))]),))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, zbus::Error::MethodError(name, _, _)
+                if name.as_str() == "org.freedesktop.DBus.Error.InvalidArgs"),
+            "{err}"
+        );
+
+        // Writes land in sysfs and come back as a property change.
+        let mut changes = props.receive_properties_changed().await.unwrap();
+        proxy.call_method("SetBrightness", &(200u32,)).await.unwrap();
+        assert_eq!(std::fs::read_to_string(panel.join("brightness")).unwrap(), "200");
+        let changed = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(signal) = changes.next().await {
+                let args = signal.args().unwrap();
+                if let Some(value) = args.changed_properties().get("Brightness") {
+                    return u32::try_from(value).unwrap();
+                }
+            }
+            0
+        })
+        .await
+        .expect("no PropertiesChanged for Brightness");
+        assert_eq!(changed, 200);
+
+        service.abort();
+    }
+}
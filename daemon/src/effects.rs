@@ -0,0 +1,330 @@
+//! Software RGB effects for the joystick rings. Every frame writes
+//! `multi_intensity` and `brightness` through the coalescer, so effects keep
+//! running with the panel closed. Settings are saved and resumed when the
+//! daemon starts.
+
+use serde::{Deserialize, Serialize};
+use std::f64::consts::TAU;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify};
+
+use crate::ops::OpError;
+use crate::persist::SettingsFile;
+use crate::runner::Op;
+use crate::state::RGB_LED;
+use crate::Daemon;
+
+pub const STATE_PATH: &str = "/var/lib/loki-master/rgb-effect.json";
+pub const SPEED_RANGE: (f64, f64) = (0.25, 4.0);
+/// Writes are debounced by the coalescer, so faster frames would be merged
+/// away.
+pub const FPS_RANGE: (u32, u32) = (1, 30);
+pub const MAX_COLORS: usize = 8;
+/// Seconds per cycle at speed 1.
+const RAINBOW_PERIOD: f64 = 6.0;
+const BREATHING_PERIOD: f64 = 4.0;
+const PULSE_PERIOD: f64 = 1.5;
+const STROBE_PERIOD: f64 = 0.25;
+/// How long an LED that has gone missing waits before it is looked for
+/// again.
+const MISSING_LED_RETRY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Off,
+    /// Cycle through the hues; colours are ignored.
+    Rainbow,
+    /// Fade each colour in and out in turn.
+    Breathing,
+    /// Flash up quickly and decay, one colour per beat.
+    Pulse,
+    /// Short full-brightness flashes, one colour per flash.
+    Strobe,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Effect {
+    pub effect: Kind,
+    /// Multiplier on the effect's natural pace.
+    pub speed: f64,
+    pub colors: Vec<[u8; 3]>,
+    /// Peak LED brightness, as written to `brightness`.
+    pub brightness: u8,
+    pub fps: u32,
+}
+
+impl Default for Effect {
+    fn default() -> Effect {
+        Effect {
+            effect: Kind::Off,
+            speed: 1.0,
+            colors: vec![[255, 0, 0], [0, 0, 255]],
+            brightness: 255,
+            fps: 30,
+        }
+    }
+}
+
+/// Changes requested by `set_rgb_effect`; omitted fields keep their value.
+#[derive(Clone, Debug, Default)]
+pub struct Update {
+    pub effect: Option<Kind>,
+    pub speed: Option<f64>,
+    pub colors: Option<Vec<[u8; 3]>>,
+    pub brightness: Option<u8>,
+    pub fps: Option<u32>,
+}
+
+fn hsv_to_rgb(h: f64) -> [u8; 3] {
+    let x = 1.0 - ((h / 60.0) % 2.0 - 1.0).abs();
+    let (r, g, b) = match (h / 60.0) as u32 % 6 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [r, g, b].map(|c: f64| (c * 255.0).round() as u8)
+}
+
+/// Colour and brightness at `phase`, the time into the effect in seconds at
+/// speed 1.
+pub fn frame(effect: &Effect, phase: f64) -> ([u8; 3], u8) {
+    let cycle = |period: f64| {
+        let cycles = phase / period;
+        let color = match effect.colors.len() {
+            0 => [255, 255, 255],
+            n => effect.colors[cycles.floor() as usize % n],
+        };
+        (color, cycles.fract())
+    };
+    let level = |level: f64| (f64::from(effect.brightness) * level).round() as u8;
+    match effect.effect {
+        Kind::Off => ([0, 0, 0], 0),
+        Kind::Rainbow => (hsv_to_rgb((phase / RAINBOW_PERIOD).fract() * 360.0), effect.brightness),
+        Kind::Breathing => {
+            let (color, x) = cycle(BREATHING_PERIOD);
+            (color, level((1.0 - (TAU * x).cos()) / 2.0))
+        }
+        Kind::Pulse => {
+            let (color, x) = cycle(PULSE_PERIOD);
+            // A tenth of the beat rising, then an exponential tail.
+            let rise = 0.1;
+            (color, level(if x < rise { x / rise } else { (-(x - rise) * 6.0).exp() }))
+        }
+        Kind::Strobe => {
+            // Flashes and gaps last at least a frame each, or fast strobes
+            // would fall between frames.
+            let frame_len = effect.speed / f64::from(effect.fps.max(1));
+            let period = STROBE_PERIOD.max(2.0 * frame_len);
+            let (color, x) = cycle(period);
+            (color, if x < 0.25f64.max(frame_len / period) { effect.brightness } else { 0 })
+        }
+    }
+}
+
+struct Inner {
+    effect: Effect,
+    /// Bumped by every change, so the engine restarts its set-up.
+    generation: u64,
+}
+
+pub struct Controller {
+    inner: Mutex<Inner>,
+    changed: Notify,
+    file: SettingsFile,
+}
+
+impl Controller {
+    /// Load the saved effect from `path`, if there is one.
+    pub fn new(path: PathBuf) -> Controller {
+        let file = SettingsFile::new(path);
+        let effect = file.load::<Effect>().unwrap_or_default();
+        Controller {
+            inner: Mutex::new(Inner { effect, generation: 0 }),
+            changed: Notify::new(),
+            file,
+        }
+    }
+
+    /// Controller persisted at `LOKI_RGB_EFFECT_STATE`.
+    pub fn from_env() -> Controller {
+        let path = std::env::var_os("LOKI_RGB_EFFECT_STATE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(STATE_PATH));
+        Controller::new(path)
+    }
+
+    pub async fn current(&self) -> Effect {
+        self.inner.lock().await.effect.clone()
+    }
+
+    pub async fn set(&self, update: Update) -> Result<Effect, OpError> {
+        let mut inner = self.inner.lock().await;
+        let mut effect = inner.effect.clone();
+        if let Some(speed) = update.speed {
+            let (min, max) = SPEED_RANGE;
+            if !(min..=max).contains(&speed) {
+                return Err(OpError::invalid(format!("speed must be between {min} and {max}")));
+            }
+            effect.speed = speed;
+        }
+        if let Some(fps) = update.fps {
+            let (min, max) = FPS_RANGE;
+            if !(min..=max).contains(&fps) {
+                return Err(OpError::invalid(format!("fps must be between {min} and {max}")));
+            }
+            effect.fps = fps;
+        }
+        if let Some(colors) = update.colors {
+            if colors.is_empty() || colors.len() > MAX_COLORS {
+                return Err(OpError::invalid(format!("give between 1 and {MAX_COLORS} colors")));
+            }
+            effect.colors = colors;
+        }
+        if let Some(kind) = update.effect {
+            effect.effect = kind;
+        }
+        effect.brightness = update.brightness.unwrap_or(effect.brightness);
+        // Only queued here; the engine's frames don't wait on the disk.
+        self.file.save(&effect);
+        inner.effect = effect.clone();
+        inner.generation += 1;
+        self.changed.notify_one();
+        Ok(effect)
+    }
+
+    /// Stop the effect before the LEDs are set some other way. Once this
+    /// returns the engine writes no further frames.
+    pub async fn stop(&self) {
+        if self.inner.lock().await.effect.effect != Kind::Off {
+            let _ = self.set(Update { effect: Some(Kind::Off), ..Update::default() }).await;
+        }
+    }
+}
+
+/// Animate the LEDs for the life of the daemon while an effect is on.
+pub async fn run(daemon: Arc<Daemon>) {
+    let controller = &daemon.rgb_effect;
+    let base = daemon.root.join("class/leds").join(RGB_LED);
+    let write = |name: &str, value: String| Op::Write {
+        path: base.join(name).to_string_lossy().into_owned(),
+        value,
+    };
+    let mut set_up = None;
+    let mut last_frame = None;
+    let mut phase = 0.0;
+    let mut last_tick = Instant::now();
+    loop {
+        let started = Instant::now();
+        let wait = {
+            // Held while the frame is written, so `stop` waits for it.
+            let inner = controller.inner.lock().await;
+            let effect = &inner.effect;
+            if effect.effect == Kind::Off {
+                None
+            } else if !base.exists() {
+                set_up = None;
+                Some(MISSING_LED_RETRY)
+            } else {
+                let now = Instant::now();
+                phase += now.duration_since(last_tick).as_secs_f64() * effect.speed;
+                last_tick = now;
+                // Software effects need the LED in static mode.
+                if set_up != Some(inner.generation) {
+                    set_up = Some(inner.generation);
+                    last_frame = None;
+                    let resp = daemon.coalescer.submit(write("led_mode", "1".into())).await;
+                    if !resp.success {
+                        eprintln!("RGB effect: {}", resp.error.unwrap_or_default());
+                    }
+                }
+                let current = frame(effect, phase);
+                if last_frame != Some(current) {
+                    last_frame = Some(current);
+                    let ([r, g, b], brightness) = current;
+                    let (color, level) = tokio::join!(
+                        daemon.coalescer.submit(write("multi_intensity", format!("{r} {g} {b}"))),
+                        daemon.coalescer.submit(write("brightness", brightness.to_string())),
+                    );
+                    if let Some(e) = [color, level].into_iter().find_map(|r| r.error) {
+                        eprintln!("RGB effect: {e}");
+                    }
+                }
+                let period = Duration::from_secs_f64(1.0 / f64::from(effect.fps.max(1)));
+                Some(period.saturating_sub(started.elapsed()))
+            }
+        };
+        match wait {
+            Some(wait) => {
+                let _ = tokio::time::timeout(wait, controller.changed.notified()).await;
+            }
+            None => {
+                controller.changed.notified().await;
+                phase = 0.0;
+                last_tick = Instant::now();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strobe(speed: f64, fps: u32) -> Effect {
+        Effect { effect: Kind::Strobe, speed, fps, ..Effect::default() }
+    }
+
+    /// Brightness of each frame the engine would write over `seconds`.
+    fn frames(effect: &Effect, seconds: f64) -> Vec<u8> {
+        let frame_len = effect.speed / f64::from(effect.fps);
+        let count = (seconds * f64::from(effect.fps)) as usize;
+        (0..count).map(|n| frame(effect, n as f64 * frame_len).1).collect()
+    }
+
+    #[test]
+    fn strobe_flashes_a_quarter_of_each_cycle_at_normal_speed() {
+        let effect = strobe(1.0, 30);
+        assert_eq!(frame(&effect, 0.0).1, 255);
+        assert_eq!(frame(&effect, STROBE_PERIOD * 0.2).1, 255);
+        assert_eq!(frame(&effect, STROBE_PERIOD * 0.3).1, 0);
+        let (first, _) = frame(&effect, 0.0);
+        let (second, _) = frame(&effect, STROBE_PERIOD);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn fast_strobes_still_light_whole_frames() {
+        // At speed 4 a quarter cycle is shorter than a frame at 30 fps.
+        for fps in [30, 20, 10] {
+            let levels = frames(&strobe(4.0, fps), 2.0);
+            let flashes = levels.windows(2).filter(|w| w[0] == 0 && w[1] > 0).count();
+            assert!(flashes >= levels.len() / 3, "{fps} fps: {levels:?}");
+            assert!(levels.contains(&0), "{fps} fps: {levels:?}");
+        }
+        // Frames stay further apart than the coalescer merges writes.
+        let (_, max_fps) = FPS_RANGE;
+        assert!(Duration::from_secs_f64(1.0 / f64::from(max_fps)) > crate::coalesce::DEBOUNCE);
+    }
+
+    #[tokio::test]
+    async fn settings_are_saved_and_resumed() {
+        let tmp = crate::testfs::TempRoot::new("effects");
+        let path = tmp.root.join("rgb-effect.json");
+        let controller = Controller::new(path.clone());
+        let update = Update { effect: Some(Kind::Pulse), speed: Some(2.0), ..Update::default() };
+        controller.set(update).await.unwrap();
+        let bad = Update { speed: Some(9.0), ..Update::default() };
+        assert!(controller.set(bad).await.is_err());
+        controller.set(Update { fps: Some(20), ..Update::default() }).await.unwrap();
+        controller.file.flush().await;
+
+        let resumed = Controller::new(path).current().await;
+        assert_eq!((resumed.effect, resumed.speed, resumed.fps), (Kind::Pulse, 2.0, 20));
+    }
+}
@@ -0,0 +1,383 @@
+//! amdgpu power management: forced performance level, power profile and
+//! manual GFX clock limits through `pp_od_clk_voltage`.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::ops::OpError;
+use crate::runner::Op;
+use crate::state::{amdgpu_cards, parse_active_dpm_level};
+use crate::sysfs::read_trimmed;
+
+/// Values accepted by `power_dpm_force_performance_level`.
+pub const PERFORMANCE_LEVELS: [&str; 8] = [
+    "auto",
+    "low",
+    "high",
+    "manual",
+    "profile_standard",
+    "profile_min_sclk",
+    "profile_min_mclk",
+    "profile_peak",
+];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Gpu {
+    /// DRM card name, e.g. `card0`.
+    pub card: String,
+    pub performance_level: Option<String>,
+    pub power_profile: Option<String>,
+    pub available_profiles: Vec<String>,
+    /// Current shader clock.
+    pub sclk_mhz: Option<u32>,
+    /// Manual GFX clock limits from `OD_SCLK`, when overdrive is enabled.
+    pub min_mhz: Option<u32>,
+    pub max_mhz: Option<u32>,
+    /// Allowed range from `OD_RANGE`.
+    pub range_min_mhz: Option<u32>,
+    pub range_max_mhz: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerProfile {
+    pub index: u32,
+    pub name: String,
+    pub active: bool,
+}
+
+/// Parse `pp_power_profile_mode`. Profile rows start with their index and
+/// name, the active one marked with `*`; per-clock detail rows and the
+/// header are skipped.
+pub fn parse_power_profiles(text: &str) -> Vec<PowerProfile> {
+    text.lines()
+        .filter_map(|line| {
+            let (index, rest) = line.trim().split_once(char::is_whitespace)?;
+            let index = index.parse().ok()?;
+            let head = rest.split(':').next()?;
+            let name = head.trim().trim_end_matches('*').trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return None;
+            }
+            Some(PowerProfile { index, name: name.to_string(), active: head.contains('*') })
+        })
+        .collect()
+}
+
+fn parse_mhz(text: &str) -> Option<u32> {
+    text.trim().to_ascii_lowercase().trim_end_matches("mhz").parse().ok()
+}
+
+/// GFX clock section of `pp_od_clk_voltage`, as `(min, max)` MHz pairs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OdSclk {
+    pub limits: Option<(u32, u32)>,
+    pub range: Option<(u32, u32)>,
+}
+
+pub fn parse_od_sclk(text: &str) -> OdSclk {
+    let mut section = "";
+    let (mut min, mut max, mut range) = (None, None, None);
+    for line in text.lines().map(str::trim) {
+        // Headers can carry a note, e.g. `OD_CCLK: (CPU core 0)` on Van Gogh.
+        let header = line.split_whitespace().next().and_then(|w| w.strip_suffix(':'));
+        if let Some(name) = header.filter(|w| w.starts_with("OD_")) {
+            section = name;
+            continue;
+        }
+        match section {
+            "OD_SCLK" => match line.split_once(':') {
+                Some(("0", v)) => min = parse_mhz(v),
+                Some(("1", v)) => max = parse_mhz(v),
+                _ => {}
+            },
+            "OD_RANGE" => {
+                if let Some(v) = line.strip_prefix("SCLK:") {
+                    let mut values = v.split_whitespace().filter_map(parse_mhz);
+                    if let (Some(lo), Some(hi)) = (values.next(), values.next()) {
+                        range = Some((lo, hi));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    OdSclk { limits: min.zip(max), range }
+}
+
+fn read_card(card: &Path) -> Option<Gpu> {
+    let dev = card.join("device");
+    let profiles = read_trimmed(dev.join("pp_power_profile_mode"))
+        .map(|t| parse_power_profiles(&t))
+        .unwrap_or_default();
+    let od = read_trimmed(dev.join("pp_od_clk_voltage"))
+        .map(|t| parse_od_sclk(&t))
+        .unwrap_or_default();
+    Some(Gpu {
+        card: card.file_name()?.to_string_lossy().into_owned(),
+        performance_level: read_trimmed(dev.join("power_dpm_force_performance_level")),
+        power_profile: profiles.iter().find(|p| p.active).map(|p| p.name.clone()),
+        available_profiles: profiles
+            .into_iter()
+            .map(|p| p.name)
+            .filter(|n| n != "CUSTOM")
+            .collect(),
+        sclk_mhz: read_trimmed(dev.join("pp_dpm_sclk")).and_then(|t| parse_active_dpm_level(&t)),
+        min_mhz: od.limits.map(|l| l.0),
+        max_mhz: od.limits.map(|l| l.1),
+        range_min_mhz: od.range.map(|r| r.0),
+        range_max_mhz: od.range.map(|r| r.1),
+    })
+}
+
+pub fn read(root: &Path) -> Vec<Gpu> {
+    amdgpu_cards(root).iter().filter_map(|c| read_card(c)).collect()
+}
+
+/// The named card's `device` directory, or the first amdgpu card's.
+fn device_dir(root: &Path, card: Option<&str>) -> Result<PathBuf, OpError> {
+    let cards = amdgpu_cards(root);
+    let found = match card {
+        Some(name) => cards.into_iter().find(|c| c.file_name().is_some_and(|n| n == name)),
+        None => cards.into_iter().next(),
+    };
+    found.map(|c| c.join("device")).ok_or_else(|| OpError::missing("amdgpu card"))
+}
+
+fn write(path: PathBuf, value: impl ToString) -> Op {
+    Op::Write { path: path.to_string_lossy().into_owned(), value: value.to_string() }
+}
+
+pub fn set_performance_level(
+    root: &Path,
+    card: Option<&str>,
+    level: &str,
+) -> Result<Vec<Op>, OpError> {
+    let dev = device_dir(root, card)?;
+    if !PERFORMANCE_LEVELS.contains(&level) {
+        return Err(OpError::invalid(format!("unknown performance level {level:?}")));
+    }
+    Ok(vec![write(dev.join("power_dpm_force_performance_level"), level)])
+}
+
+/// Select a power profile by name. The kernel only honours this with the
+/// performance level forced to `manual`. `CUSTOM` needs heuristic values
+/// and is not offered.
+pub fn set_power_profile(
+    root: &Path,
+    card: Option<&str>,
+    profile: &str,
+) -> Result<Vec<Op>, OpError> {
+    let dev = device_dir(root, card)?;
+    let profiles = read_trimmed(dev.join("pp_power_profile_mode"))
+        .map(|t| parse_power_profiles(&t))
+        .unwrap_or_default();
+    if profiles.is_empty() {
+        return Err(OpError::missing("GPU power profile control"));
+    }
+    let found = profiles
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(profile) && p.name != "CUSTOM")
+        .ok_or_else(|| OpError::invalid(format!("unknown power profile {profile:?}")))?;
+    Ok(vec![
+        write(dev.join("power_dpm_force_performance_level"), "manual"),
+        write(dev.join("pp_power_profile_mode"), found.index),
+    ])
+}
+
+/// Set manual GFX clock limits. Needs overdrive (`amdgpu.ppfeaturemask`)
+/// and the `manual` performance level. Each command is a separate write,
+/// ending with the `c` commit; they go out as one op so that a newer request
+/// replaces all of them, never just some.
+pub fn set_clock_limits(
+    root: &Path,
+    card: Option<&str>,
+    min_mhz: u32,
+    max_mhz: u32,
+) -> Result<Vec<Op>, OpError> {
+    let dev = device_dir(root, card)?;
+    let od = dev.join("pp_od_clk_voltage");
+    let range = read_trimmed(&od).and_then(|t| parse_od_sclk(&t).range);
+    let Some((lo, hi)) = range else {
+        return Err(OpError::missing("GPU clock overdrive"));
+    };
+    if min_mhz > max_mhz {
+        return Err(OpError::invalid("minimum clock is above maximum"));
+    }
+    if min_mhz < lo || max_mhz > hi {
+        return Err(OpError::invalid(format!("GPU clock must be within {lo}-{hi} MHz")));
+    }
+    Ok(vec![
+        write(dev.join("power_dpm_force_performance_level"), "manual"),
+        Op::WriteSequence {
+            path: od.to_string_lossy().into_owned(),
+            values: vec![format!("s 0 {min_mhz}"), format!("s 1 {max_mhz}"), "c".into()],
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorKind;
+    use crate::testfs::TempRoot;
+
+    /// `pp_od_clk_voltage` on a Van Gogh APU, which also lists CPU clocks.
+    const VANGOGH_OD: &str = "\
+OD_SCLK:
+0:        200Mhz
+1:       1600Mhz
+OD_CCLK: (CPU core 0)
+0:       1400Mhz
+1:       3500Mhz
+OD_RANGE:
+SCLK:         200Mhz       1600Mhz
+CCLK:        1400Mhz       3500Mhz
+";
+
+    /// `pp_od_clk_voltage` on a Navi 10 card.
+    const NAVI_OD: &str = "\
+OD_SCLK:
+0: 800Mhz
+1: 2100Mhz
+OD_MCLK:
+1: 875MHz
+OD_VDDC_CURVE:
+0: 800MHz 711mV
+1: 1450MHz 794mV
+2: 2100MHz 1156mV
+OD_RANGE:
+SCLK:     800Mhz       2150Mhz
+MCLK:     625Mhz        950Mhz
+VDDC_CURVE_SCLK[0]:     800Mhz       2150Mhz
+VDDC_CURVE_VOLT[0]:     750mV        1200mV
+";
+
+    const VANGOGH_PROFILES: &str = "\
+ 0 BOOTUP_DEFAULT*
+ 1 3D_FULL_SCREEN
+ 2 POWER_SAVING
+ 3 VIDEO
+ 4 VR
+ 5 COMPUTE
+ 6 CUSTOM
+";
+
+    const NAVI_PROFILES: &str = "\
+PROFILE_INDEX(NAME) CLOCK_TYPE(NAME) FPS MinFreqType MinActiveFreqType MinActiveFreq BoosterFreqType BoosterFreq PD_Data_limit_c PD_Data_error_coeff PD_Data_error_rate_coeff
+ 0 BOOTUP_DEFAULT :
+                     0(       GFXCLK)       0       5       1       0       4     800 4587520  -65536       0
+                     1(       SOCCLK)       0       5       1       0       4     800 3407872  -65536       0
+ 1 3D_FULL_SCREEN*:
+                     0(       GFXCLK)       1       5       1       0       1       0 3932160  -65536  -65536
+ 2 POWER_SAVING   :
+                     0(       GFXCLK)       0       5       1       0       0       0 2752512  -65536  -65536
+";
+
+    fn fake_card(name: &str, od: Option<&str>) -> TempRoot {
+        let sys = TempRoot::new(&format!("gpu-{name}"));
+        let dev = Path::new("class/drm/card0/device");
+        sys.write(dev.join("power_dpm_force_performance_level"), "auto\n");
+        sys.write(dev.join("pp_power_profile_mode"), VANGOGH_PROFILES);
+        sys.write(dev.join("pp_dpm_sclk"), "0: 200Mhz\n1: 1100Mhz *\n2: 1600Mhz\n");
+        if let Some(od) = od {
+            sys.write(dev.join("pp_od_clk_voltage"), od);
+        }
+        // Connectors sit next to cards and are not GPUs.
+        sys.dir("class/drm/card0-eDP-1");
+        sys
+    }
+
+    #[test]
+    fn parses_the_gfx_clock_section_only() {
+        let od = parse_od_sclk(VANGOGH_OD);
+        assert_eq!(od, OdSclk { limits: Some((200, 1600)), range: Some((200, 1600)) });
+        let od = parse_od_sclk(NAVI_OD);
+        assert_eq!(od, OdSclk { limits: Some((800, 2100)), range: Some((800, 2150)) });
+        // Without overdrive the file is empty or missing sections.
+        assert_eq!(parse_od_sclk(""), OdSclk::default());
+        assert_eq!(parse_od_sclk("OD_SCLK:\n0:        200Mhz\n").limits, None);
+    }
+
+    #[test]
+    fn parses_power_profiles_in_both_layouts() {
+        let names = |text| {
+            parse_power_profiles(text)
+                .into_iter()
+                .map(|p| (p.index, p.name, p.active))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(VANGOGH_PROFILES).len(), 7);
+        assert_eq!(names(VANGOGH_PROFILES)[0], (0, "BOOTUP_DEFAULT".into(), true));
+        assert_eq!(
+            names(NAVI_PROFILES),
+            [
+                (0, "BOOTUP_DEFAULT".into(), false),
+                (1, "3D_FULL_SCREEN".into(), true),
+                (2, "POWER_SAVING".into(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_cards_with_and_without_overdrive() {
+        let sys = fake_card("read", Some(VANGOGH_OD));
+        let gpus = read(&sys.root);
+        assert_eq!(gpus.len(), 1);
+        let gpu = &gpus[0];
+        assert_eq!(gpu.card, "card0");
+        assert_eq!(gpu.performance_level.as_deref(), Some("auto"));
+        assert_eq!(gpu.power_profile.as_deref(), Some("BOOTUP_DEFAULT"));
+        assert!(!gpu.available_profiles.iter().any(|p| p == "CUSTOM"));
+        assert_eq!(gpu.sclk_mhz, Some(1100));
+        assert_eq!((gpu.min_mhz, gpu.max_mhz), (Some(200), Some(1600)));
+        assert_eq!((gpu.range_min_mhz, gpu.range_max_mhz), (Some(200), Some(1600)));
+
+        let plain = fake_card("plain", None);
+        let gpu = &read(&plain.root)[0];
+        assert_eq!((gpu.min_mhz, gpu.range_max_mhz), (None, None));
+    }
+
+    #[test]
+    fn performance_levels_and_profiles_are_validated() {
+        let sys = fake_card("level", None);
+        assert_eq!(set_performance_level(&sys.root, None, "high").unwrap().len(), 1);
+        let err = set_performance_level(&sys.root, None, "turbo").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidArgument);
+        let err = set_performance_level(&sys.root, Some("card1"), "high").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NotFound);
+
+        match &set_power_profile(&sys.root, Some("card0"), "video").unwrap()[..] {
+            [Op::Write { value: level, .. }, Op::Write { path, value }] => {
+                assert_eq!(level, "manual");
+                assert!(path.ends_with("/pp_power_profile_mode"));
+                assert_eq!(value, "3");
+            }
+            ops => panic!("unexpected ops: {ops:?}"),
+        }
+        assert!(set_power_profile(&sys.root, None, "custom").is_err());
+    }
+
+    #[test]
+    fn clock_limits_are_checked_and_committed_last() {
+        let sys = fake_card("clocks", Some(VANGOGH_OD));
+        for (min, max) in [(100, 1600), (200, 1700), (1200, 800)] {
+            let err = set_clock_limits(&sys.root, None, min, max).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::InvalidArgument, "{min}-{max}");
+        }
+
+        let ops = set_clock_limits(&sys.root, None, 400, 1200).unwrap();
+        match &ops[..] {
+            [Op::Write { path, value }, Op::WriteSequence { path: od, values }] => {
+                assert!(path.ends_with("/power_dpm_force_performance_level"));
+                assert_eq!(value, "manual");
+                assert!(od.ends_with("/pp_od_clk_voltage"));
+                assert_eq!(values, &["s 0 400", "s 1 1200", "c"]);
+            }
+            ops => panic!("unexpected ops: {ops:?}"),
+        }
+
+        let plain = fake_card("no-od", None);
+        let err = set_clock_limits(&plain.root, None, 400, 1200).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NotFound);
+    }
+}
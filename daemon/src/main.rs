@@ -1,26 +1,112 @@
+mod audit;
+mod autobright;
+mod backlight;
+mod caps;
+mod coalesce;
+mod cores;
+mod cpufreq;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod effects;
+mod gpu;
+mod metrics;
+mod ops;
+mod persist;
+mod protocol;
+mod rfkill;
+mod runner;
+mod state;
+mod sysfs;
+mod telemetry;
+#[cfg(test)]
+mod testfs;
+
 use tokio::net::{UnixListener, UnixStream};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use serde::Deserialize;
-use serde::Serialize;
 use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use audit::{AuditLog, Peer};
+use coalesce::Coalescer;
+use protocol::{ErrorKind, Request, Response};
+use runner::{Op, SystemRunner};
 
 const SOCK_PATH: &str = "/run/loki-master.sock";
 
-#[derive(Deserialize)]
-#[serde(tag = "cmd", rename_all = "snake_case")]
-enum Request {
-    Write { path: String, value: String },
-    Run { program: String, args: Vec<String> },
+/// Shared state handed to every client connection.
+struct Daemon {
+    /// sysfs tree the typed operations act on; see [`sysfs::root`].
+    root: PathBuf,
+    audit: AuditLog,
+    coalescer: Arc<Coalescer<SystemRunner>>,
+    telemetry: Arc<telemetry::Recorder>,
+    /// Bumped by every brightness fade; older fades stop when it changes.
+    brightness_fade: AtomicU64,
+    auto_brightness: autobright::Controller,
+    rgb_effect: effects::Controller,
 }
 
-#[derive(Serialize)]
-struct Response {
-    success: bool,
-    error: Option<String>,
+#[cfg(all(test, feature = "dbus"))]
+impl Daemon {
+    /// A daemon that keeps its log and saved settings under `dir` and acts
+    /// on the fake sysfs tree at `root`.
+    fn for_tests(dir: &std::path::Path, root: &std::path::Path) -> Daemon {
+        Daemon {
+            root: root.to_path_buf(),
+            audit: AuditLog::new(dir.join("audit.log")),
+            coalescer: Arc::new(Coalescer::new(Arc::new(SystemRunner), coalesce::DEBOUNCE)),
+            telemetry: Arc::new(telemetry::Recorder::new(16, None)),
+            brightness_fade: AtomicU64::new(0),
+            auto_brightness: autobright::Controller::new(dir.join("auto-brightness.json")),
+            rgb_effect: effects::Controller::new(dir.join("rgb-effect.json")),
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    let audit_path =
+        std::env::var("LOKI_AUDIT_LOG").unwrap_or_else(|_| audit::AUDIT_LOG_PATH.to_string());
+    let daemon = Arc::new(Daemon {
+        root: sysfs::root(),
+        audit: AuditLog::new(audit_path),
+        coalescer: Arc::new(Coalescer::new(Arc::new(SystemRunner), coalesce::DEBOUNCE)),
+        telemetry: Arc::new(telemetry::Recorder::from_env()),
+        brightness_fade: AtomicU64::new(0),
+        auto_brightness: autobright::Controller::from_env(),
+        rgb_effect: effects::Controller::from_env(),
+    });
+    tokio::spawn(
+        daemon
+            .telemetry
+            .clone()
+            .run(telemetry::Recorder::interval_from_env()),
+    );
+
+    tokio::spawn(autobright::run(daemon.clone()));
+    tokio::spawn(effects::run(daemon.clone()));
+
+    #[cfg(feature = "dbus")]
+    {
+        let daemon = daemon.clone();
+        tokio::spawn(async move {
+            if let Err(e) = dbus::serve(daemon).await {
+                eprintln!("D-Bus service unavailable: {e}");
+            }
+        });
+    }
+
+    if let Ok(listen) = std::env::var("LOKI_METRICS_LISTEN") {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(&listen).await {
+                eprintln!("metrics endpoint on {listen} failed: {e}");
+            }
+        });
+    }
+
     let _ = std::fs::remove_file(SOCK_PATH);
     let listener = UnixListener::bind(SOCK_PATH)?;
     // Make socket world-writable so unprivileged UI can connect
@@ -28,61 +114,236 @@ async fn main() -> std::io::Result<()> {
 
     loop {
         let (stream, _) = listener.accept().await?;
+        let daemon = daemon.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_client(stream).await {
+            if let Err(e) = handle_client(stream, &daemon).await {
                 eprintln!("client error: {e}");
             }
         });
     }
 }
 
-async fn handle_client(stream: UnixStream) -> std::io::Result<()> {
+async fn handle_client(stream: UnixStream, daemon: &Daemon) -> std::io::Result<()> {
+    let peer = Peer::from_stream(&stream);
     let mut reader = BufReader::new(stream);
     let mut line = String::new();
     reader.read_line(&mut line).await?;
-    let req: Request = match serde_json::from_str(&line) {
-        Ok(r) => r,
+    let resp = match serde_json::from_str::<Request>(&line) {
+        Ok(req) => daemon.handle(req, peer).await,
         Err(e) => {
-            let mut stream = reader.into_inner();
-            let resp = Response { success: false, error: Some(format!("parse error: {e}")) };
-            let msg = serde_json::to_string(&resp)? + "\n";
-            stream.write_all(msg.as_bytes()).await?;
-            return Ok(());
+            let resp = Response::err(format!("parse error: {e}"));
+            daemon
+                .audit
+                .record(&audit::Entry {
+                    ts_ms: audit::now_ms(),
+                    peer,
+                    cmd: "invalid".into(),
+                    params: serde_json::Value::String(line.trim_end().to_string()),
+                    success: false,
+                    error: resp.error.clone(),
+                    duration_ms: 0,
+                })
+                .await;
+            resp
         }
     };
-    let resp = process_request(req).await;
     let mut stream = reader.into_inner();
     let msg = serde_json::to_string(&resp)? + "\n";
     stream.write_all(msg.as_bytes()).await?;
     Ok(())
 }
 
-async fn process_request(req: Request) -> Response {
-    match req {
-        Request::Write { path, value } => {
-            match tokio::fs::write(&path, value).await {
-                Ok(_) => Response { success: true, error: None },
-                Err(e) => Response { success: false, error: Some(e.to_string()) },
+/// Split a request into its command name and remaining parameters for the
+/// audit log.
+fn split_request(req: &Request) -> (String, serde_json::Value) {
+    let mut value = serde_json::to_value(req).unwrap_or_default();
+    let cmd = value
+        .as_object_mut()
+        .and_then(|o| o.remove("cmd"))
+        .and_then(|c| c.as_str().map(str::to_string))
+        .unwrap_or_default();
+    (cmd, value)
+}
+
+impl Daemon {
+    /// Process one request from `peer` and record it in the audit log unless
+    /// it only reads state. Every front end (socket, D-Bus) goes through here.
+    async fn handle(&self, req: Request, peer: Peer) -> Response {
+        // The log names other users' processes and what they changed.
+        if matches!(req, Request::History { .. }) && peer.uid != Some(0) {
+            return Response::fail(ErrorKind::PermissionDenied, "history is only readable by root");
+        }
+        if req.is_read_only() {
+            return self.process_request(req).await;
+        }
+        let ts_ms = audit::now_ms();
+        let started = Instant::now();
+        let (cmd, params) = split_request(&req);
+        let resp = self.process_request(req).await;
+        self.audit
+            .record(&audit::Entry {
+                ts_ms,
+                peer,
+                cmd,
+                params,
+                success: resp.success,
+                error: resp.error.clone(),
+                duration_ms: started.elapsed().as_millis() as u64,
+            })
+            .await;
+        resp
+    }
+
+    /// Run the raw operations behind a typed request in order, stopping at the
+    /// first failure.
+    async fn apply(&self, ops: Result<Vec<Op>, ops::OpError>) -> Response {
+        let ops = match ops {
+            Ok(ops) => ops,
+            Err(e) => return e.into(),
+        };
+        let mut resp = Response::ok();
+        for op in ops {
+            resp = self.coalescer.submit(op).await;
+            if !resp.success {
+                break;
+            }
+        }
+        resp
+    }
+
+    /// Step the backlight towards `percent`. Each step goes through the
+    /// coalescer, so steps are at least one debounce interval apart. A newer
+    /// fade supersedes this one.
+    async fn fade_brightness(&self, percent: f64, duration: Duration) -> Response {
+        let fade = match backlight::transition(&self.root, percent) {
+            Ok(fade) => fade,
+            Err(e) => return e.into(),
+        };
+        let id = self.brightness_fade.fetch_add(1, Ordering::SeqCst) + 1;
+        let start = Instant::now();
+        loop {
+            let t = if duration.is_zero() {
+                1.0
+            } else {
+                start.elapsed().as_secs_f64() / duration.as_secs_f64()
+            };
+            let op = Op::Write { path: fade.path.clone(), value: fade.level(t).to_string() };
+            let resp = self.coalescer.submit(op).await;
+            if !resp.success || t >= 1.0 || self.brightness_fade.load(Ordering::SeqCst) != id {
+                return resp;
             }
         }
-        Request::Run { program, args } => {
-            match tokio::process::Command::new(program)
-                .args(args)
-                .status()
-                .await
-            {
-                Ok(status) => {
-                    if status.success() {
-                        Response { success: true, error: None }
-                    } else {
-                        Response {
-                            success: false,
-                            error: Some(format!("exit status: {status}")),
-                        }
-                    }
+    }
+
+    async fn process_request(&self, req: Request) -> Response {
+        let root = &self.root;
+        match req {
+            Request::Hello { version, .. } => {
+                if version < protocol::MIN_CLIENT_VERSION {
+                    return Response::fail(
+                        ErrorKind::UnsupportedVersion,
+                        format!(
+                            "client protocol {version} is older than the minimum {}",
+                            protocol::MIN_CLIENT_VERSION
+                        ),
+                    );
+                }
+                Response::with_data(protocol::Hello {
+                    version: protocol::PROTOCOL_VERSION,
+                    min_client_version: protocol::MIN_CLIENT_VERSION,
+                    capabilities: caps::detect(root),
+                })
+            }
+            Request::Write { path, value } => self.coalescer.submit(Op::Write { path, value }).await,
+            Request::Run { program, args, timeout_ms } => {
+                let timeout = timeout_ms.map(Duration::from_millis);
+                self.coalescer.submit(Op::Run { program, args, timeout }).await
+            }
+            Request::History { query } => Response::with_data(self.audit.query(&query).await),
+            Request::Stats => Response::with_data(self.coalescer.stats()),
+            Request::Telemetry { since_ms, until_ms } => {
+                Response::with_data(self.telemetry.query(since_ms, until_ms).await)
+            }
+            Request::SetBrightness { value } => self.apply(ops::set_brightness(root, value)).await,
+            Request::SetBrightnessPercent { percent, transition_ms } => {
+                let duration = transition_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(backlight::DEFAULT_TRANSITION)
+                    .min(backlight::MAX_TRANSITION);
+                let _paused = self.auto_brightness.manual_fade();
+                let resp = self.fade_brightness(percent, duration).await;
+                if resp.success {
+                    self.auto_brightness.manual_adjustment(percent);
                 }
-                Err(e) => Response { success: false, error: Some(e.to_string()) },
+                resp
+            }
+            Request::SetAutoBrightness { enabled } => {
+                if enabled && autobright::find_sensor(root).is_none() {
+                    return Response::fail(ErrorKind::NotFound, "no light sensor on this device");
+                }
+                self.auto_brightness.set_enabled(enabled);
+                Response::ok()
+            }
+            Request::ResetAutoBrightness => {
+                self.auto_brightness.reset_curve();
+                Response::ok()
+            }
+            Request::GetAutoBrightness => {
+                Response::with_data(self.auto_brightness.status(root))
+            }
+            Request::SetTdp { watts } => {
+                let resp = self.apply(ops::set_tdp(watts)).await;
+                state::invalidate_tdp().await;
+                resp
+            }
+            Request::SetFanMode { mode } => self.apply(ops::set_fan_mode(root, &mode)).await,
+            Request::SetFanPwm { pwm } => self.apply(ops::set_fan_pwm(root, pwm)).await,
+            Request::SetRgb { mode, brightness, color } => {
+                self.rgb_effect.stop().await;
+                self.apply(ops::set_rgb(root, mode, brightness, color)).await
+            }
+            Request::SetRgbEffect { effect, speed, colors, brightness, fps } => {
+                let leds = root.join("class/leds").join(state::RGB_LED);
+                if effect.is_some_and(|k| k != effects::Kind::Off) && !leds.exists() {
+                    return ops::OpError::missing("RGB LEDs").into();
+                }
+                let update = effects::Update { effect, speed, colors, brightness, fps };
+                match self.rgb_effect.set(update).await {
+                    Ok(effect) => Response::with_data(effect),
+                    Err(e) => e.into(),
+                }
+            }
+            Request::GetRgbEffect => Response::with_data(self.rgb_effect.current().await),
+            Request::SetCpuGovernor { governor } => {
+                self.apply(cpufreq::set_governor(root, &governor)).await
+            }
+            Request::SetCpuEpp { preference } => {
+                self.apply(cpufreq::set_epp(root, &preference)).await
+            }
+            Request::SetCpuBoost { enabled } => self.apply(cpufreq::set_boost(root, enabled)).await,
+            Request::SetCpuFreqLimits { min_khz, max_khz } => {
+                self.apply(cpufreq::set_freq_limits(root, min_khz, max_khz)).await
+            }
+            Request::SetRfkill { kind, blocked } => {
+                self.apply(rfkill::set_blocked(root, &kind, blocked)).await
+            }
+            Request::SetSmt { enabled } => self.apply(cores::set_smt(root, enabled)).await,
+            Request::SetCpuOnline { cpu, online } => {
+                self.apply(cores::set_cpu_online(root, cpu, online)).await
+            }
+            Request::SetOnlineCpus { count } => {
+                self.apply(cores::set_online_count(root, count)).await
+            }
+            Request::SetGpuPerformanceLevel { level, card } => {
+                self.apply(gpu::set_performance_level(root, card.as_deref(), &level)).await
+            }
+            Request::SetGpuPowerProfile { profile, card } => {
+                self.apply(gpu::set_power_profile(root, card.as_deref(), &profile)).await
+            }
+            Request::SetGpuClockLimits { min_mhz, max_mhz, card } => {
+                self.apply(gpu::set_clock_limits(root, card.as_deref(), min_mhz, max_mhz)).await
             }
+            Request::GetState => Response::with_data(state::collect(root).await),
         }
     }
 }
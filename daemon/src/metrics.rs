@@ -0,0 +1,252 @@
+//! Optional OpenMetrics endpoint. Enabled by setting `LOKI_METRICS_LISTEN`
+//! to a TCP address (`127.0.0.1:9661`) or `unix:<path>`; every scrape of
+//! `/metrics` takes a fresh [`State`] snapshot.
+
+use std::fmt::Write as _;
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener};
+
+use crate::state::{self, State};
+use crate::sysfs;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+/// Most bytes read for the request line and headers together.
+const MAX_REQUEST_HEAD: u64 = 8192;
+/// How long a client gets to send the request line and headers.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Family<'a> {
+    out: &'a mut String,
+}
+
+impl Family<'_> {
+    fn new<'a>(out: &'a mut String, name: &str, unit: &str, help: &str) -> Family<'a> {
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        if !unit.is_empty() {
+            let _ = writeln!(out, "# UNIT {name} {unit}");
+        }
+        let _ = writeln!(out, "# HELP {name} {help}");
+        Family { out }
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) -> &mut Self {
+        let _ = write!(self.out, "{name}");
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {value}");
+        self
+    }
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Render `state` in the OpenMetrics text format. Metric names and labels are
+/// part of the daemon's interface; don't rename them.
+pub fn render(state: &State) -> String {
+    let mut out = String::new();
+
+    let name = "loki_temperature_celsius";
+    let mut f = Family::new(&mut out, name, "celsius", "Sensor temperature.");
+    for t in &state.temperatures {
+        f.sample(name, &[("sensor", &t.sensor), ("label", &t.label)], t.celsius as f64);
+    }
+
+    if let Some(fan) = &state.fan {
+        if let Some(rpm) = fan.rpm {
+            let name = "loki_fan_rpm";
+            Family::new(&mut out, name, "", "Fan speed in revolutions per minute.")
+                .sample(name, &[], rpm as f64);
+        }
+        if let Some(pwm) = fan.pwm {
+            let name = "loki_fan_pwm_ratio";
+            Family::new(&mut out, name, "ratio", "Fan PWM duty cycle.")
+                .sample(name, &[], pwm as f64 / 255.0);
+        }
+        let name = "loki_fan_manual";
+        Family::new(&mut out, name, "", "1 when the fan is under manual control.")
+            .sample(name, &[], if fan.mode == "auto" { 0.0 } else { 1.0 });
+    }
+
+    if let Some(bat) = &state.battery {
+        if let Some(cap) = bat.capacity {
+            let name = "loki_battery_capacity_ratio";
+            Family::new(&mut out, name, "ratio", "Battery charge level.")
+                .sample(name, &[("battery", &bat.name)], cap as f64 / 100.0);
+        }
+        if let Some(w) = bat.power_w {
+            let name = "loki_battery_power_watts";
+            Family::new(&mut out, name, "watts", "Battery discharge rate, negative while charging.")
+                .sample(name, &[("battery", &bat.name)], w as f64);
+        }
+    }
+
+    if let Some(tdp) = &state.tdp {
+        let name = "loki_tdp_limit_watts";
+        let mut f = Family::new(&mut out, name, "watts", "Configured power limits.");
+        for (limit, value) in [
+            ("stapm", tdp.stapm_limit),
+            ("fast", tdp.fast_limit),
+            ("slow", tdp.slow_limit),
+        ] {
+            if let Some(v) = value {
+                f.sample(name, &[("limit", limit)], v as f64);
+            }
+        }
+        if let Some(v) = tdp.stapm_value {
+            let name = "loki_package_power_watts";
+            Family::new(&mut out, name, "watts", "Averaged package power (STAPM value).")
+                .sample(name, &[], v as f64);
+        }
+    }
+
+    let name = "loki_cpu_frequency_hertz";
+    let mut f = Family::new(&mut out, name, "hertz", "Current CPU core clock.");
+    for c in &state.clocks.cpus {
+        f.sample(name, &[("cpu", &c.cpu.to_string())], c.mhz as f64 * 1e6);
+    }
+    let name = "loki_gpu_frequency_hertz";
+    let mut f = Family::new(&mut out, name, "hertz", "Current GPU shader clock.");
+    for g in &state.clocks.gpus {
+        f.sample(name, &[("card", &g.card)], g.mhz as f64 * 1e6);
+    }
+
+    out.push_str("# EOF\n");
+    out
+}
+
+/// Answer a single HTTP request on `stream`.
+async fn serve_http<S: AsyncRead + AsyncWrite + Unpin>(stream: S) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    // A client that never sends a newline can't make us buffer forever, and
+    // one that goes quiet can't hold the task open.
+    let read_head = async {
+        let mut head = (&mut reader).take(MAX_REQUEST_HEAD);
+        head.read_line(&mut request_line).await?;
+        // Drain headers.
+        loop {
+            let mut line = String::new();
+            if head.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                return Ok::<_, std::io::Error>(());
+            }
+        }
+    };
+    tokio::time::timeout(REQUEST_TIMEOUT, read_head).await.map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, "no request within the time limit")
+    })??;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let (status, content_type, body) = if method == "GET" && path == "/metrics" {
+        let state = state::collect(&sysfs::root()).await;
+        ("200 OK", CONTENT_TYPE, render(&state))
+    } else {
+        ("404 Not Found", "text/plain", "not found\n".to_string())
+    };
+    let msg = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let mut stream = reader.into_inner();
+    stream.write_all(msg.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+pub async fn serve(listen: &str) -> std::io::Result<()> {
+    if let Some(path) = listen.strip_prefix("unix:") {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666));
+        loop {
+            let (stream, _) = listener.accept().await?;
+            tokio::spawn(async move {
+                if let Err(e) = serve_http(stream).await {
+                    eprintln!("metrics client error: {e}");
+                }
+            });
+        }
+    } else {
+        let listener = TcpListener::bind(listen).await?;
+        loop {
+            let (stream, _) = listener.accept().await?;
+            tokio::spawn(async move {
+                if let Err(e) = serve_http(stream).await {
+                    eprintln!("metrics client error: {e}");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{Battery, Clocks, CpuClock, Fan, GpuClock, Tdp, Temperature};
+
+    #[test]
+    fn metric_names_are_stable() {
+        let state = State {
+            fan: Some(Fan { mode: "manual".into(), pwm: Some(51), rpm: Some(2400) }),
+            temperatures: vec![Temperature {
+                sensor: "k10temp".into(),
+                label: "Tctl".into(),
+                celsius: 55.5,
+            }],
+            tdp: Some(Tdp {
+                stapm_limit: Some(15.0),
+                stapm_value: Some(9.5),
+                fast_limit: Some(20.0),
+                slow_limit: Some(18.0),
+            }),
+            battery: Some(Battery {
+                name: "BAT0".into(),
+                capacity: Some(80),
+                status: None,
+                power_w: Some(-12.5),
+            }),
+            clocks: Clocks {
+                cpus: vec![CpuClock { cpu: 0, mhz: 3000 }],
+                gpus: vec![GpuClock { card: "card0".into(), mhz: 1600 }],
+            },
+            ..State::default()
+        };
+        let text = render(&state);
+        let families: Vec<&str> = text.lines().filter_map(|l| l.strip_prefix("# TYPE ")).collect();
+        assert_eq!(
+            families,
+            [
+                "loki_temperature_celsius gauge",
+                "loki_fan_rpm gauge",
+                "loki_fan_pwm_ratio gauge",
+                "loki_fan_manual gauge",
+                "loki_battery_capacity_ratio gauge",
+                "loki_battery_power_watts gauge",
+                "loki_tdp_limit_watts gauge",
+                "loki_package_power_watts gauge",
+                "loki_cpu_frequency_hertz gauge",
+                "loki_gpu_frequency_hertz gauge",
+            ]
+        );
+        for sample in [
+            "loki_temperature_celsius{sensor=\"k10temp\",label=\"Tctl\"} 55.5",
+            "loki_fan_pwm_ratio 0.2",
+            "loki_fan_manual 1",
+            "loki_battery_power_watts{battery=\"BAT0\"} -12.5",
+            "loki_tdp_limit_watts{limit=\"stapm\"} 15",
+            "loki_cpu_frequency_hertz{cpu=\"0\"} 3000000000",
+            "loki_gpu_frequency_hertz{card=\"card0\"} 1600000000",
+        ] {
+            assert!(text.lines().any(|l| l == sample), "missing {sample:?} in\n{text}");
+        }
+        assert!(text.ends_with("\n# EOF\n"));
+        assert_eq!(text.matches("# EOF").count(), 1);
+    }
+}
@@ -0,0 +1,156 @@
+//! Typed hardware operations. Each one validates its input and expands into
+//! the raw [`Op`]s that implement it on this device.
+
+use std::path::Path;
+
+use crate::backlight;
+use crate::protocol::{ErrorKind, Response};
+use crate::runner::Op;
+use crate::state::{FAN_HWMON, RGB_LED};
+use crate::sysfs::{find_hwmon, read_parse};
+
+pub const MIN_TDP_W: u32 = 5;
+pub const MAX_TDP_W: u32 = 28;
+
+/// Validation failure for a typed operation.
+#[derive(Debug)]
+pub struct OpError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl OpError {
+    pub fn invalid(message: impl Into<String>) -> OpError {
+        OpError { kind: ErrorKind::InvalidArgument, message: message.into() }
+    }
+
+    pub fn missing(what: &str) -> OpError {
+        OpError { kind: ErrorKind::NotFound, message: format!("no {what} on this device") }
+    }
+}
+
+impl From<OpError> for Response {
+    fn from(e: OpError) -> Response {
+        Response::fail(e.kind, e.message)
+    }
+}
+
+fn write(path: &Path, value: impl ToString) -> Op {
+    Op::Write { path: path.to_string_lossy().into_owned(), value: value.to_string() }
+}
+
+/// Set the raw backlight level, clamped between the percentage curve's floor
+/// and `max_brightness` so a raw write cannot turn the panel off either.
+pub fn set_brightness(root: &Path, value: u32) -> Result<Vec<Op>, OpError> {
+    let dev = backlight::device(root).ok_or_else(|| OpError::missing("backlight"))?;
+    let max: u32 = read_parse(dev.join("max_brightness"))
+        .ok_or_else(|| OpError::missing("backlight max_brightness"))?;
+    let curve = backlight::Curve::new(max);
+    Ok(vec![write(&dev.join("brightness"), value.max(curve.floor).min(curve.max))])
+}
+
+/// Apply a sustained power limit through `ryzenadj`.
+pub fn set_tdp(watts: u32) -> Result<Vec<Op>, OpError> {
+    if !(MIN_TDP_W..=MAX_TDP_W).contains(&watts) {
+        return Err(OpError::invalid(format!(
+            "TDP must be between {MIN_TDP_W} and {MAX_TDP_W} W"
+        )));
+    }
+    Ok(vec![Op::Run {
+        program: "ryzenadj".into(),
+        args: vec!["--stapm-limit".into(), (watts * 1000).to_string()],
+        timeout: None,
+    }])
+}
+
+/// `auto` hands the fan to the EC, `manual` leaves the current PWM in place.
+pub fn set_fan_mode(root: &Path, mode: &str) -> Result<Vec<Op>, OpError> {
+    let base = find_hwmon(root, FAN_HWMON).ok_or_else(|| OpError::missing("fan control"))?;
+    let enable = match mode {
+        "auto" => 0,
+        "manual" => 1,
+        _ => return Err(OpError::invalid(format!("unknown fan mode {mode:?}"))),
+    };
+    Ok(vec![write(&base.join("pwm1_enable"), enable)])
+}
+
+pub fn set_fan_pwm(root: &Path, pwm: u8) -> Result<Vec<Op>, OpError> {
+    let base = find_hwmon(root, FAN_HWMON).ok_or_else(|| OpError::missing("fan control"))?;
+    Ok(vec![write(&base.join("pwm1_enable"), 1), write(&base.join("pwm1"), pwm)])
+}
+
+/// Set the joystick ring LEDs. Mode 0 is the firmware breathing effect,
+/// mode 1 shows `color` at `brightness`.
+pub fn set_rgb(root: &Path, mode: u8, brightness: u8, color: [u8; 3]) -> Result<Vec<Op>, OpError> {
+    let base = root.join("class/leds").join(RGB_LED);
+    if !base.exists() {
+        return Err(OpError::missing("RGB LEDs"));
+    }
+    if mode > 1 {
+        return Err(OpError::invalid(format!("unknown RGB mode {mode}")));
+    }
+    // Mode 0 is the firmware's breathing effect, which keeps its own colour.
+    if mode == 0 {
+        return Ok(vec![write(&base.join("led_mode"), mode)]);
+    }
+    let [r, g, b] = color;
+    Ok(vec![
+        write(&base.join("led_mode"), mode),
+        write(&base.join("brightness"), brightness),
+        write(&base.join("multi_intensity"), format!("{r} {g} {b}")),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::TempRoot;
+    use std::fs;
+
+    fn written(ops: &[Op]) -> &str {
+        match ops {
+            [Op::Write { value, .. }] => value,
+            _ => panic!("expected one write, got {ops:?}"),
+        }
+    }
+
+    #[test]
+    fn raw_brightness_is_clamped_to_the_floor_and_max() {
+        let sys = TempRoot::new("ops-backlight");
+        sys.write("class/backlight/panel0/max_brightness", "1000\n");
+        sys.write("class/backlight/panel0/brightness", "500\n");
+
+        let floor = backlight::Curve::new(1000).floor.to_string();
+        assert_eq!(written(&set_brightness(&sys.root, 0).unwrap()), floor);
+        assert_eq!(written(&set_brightness(&sys.root, 600).unwrap()), "600");
+        assert_eq!(written(&set_brightness(&sys.root, 5000).unwrap()), "1000");
+
+        fs::remove_file(sys.root.join("class/backlight/panel0/max_brightness")).unwrap();
+        assert_eq!(set_brightness(&sys.root, 600).unwrap_err().kind, ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn breathing_mode_writes_only_led_mode() {
+        let sys = TempRoot::new("ops-rgb");
+        let base = sys.dir(Path::new("class/leds").join(RGB_LED));
+        let writes = |ops: Vec<Op>| -> Vec<(String, String)> {
+            ops.into_iter()
+                .map(|op| match op {
+                    Op::Write { path, value } => {
+                        (Path::new(&path).strip_prefix(&base).unwrap().display().to_string(), value)
+                    }
+                    op => panic!("expected a write, got {op:?}"),
+                })
+                .collect()
+        };
+        let pair = |file: &str, value: &str| (file.to_string(), value.to_string());
+
+        let breathe = writes(set_rgb(&sys.root, 0, 255, [255, 0, 0]).unwrap());
+        assert_eq!(breathe, [pair("led_mode", "0")]);
+        let manual = writes(set_rgb(&sys.root, 1, 128, [1, 2, 3]).unwrap());
+        assert_eq!(
+            manual,
+            [pair("led_mode", "1"), pair("brightness", "128"), pair("multi_intensity", "1 2 3")]
+        );
+    }
+}
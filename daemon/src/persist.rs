@@ -0,0 +1,100 @@
+//! Settings files written in the background. Saving only queues the new
+//! contents; a writer task puts them on disk, so callers never block on I/O.
+//! Saves made while a write is in flight are coalesced into one write of the
+//! newest contents.
+
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+struct Shared {
+    path: PathBuf,
+    /// Contents not yet written, and whether a writer task is running.
+    queue: Mutex<(Option<Vec<u8>>, bool)>,
+}
+
+pub struct SettingsFile {
+    shared: Arc<Shared>,
+}
+
+impl SettingsFile {
+    pub fn new(path: PathBuf) -> SettingsFile {
+        SettingsFile { shared: Arc::new(Shared { path, queue: Mutex::new((None, false)) }) }
+    }
+
+    /// Read and parse the file, if there is a valid one.
+    pub fn load<T: serde::de::DeserializeOwned>(&self) -> Option<T> {
+        let text = std::fs::read_to_string(&self.shared.path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Queue `value` to be written. Must be called within the tokio runtime.
+    /// Callers queue while still holding the lock that guards `value`, so
+    /// saves land in the order the changes were made.
+    pub fn save(&self, value: &impl Serialize) {
+        let bytes = match serde_json::to_vec_pretty(value) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("failed to serialize {}: {e}", self.shared.path.display());
+                return;
+            }
+        };
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.0 = Some(bytes);
+        if queue.1 {
+            return;
+        }
+        queue.1 = true;
+        let shared = self.shared.clone();
+        tokio::spawn(async move {
+            loop {
+                let bytes = {
+                    let mut queue = shared.queue.lock().unwrap();
+                    match queue.0.take() {
+                        Some(bytes) => bytes,
+                        None => {
+                            queue.1 = false;
+                            return;
+                        }
+                    }
+                };
+                let result = async {
+                    if let Some(dir) = shared.path.parent() {
+                        tokio::fs::create_dir_all(dir).await?;
+                    }
+                    tokio::fs::write(&shared.path, bytes).await
+                }
+                .await;
+                if let Err(e) = result {
+                    eprintln!("failed to save {}: {e}", shared.path.display());
+                }
+            }
+        });
+    }
+
+    /// Wait until everything queued so far is on disk.
+    #[cfg(test)]
+    pub async fn flush(&self) {
+        while self.shared.queue.lock().unwrap().1 {
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::TempRoot;
+
+    #[tokio::test]
+    async fn writes_the_newest_contents() {
+        let tmp = TempRoot::new("persist");
+        let file = SettingsFile::new(tmp.root.join("state/settings.json"));
+        assert_eq!(file.load::<u32>(), None);
+        for n in 0..100u32 {
+            file.save(&n);
+        }
+        file.flush().await;
+        assert_eq!(file.load::<u32>(), Some(99));
+    }
+}
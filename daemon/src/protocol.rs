@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{audit, effects};
+
+/// Version of the socket protocol spoken by this daemon. Bump it whenever a
+/// request or response changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest client protocol version still accepted.
+pub const MIN_CLIENT_VERSION: u32 = 1;
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    /// Handshake: exchange protocol versions and learn the device's
+    /// capabilities. Clients should send this once before anything else.
+    Hello {
+        version: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client: Option<String>,
+    },
+    Write { path: String, value: String },
+    Run {
+        program: String,
+        args: Vec<String>,
+        /// Overrides the per-program default timeout, capped at
+        /// [`crate::runner::MAX_TIMEOUT`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+    History {
+        #[serde(flatten)]
+        query: audit::Query,
+    },
+    Stats,
+    /// Recorded sensor samples in a time range; see
+    /// [`crate::telemetry::Sample`].
+    Telemetry {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        until_ms: Option<u64>,
+    },
+    SetBrightness { value: u32 },
+    /// Fade to a perceptual percentage over `transition_ms`.
+    SetBrightnessPercent { percent: f64, transition_ms: Option<u64> },
+    SetAutoBrightness { enabled: bool },
+    /// Forget learned adjustments and go back to the default lux curve.
+    ResetAutoBrightness,
+    GetAutoBrightness,
+    SetTdp { watts: u32 },
+    SetFanMode { mode: String },
+    SetFanPwm { pwm: u8 },
+    /// Static colour or firmware breathing; stops any software effect.
+    SetRgb { mode: u8, brightness: u8, color: [u8; 3] },
+    /// Start, change or stop (`"effect": "off"`) a software effect. Omitted
+    /// fields keep their current value.
+    SetRgbEffect {
+        effect: Option<effects::Kind>,
+        speed: Option<f64>,
+        colors: Option<Vec<[u8; 3]>>,
+        brightness: Option<u8>,
+        fps: Option<u32>,
+    },
+    GetRgbEffect,
+    SetCpuGovernor { governor: String },
+    SetCpuEpp { preference: String },
+    SetCpuBoost { enabled: bool },
+    SetCpuFreqLimits { min_khz: u32, max_khz: u32 },
+    /// Soft-block or unblock radios of a kernel type (`wlan`, `bluetooth`,
+    /// ...) or `all`.
+    SetRfkill { kind: String, blocked: bool },
+    SetSmt { enabled: bool },
+    SetCpuOnline { cpu: u32, online: bool },
+    /// Keep this many logical CPUs online, lowest-numbered first.
+    SetOnlineCpus { count: u32 },
+    /// GPU requests act on `card` (e.g. `card0`), or the first amdgpu card.
+    SetGpuPerformanceLevel { level: String, card: Option<String> },
+    SetGpuPowerProfile { profile: String, card: Option<String> },
+    SetGpuClockLimits { min_mhz: u32, max_mhz: u32, card: Option<String> },
+    /// Read back the current hardware state; see [`crate::state::State`].
+    GetState,
+}
+
+impl Request {
+    /// Requests that only read state. Clients poll these, so they are kept
+    /// out of the audit log.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Request::Hello { .. }
+                | Request::History { .. }
+                | Request::Stats
+                | Request::Telemetry { .. }
+                | Request::GetAutoBrightness
+                | Request::GetRgbEffect
+                | Request::GetState
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct Hello {
+    pub version: u32,
+    pub min_client_version: u32,
+    pub capabilities: crate::caps::Capabilities,
+}
+
+/// Machine-readable classification of a failed request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    Timeout,
+    NonZeroExit,
+    InvalidArgument,
+    UnsupportedVersion,
+    Io,
+}
+
+impl From<std::io::ErrorKind> for ErrorKind {
+    fn from(kind: std::io::ErrorKind) -> ErrorKind {
+        match kind {
+            std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            std::io::ErrorKind::InvalidInput => ErrorKind::InvalidArgument,
+            _ => ErrorKind::Io,
+        }
+    }
+}
+
+/// Captured result of a `Run` request. Output streams are truncated to
+/// [`crate::runner::MAX_OUTPUT`] bytes each.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Output {
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Response {
+    pub success: bool,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<ErrorKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Output>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    /// Number of earlier requests for the same target that were folded into
+    /// the write that answered this one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merged: Option<u64>,
+}
+
+impl Response {
+    pub fn ok() -> Response {
+        Response {
+            success: true,
+            error: None,
+            error_kind: None,
+            output: None,
+            data: None,
+            merged: None,
+        }
+    }
+
+    pub fn err(msg: impl Into<String>) -> Response {
+        Response { success: false, error: Some(msg.into()), ..Response::ok() }
+    }
+
+    pub fn fail(kind: ErrorKind, msg: impl Into<String>) -> Response {
+        Response { error_kind: Some(kind), ..Response::err(msg) }
+    }
+
+    pub fn io_err(e: &std::io::Error) -> Response {
+        Response::fail(e.kind().into(), e.to_string())
+    }
+
+    pub fn with_data(data: impl Serialize) -> Response {
+        match serde_json::to_value(data) {
+            Ok(v) => Response { data: Some(v), ..Response::ok() },
+            Err(e) => Response::err(format!("serialize error: {e}")),
+        }
+    }
+}
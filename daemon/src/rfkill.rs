@@ -0,0 +1,64 @@
+//! Radio kill switches. Soft blocks are set explicitly through each device's
+//! `soft` attribute; hard blocks come from a physical switch or firmware and
+//! can only be reported.
+
+use serde::Serialize;
+use std::path::Path;
+
+use crate::ops::OpError;
+use crate::runner::Op;
+use crate::sysfs::{class_entries, read_parse, read_trimmed};
+
+/// Kernel radio types accepted by `set_rfkill`, plus `all`.
+pub const KINDS: &[&str] =
+    &["all", "wlan", "bluetooth", "ultrawideband", "wimax", "wwan", "gps", "fm", "nfc"];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Rfkill {
+    /// Index of `rfkillN`, as used in `/dev/rfkill` events.
+    pub index: u32,
+    pub name: String,
+    /// Kernel type, e.g. `wlan` or `bluetooth`.
+    pub kind: String,
+    pub soft_blocked: bool,
+    pub hard_blocked: bool,
+}
+
+pub fn read(root: &Path) -> Vec<Rfkill> {
+    class_entries(root, "rfkill")
+        .into_iter()
+        .filter_map(|dev| {
+            let index = dev.file_name()?.to_str()?.strip_prefix("rfkill")?.parse().ok()?;
+            Some(Rfkill {
+                index,
+                name: read_trimmed(dev.join("name"))?,
+                kind: read_trimmed(dev.join("type"))?,
+                soft_blocked: read_parse::<u8>(dev.join("soft"))? != 0,
+                hard_blocked: read_parse::<u8>(dev.join("hard"))? != 0,
+            })
+        })
+        .collect()
+}
+
+/// Soft-block or unblock every radio of `kind`. A hard-blocked radio keeps
+/// its soft state, so it comes up as requested once the switch is released.
+pub fn set_blocked(root: &Path, kind: &str, blocked: bool) -> Result<Vec<Op>, OpError> {
+    if !KINDS.contains(&kind) {
+        return Err(OpError::invalid(format!(
+            "unknown radio type {kind:?}; expected one of {}",
+            KINDS.join(", ")
+        )));
+    }
+    let ops: Vec<Op> = class_entries(root, "rfkill")
+        .into_iter()
+        .filter(|dev| kind == "all" || read_trimmed(dev.join("type")).as_deref() == Some(kind))
+        .map(|dev| Op::Write {
+            path: dev.join("soft").to_string_lossy().into_owned(),
+            value: u8::from(blocked).to_string(),
+        })
+        .collect();
+    if ops.is_empty() {
+        return Err(OpError::missing(&format!("{kind} radio")));
+    }
+    Ok(ops)
+}
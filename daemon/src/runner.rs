@@ -0,0 +1,265 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::task::JoinHandle;
+
+use crate::protocol::{ErrorKind, Output, Response};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Bytes of stdout/stderr kept per stream.
+pub const MAX_OUTPUT: usize = 16 * 1024;
+/// Upper bound for any client-supplied timeout.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to keep reading output once the child has exited or been killed.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Default timeouts for programs that are known to be quick.
+const PROGRAM_TIMEOUTS: &[(&str, Duration)] = &[
+    ("ryzenadj", Duration::from_secs(5)),
+    ("rfkill", Duration::from_secs(5)),
+];
+
+/// A privileged hardware operation.
+#[derive(Clone, Debug)]
+pub enum Op {
+    Write { path: String, value: String },
+    /// Write each value to `path` in turn, stopping at the first failure.
+    /// For command files such as `pp_od_clk_voltage`, where a later command
+    /// must not be separated from the earlier ones.
+    WriteSequence { path: String, values: Vec<String> },
+    Run { program: String, args: Vec<String>, timeout: Option<Duration> },
+}
+
+/// Performs [`Op`]s on behalf of clients. The daemon uses [`SystemRunner`];
+/// tests can substitute a runner that records operations instead.
+pub trait Runner: Send + Sync + 'static {
+    fn execute<'a>(&'a self, op: &'a Op) -> BoxFuture<'a, Response>;
+}
+
+pub struct SystemRunner;
+
+impl Runner for SystemRunner {
+    fn execute<'a>(&'a self, op: &'a Op) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            match op {
+                Op::Write { path, value } => write(path, value).await,
+                Op::WriteSequence { path, values } => {
+                    let mut resp = Response::ok();
+                    for value in values {
+                        resp = write(path, value).await;
+                        if !resp.success {
+                            break;
+                        }
+                    }
+                    resp
+                }
+                Op::Run { program, args, timeout } => run(program, args, *timeout).await,
+            }
+        })
+    }
+}
+
+pub fn program_name(program: &str) -> &str {
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+fn timeout_for(program: &str, requested: Option<Duration>) -> Duration {
+    requested
+        .or_else(|| {
+            PROGRAM_TIMEOUTS
+                .iter()
+                .find(|(p, _)| *p == program_name(program))
+                .map(|(_, t)| *t)
+        })
+        .unwrap_or(DEFAULT_TIMEOUT)
+        .min(MAX_TIMEOUT)
+}
+
+async fn write(path: &str, value: &str) -> Response {
+    if path.is_empty() || !path.starts_with('/') || path.contains('\0') {
+        return Response::fail(ErrorKind::InvalidArgument, format!("invalid path: {path:?}"));
+    }
+    match tokio::fs::write(path, value).await {
+        Ok(_) => Response::ok(),
+        Err(e) => Response::io_err(&e),
+    }
+}
+
+/// What has been read from one of the child's pipes so far.
+#[derive(Default)]
+struct Captured {
+    buf: Vec<u8>,
+    truncated: bool,
+}
+
+/// Read at most [`MAX_OUTPUT`] bytes into `captured`, draining the rest so
+/// the child never blocks on a full pipe.
+async fn read_bounded(reader: Option<impl AsyncRead + Unpin>, captured: Arc<Mutex<Captured>>) {
+    let Some(mut reader) = reader else {
+        return;
+    };
+    let mut chunk = [0u8; 4096];
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                let mut captured = captured.lock().unwrap();
+                let room = MAX_OUTPUT - captured.buf.len();
+                captured.truncated |= n > room;
+                captured.buf.extend_from_slice(&chunk[..n.min(room)]);
+            }
+        }
+    }
+}
+
+/// Spawn a reader for one pipe. The buffer outlives the task, so output read
+/// before it is given up on is kept.
+fn capture(
+    reader: Option<impl AsyncRead + Unpin + Send + 'static>,
+) -> (JoinHandle<()>, Arc<Mutex<Captured>>) {
+    let captured = Arc::new(Mutex::new(Captured::default()));
+    (tokio::spawn(read_bounded(reader, captured.clone())), captured)
+}
+
+/// Wait up to `limit` for a reader to reach end-of-file, then return what it
+/// captured. Output is marked truncated when the reader had to be stopped.
+async fn collect(
+    mut task: JoinHandle<()>,
+    captured: &Mutex<Captured>,
+    limit: Duration,
+) -> (String, bool) {
+    let finished = matches!(tokio::time::timeout(limit, &mut task).await, Ok(Ok(())));
+    if !finished {
+        task.abort();
+    }
+    let captured = captured.lock().unwrap();
+    (String::from_utf8_lossy(&captured.buf).into_owned(), captured.truncated || !finished)
+}
+
+pub async fn run(program: &str, args: &[String], timeout: Option<Duration>) -> Response {
+    if program.is_empty() || program.contains('\0') || args.iter().any(|a| a.contains('\0')) {
+        return Response::fail(ErrorKind::InvalidArgument, "invalid program or arguments");
+    }
+    let limit = timeout_for(program, timeout);
+    let mut child = match tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(c) => c,
+        Err(e) => return Response::io_err(&e),
+    };
+    let pid = child.id();
+    let (stdout_task, stdout) = capture(child.stdout.take());
+    let (stderr_task, stderr) = capture(child.stderr.take());
+
+    let status = tokio::time::timeout(limit, child.wait()).await;
+    if status.is_err() {
+        // The child leads its own process group; take down anything it spawned too.
+        if let Some(pid) = pid {
+            // SAFETY: kill(2) takes plain integers and touches no memory. The
+            // group still exists: its leader is our unreaped child.
+            unsafe {
+                libc::kill(-(pid as i32), libc::SIGKILL);
+            }
+        }
+        let _ = child.kill().await;
+    }
+    // A descendant that left the process group can hold the pipes open, so
+    // don't wait for end-of-file indefinitely. Whatever was read by then is
+    // still reported.
+    let (stdout, out_trunc) = collect(stdout_task, &stdout, DRAIN_TIMEOUT).await;
+    let (stderr, err_trunc) = collect(stderr_task, &stderr, DRAIN_TIMEOUT).await;
+    let mut output = Output {
+        exit_code: None,
+        stdout,
+        stderr,
+        truncated: out_trunc || err_trunc,
+    };
+
+    let mut resp = match status {
+        Err(_) => Response::fail(
+            ErrorKind::Timeout,
+            format!("{program} timed out after {} ms", limit.as_millis()),
+        ),
+        Ok(Err(e)) => Response::io_err(&e),
+        Ok(Ok(status)) => {
+            output.exit_code = status.code();
+            if status.success() {
+                Response::ok()
+            } else {
+                Response::fail(ErrorKind::NonZeroExit, format!("{program} failed: {status}"))
+            }
+        }
+    };
+    resp.output = Some(output);
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn sh(script: &str) -> Vec<String> {
+        vec!["-c".into(), script.into()]
+    }
+
+    /// Dead or waiting to be reaped.
+    fn gone(pid: i32) -> bool {
+        match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+            Ok(stat) => stat.rsplit(')').next().is_some_and(|s| s.trim_start().starts_with('Z')),
+            Err(_) => true,
+        }
+    }
+
+    #[tokio::test]
+    async fn timeouts_kill_the_whole_process_group() {
+        let started = Instant::now();
+        let resp =
+            run("sh", &sh("sleep 5 & echo $!; wait"), Some(Duration::from_millis(200))).await;
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(resp.error_kind, Some(ErrorKind::Timeout));
+        let output = resp.output.unwrap();
+        assert_eq!(output.exit_code, None);
+
+        // The background sleep went down with the shell.
+        let sleep: i32 = output.stdout.trim().parse().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !gone(sleep) && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(gone(sleep), "sleep {sleep} survived the timeout");
+    }
+
+    #[tokio::test]
+    async fn keeps_output_read_before_giving_up_on_the_pipes() {
+        // The escaped sleep holds stdout open after the group is killed.
+        let script = "echo partial; echo oops >&2; setsid sleep 5 & echo $!; wait";
+        let started = Instant::now();
+        let resp = run("sh", &sh(script), Some(Duration::from_millis(200))).await;
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(resp.error_kind, Some(ErrorKind::Timeout));
+        let output = resp.output.unwrap();
+        let mut lines = output.stdout.lines();
+        assert_eq!(lines.next(), Some("partial"));
+        assert_eq!(output.stderr, "oops\n");
+        assert!(output.truncated);
+
+        if let Some(escaped) = lines.next().and_then(|l| l.parse::<i32>().ok()) {
+            // SAFETY: kill(2) takes plain integers and touches no memory.
+            unsafe {
+                libc::kill(escaped, libc::SIGKILL);
+            }
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::backlight::Curve;
+use crate::cores::{self, CpuCores};
+use crate::cpufreq::{self, CpuFreq};
+use crate::gpu::{self, Gpu};
+use crate::rfkill::{self, Rfkill};
+use crate::runner;
+use crate::sysfs::{class_entries, find_hwmon, read_parse, read_trimmed};
+
+pub const FAN_HWMON: &str = "aynec";
+pub const RGB_LED: &str = "ayn:rgb:joystick_rings";
+/// How long a TDP reading is reused.
+const TDP_TTL: Duration = Duration::from_secs(10);
+
+/// Snapshot of everything the control panel displays. Sections are `None`
+/// when the hardware is absent.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct State {
+    pub backlight: Option<Backlight>,
+    pub fan: Option<Fan>,
+    pub temperatures: Vec<Temperature>,
+    pub tdp: Option<Tdp>,
+    pub rgb: Option<Rgb>,
+    pub rfkill: Vec<Rfkill>,
+    pub battery: Option<Battery>,
+    pub clocks: Clocks,
+    pub cpufreq: Option<CpuFreq>,
+    pub cores: Option<CpuCores>,
+    pub gpus: Vec<Gpu>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Backlight {
+    pub device: String,
+    /// `firmware`, `platform` or `raw`.
+    pub kind: Option<String>,
+    /// Last requested level.
+    pub brightness: u32,
+    /// Level reported by the hardware, which can lag or differ.
+    pub actual_brightness: Option<u32>,
+    pub max_brightness: u32,
+    /// `brightness` on the perceptual 0-100 scale used by
+    /// `set_brightness_percent`.
+    pub percent: f64,
+    /// Raw level that 0% maps to.
+    pub min_brightness: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Fan {
+    /// `auto` when the EC controls the fan, `manual` otherwise.
+    pub mode: String,
+    pub pwm: Option<u8>,
+    pub rpm: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Temperature {
+    /// hwmon device name, e.g. `k10temp`.
+    pub sensor: String,
+    pub label: String,
+    pub celsius: f32,
+}
+
+/// Limits and current value reported by `ryzenadj --info`, in watts.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Tdp {
+    pub stapm_limit: Option<f32>,
+    pub stapm_value: Option<f32>,
+    pub fast_limit: Option<f32>,
+    pub slow_limit: Option<f32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Rgb {
+    pub mode: Option<u8>,
+    pub brightness: Option<u8>,
+    pub color: Option<[u8; 3]>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Battery {
+    pub name: String,
+    pub capacity: Option<u8>,
+    pub status: Option<String>,
+    /// Instantaneous power draw in watts; positive while discharging.
+    pub power_w: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Clocks {
+    pub cpus: Vec<CpuClock>,
+    pub gpus: Vec<GpuClock>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CpuClock {
+    pub cpu: u32,
+    pub mhz: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GpuClock {
+    /// DRM card name, e.g. `card0`.
+    pub card: String,
+    /// Current shader clock from the active `pp_dpm_sclk` level.
+    pub mhz: u32,
+}
+
+pub fn backlight(root: &Path) -> Option<Backlight> {
+    let dev = crate::backlight::device(root)?;
+    let actual_brightness = read_parse(dev.join("actual_brightness"));
+    let brightness = read_parse(dev.join("brightness")).or(actual_brightness)?;
+    let curve = Curve::new(read_parse(dev.join("max_brightness"))?);
+    Some(Backlight {
+        device: dev.file_name()?.to_string_lossy().into_owned(),
+        kind: read_trimmed(dev.join("type")),
+        brightness,
+        actual_brightness,
+        max_brightness: curve.max,
+        percent: curve.percent(brightness),
+        min_brightness: curve.floor,
+    })
+}
+
+pub fn fan(root: &Path) -> Option<Fan> {
+    let base = find_hwmon(root, FAN_HWMON)?;
+    let enable: Option<u8> = read_parse(base.join("pwm1_enable"));
+    Some(Fan {
+        mode: if enable == Some(0) { "auto" } else { "manual" }.to_string(),
+        pwm: read_parse(base.join("pwm1")),
+        rpm: read_parse(base.join("fan1_input")),
+    })
+}
+
+pub fn temperatures(root: &Path) -> Vec<Temperature> {
+    let mut out = Vec::new();
+    for hwmon in class_entries(root, "hwmon") {
+        let sensor = read_trimmed(hwmon.join("name")).unwrap_or_default();
+        for idx in 1..=16 {
+            let Some(milli) = read_parse::<f32>(hwmon.join(format!("temp{idx}_input"))) else {
+                continue;
+            };
+            let label = read_trimmed(hwmon.join(format!("temp{idx}_label")))
+                .unwrap_or_else(|| format!("temp{idx}"));
+            out.push(Temperature { sensor: sensor.clone(), label, celsius: milli / 1000.0 });
+        }
+    }
+    out
+}
+
+/// Parse the table printed by `ryzenadj --info`.
+pub fn parse_ryzenadj_info(text: &str) -> Tdp {
+    let mut tdp = Tdp::default();
+    for line in text.lines() {
+        let mut cols = line.split('|').map(str::trim).filter(|c| !c.is_empty());
+        let (Some(name), Some(value)) = (cols.next(), cols.next()) else {
+            continue;
+        };
+        let Ok(value) = value.parse::<f32>() else {
+            continue;
+        };
+        match name {
+            "STAPM LIMIT" => tdp.stapm_limit = Some(value),
+            "STAPM VALUE" => tdp.stapm_value = Some(value),
+            "PPT LIMIT FAST" => tdp.fast_limit = Some(value),
+            "PPT LIMIT SLOW" => tdp.slow_limit = Some(value),
+            _ => {}
+        }
+    }
+    tdp
+}
+
+/// Last `ryzenadj --info` reading and when it was taken. Every collector
+/// shares it, so polling doesn't spawn a root process each time.
+static TDP_CACHE: Mutex<Option<(Instant, Option<Tdp>)>> = Mutex::const_new(None);
+
+/// The TDP reading, at most [`TDP_TTL`] old.
+pub async fn tdp() -> Option<Tdp> {
+    let mut cache = TDP_CACHE.lock().await;
+    if let Some((at, tdp)) = cache.as_ref() {
+        if at.elapsed() < TDP_TTL {
+            return tdp.clone();
+        }
+    }
+    let tdp = read_tdp().await;
+    *cache = Some((Instant::now(), tdp.clone()));
+    tdp
+}
+
+/// Make the next [`tdp`] call read the hardware, after the limits change.
+pub async fn invalidate_tdp() {
+    TDP_CACHE.lock().await.take();
+}
+
+async fn read_tdp() -> Option<Tdp> {
+    let resp = runner::run("ryzenadj", &["--info".to_string()], Some(Duration::from_secs(2))).await;
+    if !resp.success {
+        return None;
+    }
+    Some(parse_ryzenadj_info(&resp.output?.stdout))
+}
+
+pub fn rgb(root: &Path) -> Option<Rgb> {
+    let base = root.join("class/leds").join(RGB_LED);
+    if !base.exists() {
+        return None;
+    }
+    let color = read_trimmed(base.join("multi_intensity")).and_then(|s| {
+        let v: Vec<u8> = s.split_whitespace().filter_map(|c| c.parse().ok()).collect();
+        <[u8; 3]>::try_from(v).ok()
+    });
+    Some(Rgb {
+        mode: read_parse(base.join("led_mode")),
+        brightness: read_parse(base.join("brightness")),
+        color,
+    })
+}
+
+pub fn battery(root: &Path) -> Option<Battery> {
+    let dev = class_entries(root, "power_supply")
+        .into_iter()
+        .find(|p| read_trimmed(p.join("type")).as_deref() == Some("Battery"))?;
+    let power_uw = read_parse::<f32>(dev.join("power_now")).or_else(|| {
+        let ua = read_parse::<f32>(dev.join("current_now"))?;
+        let uv = read_parse::<f32>(dev.join("voltage_now"))?;
+        Some(ua * uv / 1e6)
+    });
+    let status = read_trimmed(dev.join("status"));
+    let sign = if status.as_deref() == Some("Charging") { -1.0 } else { 1.0 };
+    Some(Battery {
+        name: dev.file_name()?.to_string_lossy().into_owned(),
+        capacity: read_parse(dev.join("capacity")),
+        status,
+        power_w: power_uw.map(|uw| sign * uw.abs() / 1e6),
+    })
+}
+
+/// The level marked with `*` in a `pp_dpm_*` table such as
+/// `1: 1600Mhz *`.
+pub fn parse_active_dpm_level(text: &str) -> Option<u32> {
+    let line = text.lines().find(|l| l.trim_end().ends_with('*'))?;
+    let value = line.split(':').nth(1)?.split_whitespace().next()?;
+    value.to_ascii_lowercase().trim_end_matches("mhz").parse().ok()
+}
+
+/// Indices of the `cpuN` directories under `devices/system/cpu`, sorted.
+pub fn cpu_indices(root: &Path) -> Vec<u32> {
+    let mut cpus: Vec<u32> = std::fs::read_dir(root.join("devices/system/cpu"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.strip_prefix("cpu")?.parse().ok())
+        .collect();
+    cpus.sort_unstable();
+    cpus
+}
+
+/// DRM cards (not connectors) backed by amdgpu power management.
+pub fn amdgpu_cards(root: &Path) -> Vec<std::path::PathBuf> {
+    class_entries(root, "drm")
+        .into_iter()
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix("card"))
+                .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+        })
+        .filter(|p| p.join("device/power_dpm_force_performance_level").exists())
+        .collect()
+}
+
+pub fn clocks(root: &Path) -> Clocks {
+    let cpus = cpu_indices(root)
+        .into_iter()
+        .filter_map(|cpu| {
+            let khz: u32 = read_parse(
+                root.join(format!("devices/system/cpu/cpu{cpu}/cpufreq/scaling_cur_freq")),
+            )?;
+            Some(CpuClock { cpu, mhz: khz / 1000 })
+        })
+        .collect();
+    let gpus = amdgpu_cards(root)
+        .into_iter()
+        .filter_map(|card| {
+            let mhz = parse_active_dpm_level(&read_trimmed(card.join("device/pp_dpm_sclk"))?)?;
+            Some(GpuClock { card: card.file_name()?.to_string_lossy().into_owned(), mhz })
+        })
+        .collect();
+    Clocks { cpus, gpus }
+}
+
+pub async fn collect(root: &Path) -> State {
+    State {
+        backlight: backlight(root),
+        fan: fan(root),
+        temperatures: temperatures(root),
+        tdp: tdp().await,
+        rgb: rgb(root),
+        rfkill: rfkill::read(root),
+        battery: battery(root),
+        clocks: clocks(root),
+        cpufreq: cpufreq::read(root),
+        cores: cores::read(root),
+        gpus: gpu::read(root),
+    }
+}
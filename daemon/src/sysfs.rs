@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Root of the sysfs tree. Overridable with `LOKI_SYSFS_ROOT` so the daemon
+/// can be pointed at a fake tree.
+pub fn root() -> PathBuf {
+    std::env::var_os("LOKI_SYSFS_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/sys"))
+}
+
+pub fn read_trimmed(path: impl AsRef<Path>) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+pub fn read_parse<T: FromStr>(path: impl AsRef<Path>) -> Option<T> {
+    read_trimmed(path)?.parse().ok()
+}
+
+/// Entries of a sysfs class directory such as `class/hwmon`, sorted by name
+/// so results are stable across calls.
+pub fn class_entries(root: &Path, class: &str) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(root.join("class").join(class)) {
+        Ok(it) => it.flatten().map(|e| e.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort();
+    entries
+}
+
+/// Find the hwmon directory whose `name` attribute equals `name`.
+pub fn find_hwmon(root: &Path, name: &str) -> Option<PathBuf> {
+    class_entries(root, "hwmon")
+        .into_iter()
+        .find(|p| read_trimmed(p.join("name")).as_deref() == Some(name))
+}
@@ -0,0 +1,181 @@
+//! Periodic sensor sampling into an in-memory ring buffer, optionally
+//! mirrored to a JSON-lines file so history survives restarts.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use crate::audit::now_ms;
+use crate::state::{self, State};
+use crate::sysfs;
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+/// One hour at the default interval.
+pub const DEFAULT_CAPACITY: usize = 1800;
+const MAX_LOG_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Sample {
+    pub ts_ms: u64,
+    pub cpu_temp_c: Option<f32>,
+    pub fan_rpm: Option<u32>,
+    pub fan_pwm: Option<u8>,
+    pub package_power_w: Option<f32>,
+    pub tdp_limit_w: Option<f32>,
+    pub battery_percent: Option<u8>,
+    pub battery_power_w: Option<f32>,
+    pub cpu_mhz: Option<u32>,
+    pub gpu_mhz: Option<u32>,
+}
+
+/// The CPU package temperature: `k10temp`'s `Tctl` when present, otherwise
+/// the hottest sensor.
+pub fn cpu_temperature(state: &State) -> Option<f32> {
+    state
+        .temperatures
+        .iter()
+        .find(|t| t.sensor == "k10temp" && t.label == "Tctl")
+        .or_else(|| {
+            state
+                .temperatures
+                .iter()
+                .max_by(|a, b| a.celsius.total_cmp(&b.celsius))
+        })
+        .map(|t| t.celsius)
+}
+
+impl Sample {
+    pub fn from_state(ts_ms: u64, state: &State) -> Sample {
+        let cpus = &state.clocks.cpus;
+        let cpu_mhz = (!cpus.is_empty())
+            .then(|| cpus.iter().map(|c| c.mhz).sum::<u32>() / cpus.len() as u32);
+        Sample {
+            ts_ms,
+            cpu_temp_c: cpu_temperature(state),
+            fan_rpm: state.fan.as_ref().and_then(|f| f.rpm),
+            fan_pwm: state.fan.as_ref().and_then(|f| f.pwm),
+            package_power_w: state.tdp.as_ref().and_then(|t| t.stapm_value),
+            tdp_limit_w: state.tdp.as_ref().and_then(|t| t.stapm_limit),
+            battery_percent: state.battery.as_ref().and_then(|b| b.capacity),
+            battery_power_w: state.battery.as_ref().and_then(|b| b.power_w),
+            cpu_mhz,
+            gpu_mhz: state.clocks.gpus.first().map(|g| g.mhz),
+        }
+    }
+}
+
+pub struct Recorder {
+    samples: Mutex<VecDeque<Sample>>,
+    capacity: usize,
+    log: Option<PathBuf>,
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+impl Recorder {
+    pub fn new(capacity: usize, log: Option<PathBuf>) -> Recorder {
+        Recorder { samples: Mutex::new(VecDeque::with_capacity(capacity)), capacity, log }
+    }
+
+    /// Build a recorder from `LOKI_TELEMETRY_CAPACITY` and
+    /// `LOKI_TELEMETRY_LOG`.
+    pub fn from_env() -> Recorder {
+        let capacity = env_or("LOKI_TELEMETRY_CAPACITY", DEFAULT_CAPACITY).max(1);
+        let log = std::env::var_os("LOKI_TELEMETRY_LOG").map(PathBuf::from);
+        Recorder::new(capacity, log)
+    }
+
+    /// Sampling period from `LOKI_TELEMETRY_INTERVAL_MS`.
+    pub fn interval_from_env() -> Duration {
+        Duration::from_millis(env_or(
+            "LOKI_TELEMETRY_INTERVAL_MS",
+            DEFAULT_INTERVAL.as_millis() as u64,
+        ))
+        .max(Duration::from_millis(100))
+    }
+
+    pub async fn record(&self, sample: Sample) {
+        {
+            let mut samples = self.samples.lock().unwrap();
+            if samples.len() == self.capacity {
+                samples.pop_front();
+            }
+            samples.push_back(sample.clone());
+        }
+        if let Some(path) = &self.log {
+            if let Err(e) = append_log(path, &sample).await {
+                eprintln!("failed to write {}: {e}", path.display());
+            }
+        }
+    }
+
+    /// Samples with `since_ms <= ts_ms <= until_ms`, oldest first. Ranges
+    /// reaching past the ring buffer are filled from the on-disk log.
+    pub async fn query(&self, since_ms: Option<u64>, until_ms: Option<u64>) -> Vec<Sample> {
+        let in_range = |s: &Sample| {
+            since_ms.is_none_or(|t| s.ts_ms >= t) && until_ms.is_none_or(|t| s.ts_ms <= t)
+        };
+        let (oldest, recent): (Option<u64>, Vec<Sample>) = {
+            let samples = self.samples.lock().unwrap();
+            (
+                samples.front().map(|s| s.ts_ms),
+                samples.iter().filter(|s| in_range(s)).cloned().collect(),
+            )
+        };
+        let mut out = Vec::new();
+        let needs_disk = match (since_ms, oldest) {
+            (Some(since), Some(oldest)) => since < oldest,
+            _ => true,
+        };
+        if let (true, Some(path)) = (needs_disk, &self.log) {
+            let mut older_file = path.as_os_str().to_owned();
+            older_file.push(".1");
+            for file in [PathBuf::from(older_file), path.clone()] {
+                let Ok(text) = tokio::fs::read_to_string(&file).await else {
+                    continue;
+                };
+                out.extend(
+                    text.lines()
+                        .filter_map(|l| serde_json::from_str::<Sample>(l).ok())
+                        .filter(|s| in_range(s) && oldest.is_none_or(|o| s.ts_ms < o)),
+                );
+            }
+        }
+        out.extend(recent);
+        out
+    }
+
+    /// Sample the hardware every `interval` for the life of the daemon.
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let root = sysfs::root();
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let state = state::collect(&root).await;
+            self.record(Sample::from_state(now_ms(), &state)).await;
+        }
+    }
+}
+
+async fn append_log(path: &Path, sample: &Sample) -> std::io::Result<()> {
+    if tokio::fs::metadata(path).await.is_ok_and(|m| m.len() >= MAX_LOG_BYTES) {
+        let mut older = path.as_os_str().to_owned();
+        older.push(".1");
+        tokio::fs::rename(path, PathBuf::from(older)).await?;
+    }
+    let line = serde_json::to_string(sample)? + "\n";
+    let mut f = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    f.write_all(line.as_bytes()).await
+}
@@ -0,0 +1,46 @@
+//! Throwaway directory trees for tests that read or write fake sysfs files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh directory under the system temp dir, removed on drop.
+pub struct TempRoot {
+    pub root: PathBuf,
+}
+
+impl TempRoot {
+    pub fn new(name: &str) -> TempRoot {
+        let n = NEXT.fetch_add(1, Ordering::SeqCst);
+        let root =
+            std::env::temp_dir().join(format!("loki-test-{}-{n}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        TempRoot { root }
+    }
+
+    /// Create `dir` and its parents under the root.
+    pub fn dir(&self, dir: impl AsRef<Path>) -> PathBuf {
+        let path = self.root.join(dir);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// Write `contents` to `file` under the root, creating its directory.
+    pub fn write(&self, file: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.root.join(file);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempRoot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,641 @@
+//! Audio through the sound server. A background thread keeps a native
+//! protocol connection open and mirrors the server's devices and streams
+//! through subscription events; the panel sends volume, mute, default
+//! device and stream moves over the same connection without spawning
+//! anything.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::pulse::{self, command, prop, subscription as sub, Connection, TagReader};
+
+const CLIENT_NAME: &str = "Loki Control Center";
+/// How long the worker waits for events before looking at queued requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Volume as pactl shows it: the loudest channel in percent.
+fn percent(volume: &[u32]) -> f64 {
+    let max = volume.iter().copied().max().unwrap_or(0);
+    max as f64 * 100.0 / pulse::VOLUME_NORM as f64
+}
+
+/// A sink (output) or source (input).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    pub index: u32,
+    pub name: String,
+    /// Human-readable name, e.g. "Built-in Audio Analog Stereo".
+    pub description: String,
+    /// Per-channel volume, [`pulse::VOLUME_NORM`] being 100%.
+    pub volume: Vec<u32>,
+    pub muted: bool,
+    /// A sink's monitor, which is listed as a source but is not a
+    /// microphone.
+    pub monitor: bool,
+}
+
+impl Device {
+    pub fn percent(&self) -> f64 {
+        percent(&self.volume)
+    }
+}
+
+/// A playback stream (sink input) or recording stream (source output).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stream {
+    pub index: u32,
+    /// Application name, or the stream's own name when it has none.
+    pub name: String,
+    /// Index of the sink or source it is connected to.
+    pub device: u32,
+    pub volume: Vec<u32>,
+    pub muted: bool,
+    /// Passthrough streams have a fixed volume.
+    pub volume_writable: bool,
+}
+
+impl Stream {
+    pub fn percent(&self) -> f64 {
+        percent(&self.volume)
+    }
+}
+
+/// `application.name`, falling back to the stream name.
+fn stream_name(props: &[(String, Vec<u8>)], stream: Option<String>) -> String {
+    prop(props, "application.name")
+        .map(str::to_string)
+        .or(stream)
+        .unwrap_or_default()
+}
+
+/// Everything the panel shows, as last reported by the server.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mixer {
+    pub default_sink: Option<String>,
+    pub default_source: Option<String>,
+    pub sinks: Vec<Device>,
+    pub sources: Vec<Device>,
+    pub sink_inputs: Vec<Stream>,
+    pub source_outputs: Vec<Stream>,
+}
+
+impl Mixer {
+    pub fn default_sink(&self) -> Option<&Device> {
+        let name = self.default_sink.as_deref()?;
+        self.sinks.iter().find(|d| d.name == name)
+    }
+
+    pub fn default_source(&self) -> Option<&Device> {
+        let name = self.default_source.as_deref()?;
+        self.sources.iter().find(|d| d.name == name)
+    }
+
+    /// Sources that are real inputs rather than sink monitors.
+    pub fn inputs(&self) -> impl Iterator<Item = &Device> {
+        self.sources.iter().filter(|d| !d.monitor)
+    }
+}
+
+/// `volume` rescaled so its loudest channel sits at `percent`, keeping the
+/// balance between channels.
+pub fn scaled(volume: &[u32], percent: f64) -> Vec<u32> {
+    let target = (percent.max(0.0) / 100.0 * pulse::VOLUME_NORM as f64).round() as u64;
+    let max = volume.iter().copied().max().unwrap_or(0) as u64;
+    if max == 0 {
+        return vec![target as u32; volume.len().max(1)];
+    }
+    volume.iter().map(|&v| (v as u64 * target / max) as u32).collect()
+}
+
+/// Device ports, which follow the flags of sink and source info.
+fn skip_ports(r: &mut TagReader, version: u32) -> io::Result<()> {
+    if version >= 16 {
+        let n = r.u32()?;
+        for _ in 0..n {
+            r.string()?;
+            r.string()?;
+            r.u32()?;
+            if version >= 24 {
+                r.u32()?;
+            }
+        }
+        // active port
+        r.string()?;
+    }
+    Ok(())
+}
+
+/// One sink or source from an info reply. Both share a layout up to the
+/// formats, which sources only list from version 22.
+fn read_device(r: &mut TagReader, version: u32, source: bool) -> io::Result<Device> {
+    let index = r.u32()?;
+    let name = r.string()?.unwrap_or_default();
+    let description = r.string()?.unwrap_or_default();
+    r.sample_spec()?;
+    r.channel_map()?;
+    let _owner_module = r.u32()?;
+    let volume = r.cvolume()?;
+    let muted = r.bool()?;
+    // A sink's monitor source, or the sink a source monitors.
+    let monitor_link = r.u32()?;
+    r.string()?;
+    r.usec()?;
+    let _driver = r.string()?;
+    let _flags = r.u32()?;
+    if version >= 13 {
+        r.proplist()?;
+        r.usec()?;
+    }
+    if version >= 15 {
+        r.volume()?;
+        let _state = r.u32()?;
+        let _volume_steps = r.u32()?;
+        let _card = r.u32()?;
+    }
+    skip_ports(r, version)?;
+    if version >= if source { 22 } else { 21 } {
+        let n = r.u8()?;
+        for _ in 0..n {
+            r.format_info()?;
+        }
+    }
+    let monitor = source && monitor_link != pulse::INVALID_INDEX;
+    Ok(Device { index, name, description, volume, muted, monitor })
+}
+
+fn read_sink_input(r: &mut TagReader, version: u32) -> io::Result<Stream> {
+    let index = r.u32()?;
+    let stream = r.string()?;
+    let _owner_module = r.u32()?;
+    let _client = r.u32()?;
+    let device = r.u32()?;
+    r.sample_spec()?;
+    r.channel_map()?;
+    let volume = r.cvolume()?;
+    r.usec()?;
+    r.usec()?;
+    r.string()?;
+    r.string()?;
+    let muted = if version >= 11 { r.bool()? } else { false };
+    let props = if version >= 13 { r.proplist()? } else { Vec::new() };
+    if version >= 19 {
+        let _corked = r.bool()?;
+    }
+    let mut volume_writable = true;
+    if version >= 20 {
+        let has_volume = r.bool()?;
+        volume_writable = has_volume && r.bool()?;
+    }
+    if version >= 21 {
+        r.format_info()?;
+    }
+    let name = stream_name(&props, stream);
+    Ok(Stream { index, name, device, volume, muted, volume_writable })
+}
+
+fn read_source_output(r: &mut TagReader, version: u32) -> io::Result<Stream> {
+    let index = r.u32()?;
+    let stream = r.string()?;
+    let _owner_module = r.u32()?;
+    let _client = r.u32()?;
+    let device = r.u32()?;
+    r.sample_spec()?;
+    r.channel_map()?;
+    r.usec()?;
+    r.usec()?;
+    r.string()?;
+    r.string()?;
+    let props = if version >= 13 { r.proplist()? } else { Vec::new() };
+    if version >= 19 {
+        let _corked = r.bool()?;
+    }
+    let (mut volume, mut muted, mut volume_writable) = (Vec::new(), false, false);
+    if version >= 22 {
+        volume = r.cvolume()?;
+        muted = r.bool()?;
+        let has_volume = r.bool()?;
+        volume_writable = has_volume && r.bool()?;
+        r.format_info()?;
+    }
+    let name = stream_name(&props, stream);
+    Ok(Stream { index, name, device, volume, muted, volume_writable })
+}
+
+/// Default sink and source names from a `GET_SERVER_INFO` reply.
+fn read_defaults(r: &mut TagReader) -> io::Result<(Option<String>, Option<String>)> {
+    for _ in 0..4 {
+        // package name and version, user and host name
+        r.string()?;
+    }
+    r.sample_spec()?;
+    Ok((r.string()?, r.string()?))
+}
+
+#[derive(Debug)]
+enum Request {
+    Volume(f64),
+    Mute(bool),
+    SourceMute(bool),
+    DefaultSink(String),
+    DefaultSource(String),
+    /// Volume of a playback stream, by index.
+    StreamVolume(u32, f64),
+    StreamMute(u32, bool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Facility {
+    Sink,
+    Source,
+    SinkInput,
+    SourceOutput,
+}
+
+impl Facility {
+    fn from_event(event: u32) -> Option<Facility> {
+        match event & sub::FACILITY_MASK {
+            sub::FACILITY_SINK => Some(Facility::Sink),
+            sub::FACILITY_SOURCE => Some(Facility::Source),
+            sub::FACILITY_SINK_INPUT => Some(Facility::SinkInput),
+            sub::FACILITY_SOURCE_OUTPUT => Some(Facility::SourceOutput),
+            _ => None,
+        }
+    }
+
+    /// Commands to fetch one object by index, and all of them.
+    fn info_commands(self) -> (u32, u32) {
+        match self {
+            Facility::Sink => (command::GET_SINK_INFO, command::GET_SINK_INFO_LIST),
+            Facility::Source => (command::GET_SOURCE_INFO, command::GET_SOURCE_INFO_LIST),
+            Facility::SinkInput => {
+                (command::GET_SINK_INPUT_INFO, command::GET_SINK_INPUT_INFO_LIST)
+            }
+            Facility::SourceOutput => {
+                (command::GET_SOURCE_OUTPUT_INFO, command::GET_SOURCE_OUTPUT_INFO_LIST)
+            }
+        }
+    }
+}
+
+/// What a pending reply is for.
+enum Pending {
+    ServerInfo,
+    /// Info on one object, or the full list when `list` is set.
+    Info { facility: Facility, list: bool },
+    Ack,
+}
+
+/// Replace or insert `item` keeping `items` ordered by index.
+fn upsert<T>(items: &mut Vec<T>, item: T, index: impl Fn(&T) -> u32) {
+    match items.binary_search_by_key(&index(&item), &index) {
+        Ok(i) => items[i] = item,
+        Err(i) => items.insert(i, item),
+    }
+}
+
+/// Handle on the audio thread.
+pub struct Audio {
+    requests: Sender<Request>,
+    /// Latest mixer state, set whenever it changes and taken by the UI.
+    /// `Some(None)` means there is no sound server.
+    latest: Arc<Mutex<Option<Option<Mixer>>>>,
+}
+
+impl Audio {
+    pub fn spawn() -> Audio {
+        let (requests, rx) = mpsc::channel();
+        let latest = Arc::new(Mutex::new(None));
+        let shared = latest.clone();
+        std::thread::spawn(move || loop {
+            match session(&rx, &shared) {
+                Ok(()) => return,
+                Err(e) => eprintln!("Sound server connection: {}", e),
+            }
+            *shared.lock().unwrap() = Some(None);
+            std::thread::sleep(RECONNECT_DELAY);
+        });
+        Audio { requests, latest }
+    }
+
+    /// The mixer if it changed since the last call.
+    pub fn take_update(&self) -> Option<Option<Mixer>> {
+        self.latest.lock().unwrap().take()
+    }
+
+    /// Volume of the default sink.
+    pub fn set_volume(&self, percent: f64) {
+        let _ = self.requests.send(Request::Volume(percent));
+    }
+
+    pub fn set_mute(&self, muted: bool) {
+        let _ = self.requests.send(Request::Mute(muted));
+    }
+
+    /// Mute the default source, normally the microphone.
+    pub fn set_source_mute(&self, muted: bool) {
+        let _ = self.requests.send(Request::SourceMute(muted));
+    }
+
+    /// Make `name` the default sink and move playing streams to it.
+    pub fn set_default_sink(&self, name: &str) {
+        let _ = self.requests.send(Request::DefaultSink(name.to_string()));
+    }
+
+    /// Volume of one playback stream.
+    pub fn set_stream_volume(&self, index: u32, percent: f64) {
+        let _ = self.requests.send(Request::StreamVolume(index, percent));
+    }
+
+    pub fn set_stream_mute(&self, index: u32, muted: bool) {
+        let _ = self.requests.send(Request::StreamMute(index, muted));
+    }
+
+    /// Make `name` the default source and move recording streams to it.
+    pub fn set_default_source(&self, name: &str) {
+        let _ = self.requests.send(Request::DefaultSource(name.to_string()));
+    }
+}
+
+struct Session<'a> {
+    conn: Connection,
+    pending: HashMap<u32, Pending>,
+    mixer: Mixer,
+    /// What the UI last got.
+    published: Option<Mixer>,
+    latest: &'a Mutex<Option<Option<Mixer>>>,
+}
+
+impl Session<'_> {
+    fn request(
+        &mut self,
+        cmd: u32,
+        kind: Pending,
+        args: impl FnOnce(&mut pulse::TagWriter),
+    ) -> io::Result<()> {
+        let tag = self.conn.send(cmd, args)?;
+        self.pending.insert(tag, kind);
+        Ok(())
+    }
+
+    fn query_server(&mut self) -> io::Result<()> {
+        self.request(command::GET_SERVER_INFO, Pending::ServerInfo, |_| {})
+    }
+
+    fn query(&mut self, facility: Facility, index: Option<u32>) -> io::Result<()> {
+        let (one, all) = facility.info_commands();
+        match index {
+            Some(index) => {
+                // Streams are looked up by index alone; devices also take a
+                // name.
+                let by_name = matches!(facility, Facility::Sink | Facility::Source);
+                self.request(one, Pending::Info { facility, list: false }, |w| {
+                    w.u32(index);
+                    if by_name {
+                        w.string(None);
+                    }
+                })
+            }
+            None => self.request(all, Pending::Info { facility, list: true }, |_| {}),
+        }
+    }
+
+    fn remove(&mut self, facility: Facility, index: u32) {
+        let m = &mut self.mixer;
+        match facility {
+            Facility::Sink => m.sinks.retain(|d| d.index != index),
+            Facility::Source => m.sources.retain(|d| d.index != index),
+            Facility::SinkInput => m.sink_inputs.retain(|s| s.index != index),
+            Facility::SourceOutput => m.source_outputs.retain(|s| s.index != index),
+        }
+    }
+
+    /// Hand the mixer to the UI once no replies are outstanding, so it never
+    /// sees a half-filled list.
+    fn publish(&mut self) {
+        if !self.pending.values().all(|p| matches!(p, Pending::Ack)) {
+            return;
+        }
+        if self.published.as_ref() != Some(&self.mixer) {
+            self.published = Some(self.mixer.clone());
+            *self.latest.lock().unwrap() = Some(Some(self.mixer.clone()));
+        }
+    }
+
+    fn apply(&mut self, request: Request) -> io::Result<()> {
+        match request {
+            Request::Volume(percent) => {
+                let Some(sink) = self.mixer.default_sink() else {
+                    return Ok(());
+                };
+                let (index, volume) = (sink.index, scaled(&sink.volume, percent));
+                self.request(command::SET_SINK_VOLUME, Pending::Ack, |w| {
+                    w.u32(index).string(None).cvolume(&volume);
+                })
+            }
+            Request::Mute(muted) => {
+                let Some(index) = self.mixer.default_sink().map(|d| d.index) else {
+                    return Ok(());
+                };
+                self.request(command::SET_SINK_MUTE, Pending::Ack, |w| {
+                    w.u32(index).string(None).bool(muted);
+                })
+            }
+            Request::SourceMute(muted) => {
+                let Some(index) = self.mixer.default_source().map(|d| d.index) else {
+                    return Ok(());
+                };
+                self.request(command::SET_SOURCE_MUTE, Pending::Ack, |w| {
+                    w.u32(index).string(None).bool(muted);
+                })
+            }
+            Request::StreamVolume(index, percent) => {
+                let Some(stream) = self.mixer.sink_inputs.iter().find(|s| s.index == index) else {
+                    return Ok(());
+                };
+                let volume = scaled(&stream.volume, percent);
+                self.request(command::SET_SINK_INPUT_VOLUME, Pending::Ack, |w| {
+                    w.u32(index).cvolume(&volume);
+                })
+            }
+            Request::StreamMute(index, muted) => {
+                self.request(command::SET_SINK_INPUT_MUTE, Pending::Ack, |w| {
+                    w.u32(index).bool(muted);
+                })
+            }
+            Request::DefaultSink(name) => {
+                let Some(target) = self.mixer.sinks.iter().find(|d| d.name == name) else {
+                    return Ok(());
+                };
+                let target = target.index;
+                self.request(command::SET_DEFAULT_SINK, Pending::Ack, |w| {
+                    w.string(Some(&name));
+                })?;
+                let streams: Vec<u32> = self
+                    .mixer
+                    .sink_inputs
+                    .iter()
+                    .filter(|s| s.device != target)
+                    .map(|s| s.index)
+                    .collect();
+                for stream in streams {
+                    self.request(command::MOVE_SINK_INPUT, Pending::Ack, |w| {
+                        w.u32(stream).u32(target).string(None);
+                    })?;
+                }
+                Ok(())
+            }
+            Request::DefaultSource(name) => {
+                let Some(target) = self.mixer.inputs().find(|d| d.name == name) else {
+                    return Ok(());
+                };
+                let target = target.index;
+                self.request(command::SET_DEFAULT_SOURCE, Pending::Ack, |w| {
+                    w.string(Some(&name));
+                })?;
+                // Streams recording a sink's monitor (visualisers, screen
+                // recorders) stay where they are.
+                let streams: Vec<u32> = self
+                    .mixer
+                    .source_outputs
+                    .iter()
+                    .filter(|s| s.device != target)
+                    .filter(|s| self.mixer.inputs().any(|d| d.index == s.device))
+                    .map(|s| s.index)
+                    .collect();
+                for stream in streams {
+                    self.request(command::MOVE_SOURCE_OUTPUT, Pending::Ack, |w| {
+                        w.u32(stream).u32(target).string(None);
+                    })?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn read_info(&mut self, r: &mut TagReader, facility: Facility, list: bool) -> io::Result<()> {
+        let version = self.conn.version;
+        if list {
+            let m = &mut self.mixer;
+            match facility {
+                Facility::Sink => m.sinks.clear(),
+                Facility::Source => m.sources.clear(),
+                Facility::SinkInput => m.sink_inputs.clear(),
+                Facility::SourceOutput => m.source_outputs.clear(),
+            }
+        }
+        while !r.at_end() {
+            let m = &mut self.mixer;
+            match facility {
+                Facility::Sink => upsert(&mut m.sinks, read_device(r, version, false)?, |d| d.index),
+                Facility::Source => {
+                    upsert(&mut m.sources, read_device(r, version, true)?, |d| d.index)
+                }
+                Facility::SinkInput => {
+                    upsert(&mut m.sink_inputs, read_sink_input(r, version)?, |s| s.index)
+                }
+                Facility::SourceOutput => {
+                    upsert(&mut m.source_outputs, read_source_output(r, version)?, |s| s.index)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, mut packet: pulse::Packet) -> io::Result<()> {
+        if packet.command == command::SUBSCRIBE_EVENT {
+            let event = packet.body.u32()?;
+            let index = packet.body.u32()?;
+            if event & sub::FACILITY_MASK == sub::FACILITY_SERVER {
+                // The defaults may have changed.
+                return self.query_server();
+            }
+            if let Some(facility) = Facility::from_event(event) {
+                if event & sub::TYPE_MASK == sub::TYPE_REMOVE {
+                    self.remove(facility, index);
+                } else {
+                    self.query(facility, Some(index))?;
+                }
+            }
+            return Ok(());
+        }
+        let Some(kind) = self.pending.remove(&packet.tag) else {
+            return Ok(());
+        };
+        if packet.command == command::ERROR {
+            let code = packet.body.u32().unwrap_or(0);
+            // Objects often vanish between an event and our query.
+            if !matches!(kind, Pending::Info { .. }) {
+                eprintln!("Sound server request failed: error {}", code);
+            }
+            return Ok(());
+        }
+        match kind {
+            Pending::ServerInfo => {
+                let (sink, source) = read_defaults(&mut packet.body)?;
+                self.mixer.default_sink = sink;
+                self.mixer.default_source = source;
+            }
+            Pending::Info { facility, list } => self.read_info(&mut packet.body, facility, list)?,
+            Pending::Ack => {}
+        }
+        Ok(())
+    }
+}
+
+/// One connection's lifetime. Returns `Ok` once the UI has dropped its
+/// handle.
+fn session(rx: &Receiver<Request>, latest: &Mutex<Option<Option<Mixer>>>) -> io::Result<()> {
+    let conn = Connection::connect(CLIENT_NAME)?;
+    let mut s = Session {
+        conn,
+        pending: HashMap::new(),
+        mixer: Mixer::default(),
+        published: None,
+        latest,
+    };
+    let mask = sub::MASK_SINK
+        | sub::MASK_SOURCE
+        | sub::MASK_SINK_INPUT
+        | sub::MASK_SOURCE_OUTPUT
+        | sub::MASK_SERVER;
+    s.request(command::SUBSCRIBE, Pending::Ack, |w| {
+        w.u32(mask);
+    })?;
+    s.query_server()?;
+    for facility in [Facility::Sink, Facility::Source, Facility::SinkInput, Facility::SourceOutput] {
+        s.query(facility, None)?;
+    }
+    loop {
+        // Slider drags queue many volumes; only the last one for each
+        // sink or stream matters.
+        let mut volume = None;
+        let mut stream_volumes: Vec<(u32, f64)> = Vec::new();
+        loop {
+            match rx.try_recv() {
+                Ok(Request::Volume(v)) => volume = Some(v),
+                Ok(Request::StreamVolume(index, v)) => {
+                    stream_volumes.retain(|&(i, _)| i != index);
+                    stream_volumes.push((index, v));
+                }
+                Ok(r) => s.apply(r)?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        if let Some(v) = volume {
+            s.apply(Request::Volume(v))?;
+        }
+        for (index, v) in stream_volumes {
+            s.apply(Request::StreamVolume(index, v))?;
+        }
+        while s.conn.poll(POLL_INTERVAL)? {
+            let packet = s.conn.recv()?;
+            s.handle(packet)?;
+        }
+        s.publish();
+    }
+}
@@ -0,0 +1,102 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::client::daemon_request;
+
+const BACKLIGHT_CLASS: &str = "/sys/class/backlight";
+
+/// Pause after a change so a daemon fade is followed in a few steps rather
+/// than one request per level.
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// The backlight the daemon controls, from `get_state` (`backlight` section).
+#[derive(Clone, Debug, Deserialize)]
+pub struct Backlight {
+    pub device: String,
+    /// Perceptual 0-100 position of `brightness`, on the daemon's curve.
+    #[serde(default)]
+    pub percent: f64,
+}
+
+impl Backlight {
+    pub fn from_state(state: &serde_json::Value) -> Option<Backlight> {
+        serde_json::from_value(state.get("backlight")?.clone()).ok()
+    }
+}
+
+/// The daemon's view of the backlight. Blocks, so only call it off the GTK
+/// thread.
+fn fetch() -> Option<Backlight> {
+    daemon_request(json!({"cmd": "get_state"})).as_ref().and_then(Backlight::from_state)
+}
+
+/// Watch `device`'s `brightness` and `actual_brightness` with inotify and
+/// store the new slider position in `latest` whenever something changes the
+/// level: hotkeys, other tools or the daemon. The position comes from the
+/// daemon, which owns the brightness curve.
+pub fn spawn_watcher(device: &str, latest: Arc<Mutex<Option<f64>>>) {
+    let dir = Path::new(BACKLIGHT_CLASS).join(device);
+    std::thread::spawn(move || {
+        // SAFETY: plain syscalls on a descriptor this thread owns.
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            eprintln!("inotify_init1 failed: {}", std::io::Error::last_os_error());
+            return;
+        }
+        let mut watches: Vec<i32> = Vec::new();
+        for name in ["brightness", "actual_brightness"] {
+            let path = dir.join(name);
+            let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+                continue;
+            };
+            let wd = unsafe { libc::inotify_add_watch(fd, c_path.as_ptr(), libc::IN_MODIFY) };
+            if wd < 0 {
+                eprintln!(
+                    "Cannot watch {}: {}",
+                    path.display(),
+                    std::io::Error::last_os_error()
+                );
+            } else {
+                watches.push(wd);
+            }
+        }
+        if watches.is_empty() {
+            unsafe { libc::close(fd) };
+            return;
+        }
+
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                eprintln!("Backlight watch failed: {}", err);
+                break;
+            }
+            // One lookup for the whole batch.
+            let mut offset = 0;
+            let mut changed = false;
+            while offset + std::mem::size_of::<libc::inotify_event>() <= n as usize {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset).cast()) };
+                changed |= watches.contains(&event.wd);
+                offset += std::mem::size_of::<libc::inotify_event>() + event.len as usize;
+            }
+            if changed {
+                std::thread::sleep(SETTLE);
+                if let Some(dev) = fetch() {
+                    *latest.lock().unwrap() = Some(dev.percent);
+                }
+            }
+        }
+        unsafe { libc::close(fd) };
+    });
+}
@@ -0,0 +1,866 @@
+//! Bluetooth devices through BlueZ's D-Bus API: paired and discovered
+//! devices, scanning, pairing, connecting and forgetting. The panel talks to
+//! BlueZ itself on the user's system bus connection. Discovery sessions and
+//! pairing agents belong to the connection that started them, so they last
+//! as long as the panel. Requests run on the tokio runtime and their results
+//! are left for the GUI to pick up.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zbus::fdo::{ManagedObjects, ObjectManagerProxy};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+use zbus::{interface, proxy, Connection};
+
+use crate::client::{system_bus, tokio_rt};
+
+const SERVICE: &str = "org.bluez";
+const ADAPTER: &str = "org.bluez.Adapter1";
+const DEVICE: &str = "org.bluez.Device1";
+const BATTERY: &str = "org.bluez.Battery1";
+const AGENT_PATH: &str = "/org/loki/MasterControl/BluetoothAgent";
+/// The panel has no way to enter or show a passkey.
+const AGENT_CAPABILITY: &str = "NoInputNoOutput";
+/// How long discovery runs after a scan request.
+const SCAN_DURATION: Duration = Duration::from_secs(30);
+/// How long pairing may take, including the other side's confirmation.
+const PAIR_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[proxy(interface = "org.bluez.Adapter1", default_service = "org.bluez")]
+trait Adapter {
+    fn start_discovery(&self) -> zbus::Result<()>;
+
+    fn stop_discovery(&self) -> zbus::Result<()>;
+
+    fn remove_device(&self, device: &ObjectPath<'_>) -> zbus::Result<()>;
+}
+
+#[proxy(interface = "org.bluez.Device1", default_service = "org.bluez")]
+trait Device {
+    fn pair(&self) -> zbus::Result<()>;
+
+    fn cancel_pairing(&self) -> zbus::Result<()>;
+
+    fn connect(&self) -> zbus::Result<()>;
+
+    fn disconnect(&self) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.bluez.AgentManager1",
+    default_service = "org.bluez",
+    default_path = "/org/bluez"
+)]
+trait AgentManager {
+    fn register_agent(&self, agent: &ObjectPath<'_>, capability: &str) -> zbus::Result<()>;
+}
+
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.bluez.Error")]
+enum AgentError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    Rejected(String),
+}
+
+/// Pairing agent for pairings started from the panel. BlueZ only asks the
+/// agent of the client that called `Pair`; it is not the default agent, so
+/// requests from other devices never reach it. Nothing can be shown to the
+/// user, so it refuses everything that needs them to see, compare or type a
+/// code, and never logs one.
+struct Agent;
+
+#[interface(name = "org.bluez.Agent1")]
+impl Agent {
+    fn release(&self) {}
+
+    fn request_pin_code(&self, _device: OwnedObjectPath) -> Result<String, AgentError> {
+        Err(AgentError::Rejected("no way to enter a PIN".into()))
+    }
+
+    fn display_pin_code(
+        &self,
+        _device: OwnedObjectPath,
+        _pincode: String,
+    ) -> Result<(), AgentError> {
+        Err(AgentError::Rejected("no way to show a PIN".into()))
+    }
+
+    fn request_passkey(&self, _device: OwnedObjectPath) -> Result<u32, AgentError> {
+        Err(AgentError::Rejected("no way to enter a passkey".into()))
+    }
+
+    fn display_passkey(
+        &self,
+        _device: OwnedObjectPath,
+        _passkey: u32,
+        _entered: u16,
+    ) -> Result<(), AgentError> {
+        Err(AgentError::Rejected("no way to show a passkey".into()))
+    }
+
+    /// Accepting a code nobody has compared would let anyone in range pair.
+    fn request_confirmation(
+        &self,
+        _device: OwnedObjectPath,
+        _passkey: u32,
+    ) -> Result<(), AgentError> {
+        Err(AgentError::Rejected("no way to compare a passkey".into()))
+    }
+
+    fn request_authorization(&self, _device: OwnedObjectPath) -> Result<(), AgentError> {
+        Err(AgentError::Rejected("no way to ask the user".into()))
+    }
+
+    fn authorize_service(&self, _device: OwnedObjectPath, _uuid: String) -> Result<(), AgentError> {
+        Err(AgentError::Rejected("no way to ask the user".into()))
+    }
+
+    fn cancel(&self) {}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    /// `AA:BB:CC:DD:EE:FF`, used to pick a device.
+    pub address: String,
+    pub name: String,
+    /// freedesktop icon name from the device class, e.g. `input-gaming`.
+    pub icon: Option<String>,
+    pub paired: bool,
+    pub connected: bool,
+    /// Battery percentage, for devices that report it.
+    pub battery: Option<u8>,
+}
+
+impl Device {
+    pub fn describe(&self) -> String {
+        let mut detail = match (self.connected, self.paired) {
+            (true, _) => "Connected".to_string(),
+            (false, true) => "Paired".to_string(),
+            (false, false) => "Not paired".to_string(),
+        };
+        if let Some(battery) = self.battery {
+            detail.push_str(&format!(" · {battery}% battery"));
+        }
+        detail
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    /// Adapter address, or `None` without an adapter.
+    pub adapter: Option<String>,
+    pub powered: bool,
+    pub discovering: bool,
+    /// Connected first, then paired, then by signal.
+    pub devices: Vec<Device>,
+}
+
+fn bus_error(e: zbus::Error) -> String {
+    match e {
+        zbus::Error::MethodError(name, msg, _) => match name.as_str() {
+            "org.freedesktop.DBus.Error.ServiceUnknown"
+            | "org.freedesktop.DBus.Error.NameHasNoOwner" => "BlueZ is not running".to_string(),
+            "org.bluez.Error.NotReady" => "Bluetooth is off".to_string(),
+            _ => msg.unwrap_or_else(|| name.to_string()),
+        },
+        e => format!("BlueZ: {e}"),
+    }
+}
+
+fn is_error(e: &zbus::Error, name: &str) -> bool {
+    matches!(e, zbus::Error::MethodError(n, _, _) if n.as_str() == name)
+}
+
+async fn managed_objects(conn: &Connection) -> Result<ManagedObjects, String> {
+    let manager = ObjectManagerProxy::builder(conn)
+        .destination(SERVICE)
+        .map_err(bus_error)?
+        .path("/")
+        .map_err(bus_error)?
+        .build()
+        .await
+        .map_err(bus_error)?;
+    manager.get_managed_objects().await.map_err(|e| match e {
+        zbus::fdo::Error::ServiceUnknown(_) | zbus::fdo::Error::NameHasNoOwner(_) => {
+            "BlueZ is not running".to_string()
+        }
+        e => format!("BlueZ: {e}"),
+    })
+}
+
+fn prop<T: TryFrom<OwnedValue>>(props: &HashMap<String, OwnedValue>, key: &str) -> Option<T> {
+    T::try_from(props.get(key)?.try_clone().ok()?).ok()
+}
+
+fn interface<'a>(
+    ifaces: &'a HashMap<zbus::names::OwnedInterfaceName, HashMap<String, OwnedValue>>,
+    name: &str,
+) -> Option<&'a HashMap<String, OwnedValue>> {
+    ifaces
+        .iter()
+        .find(|(n, _)| n.as_str() == name)
+        .map(|(_, props)| props)
+}
+
+/// The first adapter, by path, with its properties.
+fn adapter(objects: &ManagedObjects) -> Option<(&OwnedObjectPath, &HashMap<String, OwnedValue>)> {
+    let mut adapters: Vec<_> = objects
+        .iter()
+        .filter_map(|(path, ifaces)| Some((path, interface(ifaces, ADAPTER)?)))
+        .collect();
+    adapters.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    adapters.into_iter().next()
+}
+
+fn check_address(address: &str) -> Result<(), String> {
+    let valid = address.len() == 17
+        && address.split(':').count() == 6
+        && address
+            .split(':')
+            .all(|b| b.len() == 2 && b.chars().all(|c| c.is_ascii_hexdigit()));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("{address:?} is not a Bluetooth address"))
+    }
+}
+
+/// Adapter path, object path and properties of the device with `address`
+/// on the first adapter.
+async fn find_device(
+    conn: &Connection,
+    address: &str,
+) -> Result<
+    (
+        OwnedObjectPath,
+        OwnedObjectPath,
+        HashMap<String, OwnedValue>,
+    ),
+    String,
+> {
+    check_address(address)?;
+    let objects = managed_objects(conn).await?;
+    let (adapter_path, _) = adapter(&objects).ok_or("no Bluetooth adapter")?;
+    for (path, ifaces) in &objects {
+        let Some(props) = interface(ifaces, DEVICE) else {
+            continue;
+        };
+        let on_adapter = prop::<OwnedObjectPath>(props, "Adapter").as_ref() == Some(adapter_path);
+        let same =
+            prop::<String>(props, "Address").is_some_and(|a| a.eq_ignore_ascii_case(address));
+        if on_adapter && same {
+            return Ok((adapter_path.clone(), path.clone(), props.clone()));
+        }
+    }
+    Err(format!("no Bluetooth device {address}"))
+}
+
+async fn device_proxy(
+    conn: &Connection,
+    path: &OwnedObjectPath,
+) -> Result<DeviceProxy<'static>, String> {
+    DeviceProxy::builder(conn)
+        .path(path.clone())
+        .map_err(bus_error)?
+        .build()
+        .await
+        .map_err(bus_error)
+}
+
+pub async fn status(conn: &Connection) -> Result<Status, String> {
+    let objects = managed_objects(conn).await?;
+    let Some((adapter_path, adapter_props)) = adapter(&objects) else {
+        return Ok(Status::default());
+    };
+    let mut devices: Vec<(Device, i16)> = objects
+        .values()
+        .filter_map(|ifaces| {
+            let props = interface(ifaces, DEVICE)?;
+            if prop::<OwnedObjectPath>(props, "Adapter").as_ref() != Some(adapter_path) {
+                return None;
+            }
+            let address: String = prop(props, "Address")?;
+            let device = Device {
+                name: prop(props, "Alias").unwrap_or_else(|| address.clone()),
+                address,
+                icon: prop(props, "Icon"),
+                paired: prop(props, "Paired").unwrap_or(false),
+                connected: prop(props, "Connected").unwrap_or(false),
+                battery: interface(ifaces, BATTERY).and_then(|b| prop(b, "Percentage")),
+            };
+            // Only present while discovery sees the device.
+            let rssi = prop(props, "RSSI").unwrap_or(i16::MIN);
+            Some((device, rssi))
+        })
+        .collect();
+    devices.sort_by(|(a, a_rssi), (b, b_rssi)| {
+        (b.connected, b.paired, b_rssi)
+            .cmp(&(a.connected, a.paired, a_rssi))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(Status {
+        adapter: prop(adapter_props, "Address"),
+        powered: prop(adapter_props, "Powered").unwrap_or(false),
+        discovering: prop(adapter_props, "Discovering").unwrap_or(false),
+        devices: devices.into_iter().map(|(d, _)| d).collect(),
+    })
+}
+
+/// Look for devices for [`SCAN_DURATION`]. Scanning again while a scan runs
+/// extends it.
+pub async fn scan(conn: &Connection) -> Result<(), String> {
+    static GENERATION: AtomicU64 = AtomicU64::new(0);
+    let objects = managed_objects(conn).await?;
+    let (path, _) = adapter(&objects).ok_or("no Bluetooth adapter")?;
+    let adapter = AdapterProxy::builder(conn)
+        .path(path.clone())
+        .map_err(bus_error)?
+        .build()
+        .await
+        .map_err(bus_error)?;
+    match adapter.start_discovery().await {
+        Err(e) if !is_error(&e, "org.bluez.Error.InProgress") => return Err(bus_error(e)),
+        _ => {}
+    }
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    tokio::spawn(async move {
+        tokio::time::sleep(SCAN_DURATION).await;
+        if GENERATION.load(Ordering::SeqCst) == generation {
+            let _ = adapter.stop_discovery().await;
+        }
+    });
+    Ok(())
+}
+
+/// Pair with a discovered device and connect. The device is not trusted, so
+/// BlueZ still asks before it connects by itself.
+pub async fn pair(conn: &Connection, address: &str) -> Result<(), String> {
+    let (_, path, props) = find_device(conn, address).await?;
+    let device = device_proxy(conn, &path).await?;
+    if !prop::<bool>(&props, "Paired").unwrap_or(false) {
+        // Serving twice is a no-op, and bluetoothd forgets agents when it
+        // restarts, so both happen before every pairing.
+        conn.object_server()
+            .at(AGENT_PATH, Agent)
+            .await
+            .map_err(bus_error)?;
+        let agents = AgentManagerProxy::new(conn).await.map_err(bus_error)?;
+        match agents
+            .register_agent(
+                &ObjectPath::from_static_str_unchecked(AGENT_PATH),
+                AGENT_CAPABILITY,
+            )
+            .await
+        {
+            Err(e) if !is_error(&e, "org.bluez.Error.AlreadyExists") => return Err(bus_error(e)),
+            _ => {}
+        }
+        match tokio::time::timeout(PAIR_TIMEOUT, device.pair()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) if is_error(&e, "org.bluez.Error.AlreadyExists") => {}
+            Ok(Err(e)) => return Err(bus_error(e)),
+            Err(_) => {
+                let _ = device.cancel_pairing().await;
+                return Err(format!("timed out pairing with {address}"));
+            }
+        }
+    }
+    connect(conn, address).await
+}
+
+pub async fn connect(conn: &Connection, address: &str) -> Result<(), String> {
+    let (_, path, _) = find_device(conn, address).await?;
+    match device_proxy(conn, &path).await?.connect().await {
+        Err(e) if !is_error(&e, "org.bluez.Error.AlreadyConnected") => Err(bus_error(e)),
+        _ => Ok(()),
+    }
+}
+
+pub async fn disconnect(conn: &Connection, address: &str) -> Result<(), String> {
+    let (_, path, _) = find_device(conn, address).await?;
+    device_proxy(conn, &path)
+        .await?
+        .disconnect()
+        .await
+        .map_err(bus_error)
+}
+
+/// Remove the pairing and BlueZ's record of the device.
+pub async fn forget(conn: &Connection, address: &str) -> Result<(), String> {
+    let (adapter_path, path, _) = find_device(conn, address).await?;
+    let adapter = AdapterProxy::builder(conn)
+        .path(adapter_path)
+        .map_err(bus_error)?
+        .build()
+        .await
+        .map_err(bus_error)?;
+    adapter
+        .remove_device(&path.as_ref())
+        .await
+        .map_err(bus_error)
+}
+
+/// What came back from BlueZ since the last poll.
+#[derive(Debug, Default)]
+pub struct Update {
+    /// `None` when BlueZ cannot be reached.
+    pub status: Option<Option<Status>>,
+    /// Error from the last action, for display.
+    pub error: Option<String>,
+    /// No action is in flight.
+    pub idle: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    Scan,
+    Pair,
+    Connect,
+    Disconnect,
+    Forget,
+}
+
+async fn perform(action: Action, address: &str) -> Result<(), String> {
+    let conn = system_bus().await?;
+    match action {
+        Action::Scan => scan(&conn).await,
+        Action::Pair => pair(&conn, address).await,
+        Action::Connect => connect(&conn, address).await,
+        Action::Disconnect => disconnect(&conn, address).await,
+        Action::Forget => forget(&conn, address).await,
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Bluetooth {
+    latest: Arc<Mutex<Option<Update>>>,
+}
+
+impl Bluetooth {
+    pub fn take_update(&self) -> Option<Update> {
+        self.latest.lock().unwrap().take()
+    }
+
+    fn post(&self, f: impl FnOnce(&mut Update)) {
+        let mut latest = self.latest.lock().unwrap();
+        f(latest.get_or_insert_with(Update::default));
+    }
+
+    /// Perform `action` on the device with `address`, then re-read the
+    /// device list.
+    fn run(&self, action: Action, address: &str) {
+        let bt = self.clone();
+        let address = address.to_string();
+        tokio_rt().spawn(async move {
+            if let Err(e) = perform(action, &address).await {
+                bt.post(|u| u.error = Some(e));
+            }
+            bt.fetch().await;
+            bt.post(|u| u.idle = true);
+        });
+    }
+
+    async fn fetch(&self) {
+        let result = match system_bus().await {
+            Ok(conn) => status(&conn).await,
+            Err(e) => Err(e),
+        };
+        let status = match result {
+            Ok(status) => Some(status),
+            Err(e) => {
+                eprintln!("Bluetooth status unavailable: {e}");
+                None
+            }
+        };
+        self.post(|u| u.status = Some(status));
+    }
+
+    pub fn refresh(&self) {
+        let bt = self.clone();
+        tokio_rt().spawn(async move { bt.fetch().await });
+    }
+
+    /// Discovery keeps running after this returns; devices show up on later
+    /// refreshes.
+    pub fn scan(&self) {
+        self.run(Action::Scan, "");
+    }
+
+    pub fn pair(&self, address: &str) {
+        self.run(Action::Pair, address);
+    }
+
+    pub fn connect(&self, address: &str) {
+        self.run(Action::Connect, address);
+    }
+
+    pub fn disconnect(&self, address: &str) {
+        self.run(Action::Disconnect, address);
+    }
+
+    pub fn forget(&self, address: &str) {
+        self.run(Action::Forget, address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus::{temp_dir, PrivateBus};
+    use zbus::fdo;
+    use zbus::object_server::ObjectServer;
+
+    const ADAPTER_PATH: &str = "/org/bluez/hci0";
+    const EARBUDS: &str = "11:22:33:44:55:66";
+    const CONTROLLER: &str = "AA:BB:CC:DD:EE:01";
+    const PHONE: &str = "AA:BB:CC:DD:EE:02";
+    const KEYBOARD: &str = "AA:BB:CC:DD:EE:03";
+
+    #[derive(Debug, zbus::DBusError)]
+    #[zbus(prefix = "org.bluez.Error")]
+    enum BluezError {
+        #[zbus(error)]
+        ZBus(zbus::Error),
+        Failed(String),
+        AuthenticationFailed(String),
+        AlreadyExists(String),
+        DoesNotExist(String),
+    }
+
+    fn device_path(address: &str) -> OwnedObjectPath {
+        let path = format!("{ADAPTER_PATH}/dev_{}", address.replace(':', "_"));
+        ObjectPath::try_from(path).unwrap().into()
+    }
+
+    /// Unique name and object path of the registered agent.
+    type Registered = Arc<Mutex<Option<(String, OwnedObjectPath)>>>;
+
+    struct MockAgentManager {
+        agent: Registered,
+    }
+
+    #[interface(name = "org.bluez.AgentManager1")]
+    impl MockAgentManager {
+        fn register_agent(
+            &self,
+            agent: OwnedObjectPath,
+            _capability: String,
+            #[zbus(header)] header: zbus::message::Header<'_>,
+        ) -> Result<(), BluezError> {
+            let sender = header.sender().unwrap().to_string();
+            let mut registered = self.agent.lock().unwrap();
+            if registered.as_ref().is_some_and(|(s, _)| *s == sender) {
+                return Err(BluezError::AlreadyExists("Already Exists".into()));
+            }
+            *registered = Some((sender, agent));
+            Ok(())
+        }
+    }
+
+    struct MockAdapter {
+        agent: Registered,
+        discovering: Mutex<bool>,
+    }
+
+    #[interface(name = "org.bluez.Adapter1")]
+    impl MockAdapter {
+        async fn start_discovery(&self, #[zbus(object_server)] server: &ObjectServer) {
+            *self.discovering.lock().unwrap() = true;
+            let found = [
+                (CONTROLLER, "8BitDo Pro 2", "input-gaming", -55),
+                (PHONE, "Phone", "phone", -70),
+                (KEYBOARD, "Keyboard", "input-keyboard", -65),
+            ];
+            for (address, name, icon, rssi) in found {
+                let device = MockDevice::new(&self.agent, address, name, icon, rssi, false);
+                let _ = server.at(device_path(address), device).await;
+            }
+        }
+
+        fn stop_discovery(&self) {
+            *self.discovering.lock().unwrap() = false;
+        }
+
+        async fn remove_device(
+            &self,
+            device: OwnedObjectPath,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> Result<(), BluezError> {
+            let _ = server.remove::<MockBattery, _>(&device).await;
+            match server.remove::<MockDevice, _>(&device).await {
+                Ok(true) => Ok(()),
+                _ => Err(BluezError::DoesNotExist("Does Not Exist".into())),
+            }
+        }
+
+        #[zbus(property)]
+        fn address(&self) -> String {
+            "00:1A:7D:DA:71:13".into()
+        }
+
+        #[zbus(property)]
+        fn powered(&self) -> bool {
+            true
+        }
+
+        #[zbus(property)]
+        fn discovering(&self) -> bool {
+            *self.discovering.lock().unwrap()
+        }
+    }
+
+    struct DeviceState {
+        paired: bool,
+        connected: bool,
+        trusted: bool,
+    }
+
+    struct MockDevice {
+        agent: Registered,
+        address: String,
+        name: String,
+        icon: String,
+        rssi: i16,
+        state: Arc<Mutex<DeviceState>>,
+    }
+
+    impl MockDevice {
+        fn new(
+            agent: &Registered,
+            address: &str,
+            name: &str,
+            icon: &str,
+            rssi: i16,
+            paired: bool,
+        ) -> MockDevice {
+            MockDevice {
+                agent: agent.clone(),
+                address: address.into(),
+                name: name.into(),
+                icon: icon.into(),
+                rssi,
+                state: Arc::new(Mutex::new(DeviceState {
+                    paired,
+                    connected: false,
+                    trusted: false,
+                })),
+            }
+        }
+    }
+
+    #[interface(name = "org.bluez.Device1")]
+    impl MockDevice {
+        /// Ask the registered agent, as BlueZ does: a phone wants a passkey
+        /// typed in, a keyboard a passkey shown to type on it, and a
+        /// controller pairs without asking.
+        async fn pair(&self, #[zbus(connection)] conn: &Connection) -> Result<(), BluezError> {
+            if self.state.lock().unwrap().paired {
+                return Err(BluezError::AlreadyExists("Already Exists".into()));
+            }
+            let Some((sender, agent)) = self.agent.lock().unwrap().clone() else {
+                return Err(BluezError::AuthenticationFailed("no agent".into()));
+            };
+            let proxy = zbus::Proxy::new(conn, sender, agent, "org.bluez.Agent1").await?;
+            let path = device_path(&self.address);
+            let answer = match self.icon.as_str() {
+                "phone" => proxy
+                    .call_method("RequestPasskey", &(path,))
+                    .await
+                    .map(|_| ()),
+                "input-keyboard" => proxy
+                    .call_method("DisplayPasskey", &(path, 123456u32, 0u16))
+                    .await
+                    .map(|_| ()),
+                _ => Ok(()),
+            };
+            if let Err(e) = answer {
+                return Err(BluezError::AuthenticationFailed(format!(
+                    "agent refused: {e}"
+                )));
+            }
+            self.state.lock().unwrap().paired = true;
+            Ok(())
+        }
+
+        fn cancel_pairing(&self) {}
+
+        fn connect(&self) -> Result<(), BluezError> {
+            let mut state = self.state.lock().unwrap();
+            if !state.paired {
+                return Err(BluezError::Failed("Not paired".into()));
+            }
+            state.connected = true;
+            Ok(())
+        }
+
+        fn disconnect(&self) {
+            self.state.lock().unwrap().connected = false;
+        }
+
+        #[zbus(property)]
+        fn address(&self) -> String {
+            self.address.clone()
+        }
+
+        #[zbus(property)]
+        fn alias(&self) -> String {
+            self.name.clone()
+        }
+
+        #[zbus(property)]
+        fn icon(&self) -> String {
+            self.icon.clone()
+        }
+
+        #[zbus(property)]
+        fn adapter(&self) -> OwnedObjectPath {
+            ObjectPath::from_static_str_unchecked(ADAPTER_PATH).into()
+        }
+
+        #[zbus(property, name = "RSSI")]
+        fn rssi(&self) -> i16 {
+            self.rssi
+        }
+
+        #[zbus(property)]
+        fn paired(&self) -> bool {
+            self.state.lock().unwrap().paired
+        }
+
+        #[zbus(property)]
+        fn connected(&self) -> bool {
+            self.state.lock().unwrap().connected
+        }
+
+        #[zbus(property)]
+        fn trusted(&self) -> bool {
+            self.state.lock().unwrap().trusted
+        }
+
+        #[zbus(property)]
+        fn set_trusted(&self, trusted: bool) {
+            self.state.lock().unwrap().trusted = trusted;
+        }
+    }
+
+    struct MockBattery(u8);
+
+    #[interface(name = "org.bluez.Battery1")]
+    impl MockBattery {
+        #[zbus(property)]
+        fn percentage(&self) -> u8 {
+            self.0
+        }
+    }
+
+    fn address_of(status: &Status, address: &str) -> Option<Device> {
+        status
+            .devices
+            .iter()
+            .find(|d| d.address == address)
+            .cloned()
+    }
+
+    /// Whether the mock's device at `address` has been marked trusted.
+    async fn trusted(server: &Connection, address: &str) -> bool {
+        let device = server
+            .object_server()
+            .interface::<_, MockDevice>(device_path(address))
+            .await
+            .unwrap();
+        let trusted = device.get().await.state.lock().unwrap().trusted;
+        trusted
+    }
+
+    #[test]
+    fn addresses_are_checked() {
+        assert!(check_address("AA:bb:CC:00:11:22").is_ok());
+        assert!(check_address("AA:BB:CC:00:11").is_err());
+        assert!(check_address("AA:BB:CC:00:11:2G").is_err());
+        assert!(check_address("AABBCC:00:11:22:3").is_err());
+    }
+
+    #[tokio::test]
+    async fn talks_to_bluez_on_a_private_bus() {
+        let dir = temp_dir("bluetooth");
+        let Some(bus) = PrivateBus::start(&dir) else {
+            eprintln!("dbus-daemon not available; skipping");
+            return;
+        };
+        let agent = Registered::default();
+        let earbuds = MockDevice::new(&agent, EARBUDS, "Earbuds", "audio-headset", -60, true);
+        let bluez = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.bluez")
+            .unwrap()
+            .serve_at(
+                "/org/bluez",
+                MockAgentManager {
+                    agent: agent.clone(),
+                },
+            )
+            .unwrap()
+            .serve_at(
+                ADAPTER_PATH,
+                MockAdapter {
+                    agent: agent.clone(),
+                    discovering: Mutex::new(false),
+                },
+            )
+            .unwrap()
+            .serve_at(device_path(EARBUDS), earbuds)
+            .unwrap()
+            .serve_at(device_path(EARBUDS), MockBattery(70))
+            .unwrap()
+            .serve_at("/", fdo::ObjectManager)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let conn = bus.connect().await;
+
+        let status = super::status(&conn).await.unwrap();
+        assert_eq!(status.adapter.as_deref(), Some("00:1A:7D:DA:71:13"));
+        assert!(status.powered && !status.discovering);
+        let earbuds = address_of(&status, EARBUDS).unwrap();
+        assert_eq!(earbuds.describe(), "Paired · 70% battery");
+
+        scan(&conn).await.unwrap();
+        let status = super::status(&conn).await.unwrap();
+        assert!(status.discovering);
+        let order: Vec<_> = status.devices.iter().map(|d| d.address.as_str()).collect();
+        assert_eq!(order, [EARBUDS, CONTROLLER, KEYBOARD, PHONE]);
+
+        // Pairing connects, but leaves trusting the device to the user.
+        pair(&conn, CONTROLLER).await.unwrap();
+        let controller = address_of(&super::status(&conn).await.unwrap(), CONTROLLER).unwrap();
+        assert!(controller.paired && controller.connected);
+        assert!(!trusted(&bluez, CONTROLLER).await);
+
+        // Nothing is shown to the user, so codes are never accepted.
+        let err = pair(&conn, PHONE).await.unwrap_err();
+        assert!(err.contains("agent refused"), "{err}");
+        let err = pair(&conn, KEYBOARD).await.unwrap_err();
+        assert!(err.contains("agent refused"), "{err}");
+        let status = super::status(&conn).await.unwrap();
+        assert!(!address_of(&status, PHONE).unwrap().paired);
+        assert!(!address_of(&status, KEYBOARD).unwrap().paired);
+
+        disconnect(&conn, CONTROLLER).await.unwrap();
+        connect(&conn, EARBUDS).await.unwrap();
+        let status = super::status(&conn).await.unwrap();
+        assert_eq!(status.devices[0].address, EARBUDS);
+        assert!(!address_of(&status, CONTROLLER).unwrap().connected);
+
+        forget(&conn, CONTROLLER).await.unwrap();
+        let status = super::status(&conn).await.unwrap();
+        assert!(address_of(&status, CONTROLLER).is_none());
+        assert!(connect(&conn, "00:00:00:00:00:00").await.is_err());
+        assert!(connect(&conn, "earbuds").await.is_err());
+
+        drop(bus);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,185 @@
+use serde::Deserialize;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+    runtime::Runtime,
+};
+
+pub const SOCK_PATH: &str = "/run/loki-master.sock";
+
+/// Protocol version this client speaks; see the daemon's `PROTOCOL_VERSION`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Device features advertised by the daemon in its `hello` response.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Capabilities {
+    #[serde(default)]
+    pub backlight: bool,
+    #[serde(default)]
+    pub fan_control: bool,
+    #[serde(default)]
+    pub rgb_zones: Vec<String>,
+    #[serde(default)]
+    pub tdp_backend: Option<String>,
+    #[serde(default)]
+    pub cpufreq: bool,
+    #[serde(default)]
+    pub cpu_hotplug: bool,
+    #[serde(default)]
+    pub smt: bool,
+    #[serde(default)]
+    pub gpus: Vec<String>,
+    #[serde(default)]
+    pub light_sensor: bool,
+}
+
+#[derive(Deserialize)]
+struct HelloReply {
+    version: u32,
+    #[serde(default)]
+    min_client_version: u32,
+    capabilities: Capabilities,
+}
+
+/// Outcome of the protocol handshake.
+pub enum Handshake {
+    Ready(Capabilities),
+    /// The daemon no longer accepts this client's protocol; the reason is
+    /// meant for display.
+    Unsupported(String),
+    /// The daemon is unreachable or too old to know about capabilities.
+    Unknown,
+}
+
+pub fn tokio_rt() -> &'static Runtime {
+    static RT: OnceLock<Runtime> = OnceLock::new();
+    RT.get_or_init(|| Runtime::new().expect("tokio runtime"))
+}
+
+/// Fire-and-forget a request to the daemon, retrying the connection a few
+/// times.
+pub fn daemon_send(val: serde_json::Value) {
+    let rt = tokio_rt();
+    rt.spawn(async move {
+        let msg = val.to_string() + "\n";
+        for _ in 0..3 {
+            match UnixStream::connect(SOCK_PATH).await {
+                Ok(stream) => {
+                    let mut stream = stream;
+                    if stream.write_all(msg.as_bytes()).await.is_ok() {
+                        let mut reader = BufReader::new(stream);
+                        let mut resp = String::new();
+                        let _ = reader.read_line(&mut resp).await;
+                    }
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("connect failed: {e}");
+                    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                }
+            }
+        }
+    });
+}
+
+async fn roundtrip(val: serde_json::Value) -> Option<serde_json::Value> {
+    let mut stream = UnixStream::connect(SOCK_PATH).await.ok()?;
+    let msg = val.to_string() + "\n";
+    stream.write_all(msg.as_bytes()).await.ok()?;
+    let mut reader = BufReader::new(stream);
+    let mut resp = String::new();
+    reader.read_line(&mut resp).await.ok()?;
+    serde_json::from_str(&resp).ok()
+}
+
+/// Send a request from async code. Returns the `data` field of a successful
+/// response.
+pub async fn request_async(val: serde_json::Value) -> Option<serde_json::Value> {
+    let resp = tokio::time::timeout(Duration::from_secs(2), roundtrip(val))
+        .await
+        .ok()
+        .flatten()?;
+    if resp.get("success").and_then(|s| s.as_bool()) != Some(true) {
+        eprintln!(
+            "daemon request failed: {}",
+            resp.get("error").and_then(|e| e.as_str()).unwrap_or("unknown error")
+        );
+        return None;
+    }
+    resp.get("data").cloned()
+}
+
+/// The user's own system bus connection, made once and shared. Wi-Fi and
+/// Bluetooth talk to NetworkManager and BlueZ over it, so their polkit rules
+/// apply to the user rather than to the daemon. `LOKI_DBUS_ADDRESS` points
+/// it at a private bus instead.
+pub async fn system_bus() -> Result<zbus::Connection, String> {
+    static BUS: tokio::sync::OnceCell<zbus::Connection> = tokio::sync::OnceCell::const_new();
+    BUS.get_or_try_init(|| async {
+        let builder = match std::env::var("LOKI_DBUS_ADDRESS") {
+            Ok(addr) => zbus::connection::Builder::address(addr.as_str())?,
+            Err(_) => zbus::connection::Builder::system()?,
+        };
+        builder.build().await
+    })
+    .await
+    .cloned()
+    .map_err(|e| format!("cannot reach the system bus: {e}"))
+}
+
+/// Send a request and wait (briefly) for the reply. Returns the `data` field
+/// of a successful response.
+pub fn daemon_request(val: serde_json::Value) -> Option<serde_json::Value> {
+    tokio_rt().block_on(request_async(val))
+}
+
+/// Perform the protocol handshake.
+pub fn hello() -> Handshake {
+    let request = serde_json::json!({
+        "cmd": "hello",
+        "version": PROTOCOL_VERSION,
+        "client": "loki-control-ui",
+    });
+    let Some(resp) = tokio_rt().block_on(async {
+        tokio::time::timeout(Duration::from_secs(2), roundtrip(request))
+            .await
+            .ok()
+            .flatten()
+    }) else {
+        return Handshake::Unknown;
+    };
+    if resp.get("error_kind").and_then(|k| k.as_str()) == Some("unsupported_version") {
+        let error = resp
+            .get("error")
+            .and_then(|e| e.as_str())
+            .unwrap_or_default();
+        return Handshake::Unsupported(error.to_string());
+    }
+    let Some(reply) = resp
+        .get("data")
+        .and_then(|d| serde_json::from_value::<HelloReply>(d.clone()).ok())
+    else {
+        return Handshake::Unknown;
+    };
+    if reply.min_client_version > PROTOCOL_VERSION {
+        return Handshake::Unsupported(format!(
+            "daemon needs client protocol {} or newer, this panel speaks {}",
+            reply.min_client_version, PROTOCOL_VERSION
+        ));
+    }
+    if reply.version != PROTOCOL_VERSION {
+        eprintln!(
+            "daemon speaks protocol {}, UI speaks {}",
+            reply.version, PROTOCOL_VERSION
+        );
+    }
+    Handshake::Ready(reply.capabilities)
+}
+
+/// Fetch the daemon's full `get_state` snapshot. Sections are picked apart by
+/// the modules that display them.
+pub fn get_state() -> Option<serde_json::Value> {
+    daemon_request(serde_json::json!({"cmd": "get_state"}))
+}
@@ -0,0 +1,63 @@
+use serde::Deserialize;
+
+/// cpufreq settings from the daemon's `get_state` (`cpufreq` section).
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct CpuFreq {
+    pub governor: Option<String>,
+    #[serde(default)]
+    pub available_governors: Vec<String>,
+    pub epp: Option<String>,
+    #[serde(default)]
+    pub available_epp: Vec<String>,
+    pub boost: Option<bool>,
+    pub min_khz: Option<u32>,
+    pub max_khz: Option<u32>,
+    pub hw_min_khz: Option<u32>,
+    pub hw_max_khz: Option<u32>,
+}
+
+impl CpuFreq {
+    pub fn from_state(state: &serde_json::Value) -> Option<CpuFreq> {
+        serde_json::from_value(state.get("cpufreq")?.clone()).ok()
+    }
+}
+
+/// SMT and CPU hotplug state from `get_state` (`cores` section).
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct CpuCores {
+    pub smt: Option<String>,
+    #[serde(default)]
+    pub present: Vec<u32>,
+    #[serde(default)]
+    pub online: Vec<u32>,
+    /// CPUs that can be online; without SMT, sibling threads are missing.
+    #[serde(default)]
+    pub available: Vec<u32>,
+}
+
+impl CpuCores {
+    pub fn from_state(state: &serde_json::Value) -> Option<CpuCores> {
+        serde_json::from_value(state.get("cores")?.clone()).ok()
+    }
+
+    /// How many CPUs can be online at once. Older daemons don't report
+    /// `available`.
+    pub fn max_online(&self) -> usize {
+        if self.available.is_empty() {
+            self.present.len()
+        } else {
+            self.available.len()
+        }
+    }
+
+    /// SMT can be toggled; `forceoff` and `notsupported` are fixed.
+    pub fn smt_switchable(&self) -> bool {
+        matches!(self.smt.as_deref(), Some("on" | "off"))
+    }
+}
+
+/// Index of `current` in `options`, for initialising a dropdown.
+pub fn position(options: &[String], current: Option<&str>) -> Option<u32> {
+    let current = current?;
+    options.iter().position(|o| o == current).map(|i| i as u32)
+}
@@ -0,0 +1,303 @@
+//! Display mode switching through the compositor: `wlr-randr` on wlroots
+//! compositors, or gamescope's root window properties when nested in it.
+
+use std::path::Path;
+use std::process::Command;
+
+const DRM_CLASS: &str = "/sys/class/drm";
+
+/// Spacing of the rates offered inside the panel's refresh range.
+const GAMESCOPE_RATE_STEP: u32 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mode {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_hz: f32,
+    pub current: bool,
+    pub preferred: bool,
+}
+
+impl Mode {
+    pub fn resolution(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// `60Hz`, or `59.94Hz` for fractional rates.
+    pub fn refresh_label(&self) -> String {
+        let hz = self.refresh_hz;
+        if (hz - hz.round()).abs() < 0.05 {
+            format!("{hz:.0}Hz")
+        } else {
+            format!("{hz:.2}Hz")
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Output {
+    pub name: String,
+    pub enabled: bool,
+    pub modes: Vec<Mode>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Backend {
+    /// wlr-output-management through `wlr-randr`, driving one output.
+    WlrRandr { output: String },
+    /// gamescope only switches the refresh rate; the output resolution is
+    /// fixed and games pick their own.
+    Gamescope,
+}
+
+pub fn find_program(name: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| dir.join(name).is_file())
+    })
+}
+
+pub fn under_gamescope() -> bool {
+    std::env::var_os("GAMESCOPE_WAYLAND_DISPLAY").is_some()
+        || std::env::var("XDG_CURRENT_DESKTOP").is_ok_and(|d| d.eq_ignore_ascii_case("gamescope"))
+}
+
+/// Parse `wlr-randr` output:
+///
+/// ```text
+/// eDP-1 "Valve Corporation ANX7530 U 0x00000001 (eDP-1)"
+///   Enabled: yes
+///   Modes:
+///     1920x1080 px, 60.000000 Hz (preferred, current)
+///     1280x720 px, 60.000000 Hz
+/// ```
+pub fn parse_wlr_randr(text: &str) -> Vec<Output> {
+    let mut outputs: Vec<Output> = Vec::new();
+    for line in text.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            if let Some(name) = line.split_whitespace().next() {
+                outputs.push(Output { name: name.to_string(), enabled: true, modes: Vec::new() });
+            }
+            continue;
+        }
+        let Some(output) = outputs.last_mut() else {
+            continue;
+        };
+        let line = line.trim();
+        if let Some(v) = line.strip_prefix("Enabled:") {
+            output.enabled = v.trim() == "yes";
+        } else if let Some(mode) = parse_wlr_mode(line) {
+            output.modes.push(mode);
+        }
+    }
+    outputs
+}
+
+fn parse_wlr_mode(line: &str) -> Option<Mode> {
+    let (size, rest) = line.split_once(" px, ")?;
+    let (w, h) = size.split_once('x')?;
+    let (hz, flags) = rest.split_once(" Hz").unwrap_or((rest, ""));
+    Some(Mode {
+        width: w.parse().ok()?,
+        height: h.parse().ok()?,
+        refresh_hz: hz.trim().parse().ok()?,
+        current: flags.contains("current"),
+        preferred: flags.contains("preferred"),
+    })
+}
+
+/// The built-in panel if there is one, otherwise the first enabled output.
+fn internal_output(outputs: &[Output]) -> Option<&Output> {
+    outputs
+        .iter()
+        .find(|o| o.enabled && (o.name.starts_with("eDP") || o.name.starts_with("DSI")))
+        .or_else(|| outputs.iter().find(|o| o.enabled))
+}
+
+fn wlr_randr_outputs() -> Vec<Output> {
+    match Command::new("wlr-randr").output() {
+        Ok(out) if out.status.success() => parse_wlr_randr(&String::from_utf8_lossy(&out.stdout)),
+        Ok(out) => {
+            eprintln!("wlr-randr failed: {}", String::from_utf8_lossy(&out.stderr).trim());
+            Vec::new()
+        }
+        Err(e) => {
+            eprintln!("Failed to run wlr-randr: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Parse `xprop -root NAME` output such as `NAME(CARDINAL) = 60`.
+pub fn parse_xprop_cardinal(text: &str) -> Option<u32> {
+    text.split_once('=')?.1.split(',').next()?.trim().parse().ok()
+}
+
+/// Read a CARDINAL property from gamescope's root window.
+pub fn xprop_get(name: &str) -> Option<u32> {
+    let out = Command::new("xprop").args(["-root", name]).output().ok()?;
+    parse_xprop_cardinal(&String::from_utf8_lossy(&out.stdout))
+}
+
+/// Set a CARDINAL property on gamescope's root window.
+pub fn xprop_set(name: &str, value: u32) -> Result<(), String> {
+    xprop_set_values(name, &[value])
+}
+
+/// Set a CARDINAL[] property on gamescope's root window.
+pub fn xprop_set_values(name: &str, values: &[u32]) -> Result<(), String> {
+    let format = format!("32{}", "c".repeat(values.len()));
+    let value: Vec<String> = values.iter().map(u32::to_string).collect();
+    run("xprop", &["-root", "-f", name, &format, "-set", name, &value.join(",")])
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let out = Command::new(program).args(args).output().map_err(|e| e.to_string())?;
+    if !out.status.success() {
+        return Err(format!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Refresh rates of the modes in an EDID's detailed timing descriptors,
+/// plus every [`GAMESCOPE_RATE_STEP`] Hz inside its display range limits.
+pub fn parse_edid_rates(edid: &[u8]) -> Vec<u32> {
+    let mut rates = Vec::new();
+    for d in edid.get(54..126).into_iter().flat_map(|b| b.chunks_exact(18)) {
+        let clock = u16::from_le_bytes([d[0], d[1]]) as u64 * 10_000;
+        if clock != 0 {
+            let h_total =
+                (d[2] as u64 | (d[4] as u64 >> 4) << 8) + (d[3] as u64 | (d[4] as u64 & 0xf) << 8);
+            let v_total =
+                (d[5] as u64 | (d[7] as u64 >> 4) << 8) + (d[6] as u64 | (d[7] as u64 & 0xf) << 8);
+            if h_total * v_total != 0 {
+                rates.push((clock as f64 / (h_total * v_total) as f64).round() as u32);
+            }
+        } else if d[3] == 0xfd {
+            // Display range limits; bits 0 and 1 of byte 4 add 255 to the
+            // minimum and maximum vertical rates.
+            let min = d[5] as u32 + if d[4] & 0x01 != 0 { 255 } else { 0 };
+            let max = d[6] as u32 + if d[4] & 0x02 != 0 { 255 } else { 0 };
+            if min <= max {
+                let first = min.div_ceil(GAMESCOPE_RATE_STEP) * GAMESCOPE_RATE_STEP;
+                rates.push(min);
+                rates.extend((first..=max).step_by(GAMESCOPE_RATE_STEP as usize));
+                rates.push(max);
+            }
+        }
+    }
+    rates.retain(|&r| r > 0);
+    rates.sort_unstable();
+    rates.dedup();
+    rates
+}
+
+/// Rates from the EDID of the connected built-in panel.
+fn panel_rates(drm: &Path) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir(drm) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| {
+            let name = e.file_name();
+            let name = name.to_string_lossy();
+            name.contains("-eDP-") || name.contains("-DSI-")
+        })
+        .filter(|e| {
+            std::fs::read_to_string(e.path().join("status")).is_ok_and(|s| s.trim() == "connected")
+        })
+        .find_map(|e| {
+            let rates = parse_edid_rates(&std::fs::read(e.path().join("edid")).ok()?);
+            (!rates.is_empty()).then_some(rates)
+        })
+        .unwrap_or_default()
+}
+
+/// Rates gamescope may switch to. gamescope does not list them, so they
+/// come from the panel's EDID. Override with
+/// `LOKI_GAMESCOPE_REFRESH_RATES=40,45,50,55,60`.
+fn gamescope_rates() -> Vec<u32> {
+    std::env::var("LOKI_GAMESCOPE_REFRESH_RATES")
+        .ok()
+        .map(|v| v.split(',').filter_map(|r| r.trim().parse().ok()).collect::<Vec<u32>>())
+        .filter(|r| !r.is_empty())
+        .unwrap_or_else(|| panel_rates(Path::new(DRM_CLASS)))
+}
+
+impl Backend {
+    pub fn detect() -> Option<Backend> {
+        if under_gamescope() && find_program("xprop") {
+            return Some(Backend::Gamescope);
+        }
+        if find_program("wlr-randr") {
+            let outputs = wlr_randr_outputs();
+            let output = internal_output(&outputs)?;
+            return Some(Backend::WlrRandr { output: output.name.clone() });
+        }
+        None
+    }
+
+    pub fn can_set_resolution(&self) -> bool {
+        matches!(self, Backend::WlrRandr { .. })
+    }
+
+    /// Modes of the controlled output. Under gamescope the resolution is
+    /// reported as 0x0.
+    pub fn modes(&self) -> Vec<Mode> {
+        match self {
+            Backend::WlrRandr { output } => wlr_randr_outputs()
+                .into_iter()
+                .find(|o| &o.name == output)
+                .map(|o| o.modes)
+                .unwrap_or_default(),
+            Backend::Gamescope => {
+                let current = xprop_get("GAMESCOPE_DISPLAY_REFRESH_RATE_FEEDBACK");
+                let mut rates = gamescope_rates();
+                if let Some(c) = current.filter(|c| !rates.contains(c)) {
+                    rates.push(c);
+                    rates.sort_unstable();
+                }
+                rates
+                    .into_iter()
+                    .map(|hz| Mode {
+                        width: 0,
+                        height: 0,
+                        refresh_hz: hz as f32,
+                        current: current == Some(hz),
+                        preferred: false,
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn apply(&self, mode: &Mode) -> Result<(), String> {
+        match self {
+            Backend::WlrRandr { output } => {
+                let spec = format!("{}x{}@{:.6}Hz", mode.width, mode.height, mode.refresh_hz);
+                run("wlr-randr", &["--output", output, "--mode", &spec])
+            }
+            Backend::Gamescope => {
+                xprop_set("GAMESCOPE_DYNAMIC_REFRESH", mode.refresh_hz.round() as u32)
+            }
+        }
+    }
+}
+
+/// Distinct resolutions, largest first.
+pub fn resolutions(modes: &[Mode]) -> Vec<(u32, u32)> {
+    let mut res: Vec<(u32, u32)> = modes.iter().map(Mode::resolution).collect();
+    res.sort_unstable_by(|a, b| (b.0 * b.1).cmp(&(a.0 * a.1)).then(b.cmp(a)));
+    res.dedup();
+    res
+}
+
+/// Modes at `resolution`, lowest refresh rate first.
+pub fn rates_for(modes: &[Mode], resolution: (u32, u32)) -> Vec<Mode> {
+    let mut rates: Vec<Mode> = modes.iter().filter(|m| m.resolution() == resolution).cloned().collect();
+    rates.sort_by(|a, b| a.refresh_hz.total_cmp(&b.refresh_hz));
+    rates
+}
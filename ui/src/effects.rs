@@ -0,0 +1,73 @@
+//! Software RGB effects. The daemon animates the LEDs, so an effect keeps
+//! running after the panel closes.
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::client::{daemon_send, request_async};
+
+/// Effect names as the daemon knows them, with their labels.
+pub const KINDS: [(&str, &str); 4] = [
+    ("rainbow", "Rainbow"),
+    ("breathing", "Breathing"),
+    ("pulse", "Pulse"),
+    ("strobe", "Strobe"),
+];
+pub const SPEED_RANGE: (f64, f64) = (0.25, 4.0);
+
+/// Whether the effect named `kind` takes colours; rainbow picks its own.
+pub fn uses_colors(kind: &str) -> bool {
+    kind != "rainbow"
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Effect {
+    /// `off` or one of [`KINDS`].
+    pub effect: String,
+    pub speed: f64,
+    pub colors: Vec<[u8; 3]>,
+    pub brightness: u8,
+}
+
+impl Default for Effect {
+    fn default() -> Effect {
+        Effect {
+            effect: "off".into(),
+            speed: 1.0,
+            colors: vec![[255, 0, 0], [0, 0, 255]],
+            brightness: 255,
+        }
+    }
+}
+
+impl Effect {
+    pub fn is_on(&self) -> bool {
+        self.effect != "off"
+    }
+
+    pub fn uses_colors(&self) -> bool {
+        uses_colors(&self.effect)
+    }
+
+    /// Position of the effect in [`KINDS`], if it is on.
+    pub fn kind_index(&self) -> Option<usize> {
+        KINDS.iter().position(|(name, _)| *name == self.effect)
+    }
+}
+
+/// The daemon's effect, or `None` if it cannot be asked.
+pub async fn current() -> Option<Effect> {
+    let data = request_async(json!({"cmd": "get_rgb_effect"})).await?;
+    serde_json::from_value(data).ok()
+}
+
+/// Start or change the effect. Setting the LEDs with `set_rgb` stops it.
+pub fn apply(effect: &Effect) {
+    daemon_send(json!({
+        "cmd": "set_rgb_effect",
+        "effect": effect.effect,
+        "speed": effect.speed,
+        "colors": effect.colors,
+        "brightness": effect.brightness,
+    }));
+}
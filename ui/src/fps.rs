@@ -0,0 +1,105 @@
+//! Framerate cap: gamescope's FPS limit when nested in it, otherwise
+//! MangoHud's `fps_limit`, which MangoHud picks up when its config changes.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::display;
+
+const GAMESCOPE_ATOM: &str = "GAMESCOPE_FPS_LIMIT";
+const MANGOHUD_KEY: &str = "fps_limit";
+
+#[derive(Clone, Debug)]
+pub enum Limiter {
+    Gamescope,
+    MangoHud { config: PathBuf },
+}
+
+/// `$MANGOHUD_CONFIGFILE`, or `MangoHud/MangoHud.conf` in the user's config
+/// directory.
+fn mangohud_config() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("MANGOHUD_CONFIGFILE") {
+        return Some(PathBuf::from(path));
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("MangoHud/MangoHud.conf"))
+}
+
+/// First value of `key=` in a MangoHud config. `fps_limit` may hold a list
+/// of limits to cycle through; the first is the active one.
+pub fn config_value(text: &str, key: &str) -> Option<u32> {
+    text.lines().find_map(|line| {
+        let (k, v) = line.split_once('=')?;
+        if k.trim() != key {
+            return None;
+        }
+        v.split(',').next()?.trim().parse().ok()
+    })
+}
+
+/// `text` with the first `key=` line set to `value`, appended if there is
+/// none. Comments and later duplicates are left as they are.
+pub fn set_config_value(text: &str, key: &str, value: u32) -> String {
+    let is_key = |line: &str| line.split_once('=').is_some_and(|(k, _)| k.trim() == key);
+    let mut out = String::new();
+    let mut replaced = false;
+    for line in text.lines() {
+        if !replaced && is_key(line) {
+            out.push_str(&format!("{key}={value}\n"));
+            replaced = true;
+        } else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    if !replaced {
+        out.push_str(&format!("{key}={value}\n"));
+    }
+    out
+}
+
+/// Caps at the full, half and third of `refresh_hz`.
+pub fn presets(refresh_hz: f32) -> Vec<u32> {
+    let mut presets: Vec<u32> = [1.0, 2.0, 3.0]
+        .iter()
+        .map(|d| (refresh_hz / d).round() as u32)
+        .filter(|&fps| fps > 0)
+        .collect();
+    presets.dedup();
+    presets
+}
+
+impl Limiter {
+    pub fn detect() -> Option<Limiter> {
+        if display::under_gamescope() {
+            return Some(Limiter::Gamescope);
+        }
+        // Only edit a config the user already has, never create one.
+        mangohud_config().filter(|c| c.is_file()).map(|config| Limiter::MangoHud { config })
+    }
+
+    /// Current cap, `Some(0)` when uncapped.
+    pub fn current(&self) -> Option<u32> {
+        match self {
+            Limiter::Gamescope => Some(display::xprop_get(GAMESCOPE_ATOM).unwrap_or(0)),
+            Limiter::MangoHud { config } => {
+                let text = fs::read_to_string(config).unwrap_or_default();
+                Some(config_value(&text, MANGOHUD_KEY).unwrap_or(0))
+            }
+        }
+    }
+
+    /// Cap games at `fps`; 0 removes the cap.
+    pub fn set(&self, fps: u32) -> Result<(), String> {
+        match self {
+            Limiter::Gamescope => display::xprop_set(GAMESCOPE_ATOM, fps),
+            Limiter::MangoHud { config } => {
+                let text = fs::read_to_string(config).map_err(|e| e.to_string())?;
+                fs::write(config, set_config_value(&text, MANGOHUD_KEY, fps))
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use serde::Deserialize;
+
+/// Performance levels offered in the panel, a subset of what amdgpu accepts.
+pub const PERFORMANCE_LEVELS: [&str; 4] = ["auto", "low", "high", "manual"];
+
+/// One amdgpu card from the daemon's `get_state` (`gpus` section).
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Gpu {
+    pub card: String,
+    pub performance_level: Option<String>,
+    pub power_profile: Option<String>,
+    #[serde(default)]
+    pub available_profiles: Vec<String>,
+    pub sclk_mhz: Option<u32>,
+    pub min_mhz: Option<u32>,
+    pub max_mhz: Option<u32>,
+    pub range_min_mhz: Option<u32>,
+    pub range_max_mhz: Option<u32>,
+}
+
+impl Gpu {
+    /// The first card, which is the one the panel controls.
+    pub fn from_state(state: &serde_json::Value) -> Option<Gpu> {
+        let gpus: Vec<Gpu> = serde_json::from_value(state.get("gpus")?.clone()).ok()?;
+        gpus.into_iter().next()
+    }
+
+    /// `POWER_SAVING` -> `Power saving`.
+    pub fn profile_label(name: &str) -> String {
+        let lower = name.replace('_', " ").to_lowercase();
+        let mut chars = lower.chars();
+        match chars.next() {
+            Some(c) => c.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    }
+}
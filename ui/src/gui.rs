@@ -7,17 +7,26 @@ use gtk4 as gtk;
 use gtk4_layer_shell::{self as layer_shell, LayerShell};
 use libc;
 use serde_json::json;
-use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::UnixStream, runtime::Runtime};
 use std::cell::{Cell, RefCell};
 use std::fs;
-use std::process::Command;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
 
-static BRIGHTNESS_PATH: OnceLock<String> = OnceLock::new();
-static MAX_BRIGHTNESS: OnceLock<u32> = OnceLock::new();
-static DEFAULT_SINK: OnceLock<String> = OnceLock::new();
+use crate::audio::{Audio, Device as AudioDevice, Mixer};
+use crate::backlight::{self, Backlight};
+use crate::bluetooth::{self, Bluetooth};
+use crate::client::{self, daemon_send, Handshake};
+use crate::cpu::{self, CpuCores, CpuFreq};
+use crate::display::{self, Mode};
+use crate::effects::{self, Effect};
+use crate::fps::{self, Limiter};
+use crate::gpu::{self, Gpu};
+use crate::nightlight::{self, Config as NightLightConfig};
+use crate::rfkill;
+use crate::telemetry::{self, Metric, Monitor};
+use crate::wifi::{self, Wifi};
+
 static PWM_BASE: OnceLock<Option<String>> = OnceLock::new();
 
 #[derive(Clone, Copy)]
@@ -72,89 +81,6 @@ static AGGRESSIVE_CURVE: [FanPoint; 5] = [
     },
 ];
 
-fn init_backlight() {
-    if MAX_BRIGHTNESS.get().is_some() && BRIGHTNESS_PATH.get().is_some() {
-        return;
-    }
-    let dir_iter = match fs::read_dir("/sys/class/backlight") {
-        Ok(it) => it,
-        Err(e) => {
-            eprintln!("Failed to read /sys/class/backlight: {}", e);
-            return;
-        }
-    };
-    let entry = match dir_iter.into_iter().next() {
-        Some(Ok(e)) => e.path(),
-        Some(Err(e)) => {
-            eprintln!("Error reading backlight entry: {}", e);
-            return;
-        }
-        None => {
-            eprintln!("No backlight device found");
-            return;
-        }
-    };
-
-    let max_path = entry.join("max_brightness");
-    match fs::read_to_string(&max_path) {
-        Ok(s) => match s.trim().parse::<u32>() {
-            Ok(v) => {
-                let _ = MAX_BRIGHTNESS.set(v);
-                let _ =
-                    BRIGHTNESS_PATH.set(entry.join("brightness").to_string_lossy().into_owned());
-            }
-            Err(e) => eprintln!("Failed to parse {}: {}", max_path.display(), e),
-        },
-        Err(e) => eprintln!("Failed to read {}: {}", max_path.display(), e),
-    }
-}
-
-fn read_max_brightness() -> u32 {
-    init_backlight();
-    MAX_BRIGHTNESS.get().copied().unwrap_or(100)
-}
-
-fn write_brightness(value: u32) {
-    init_backlight();
-    if let Some(path) = BRIGHTNESS_PATH.get() {
-        daemon_send(json!({"cmd":"write","path":path,"value":value.to_string()}));
-    } else {
-        eprintln!("Backlight brightness path unavailable");
-    }
-}
-
-fn default_sink() -> &'static str {
-    DEFAULT_SINK
-        .get_or_init(|| match Command::new("pactl").arg("info").output() {
-            Ok(out) => {
-                if let Ok(text) = String::from_utf8(out.stdout) {
-                    for line in text.lines() {
-                        if let Some(rest) = line.strip_prefix("Default Sink:") {
-                            return rest.trim().to_string();
-                        }
-                    }
-                }
-                "@DEFAULT_SINK@".to_string()
-            }
-            Err(e) => {
-                eprintln!("Failed to run pactl info: {}", e);
-                "@DEFAULT_SINK@".to_string()
-            }
-        })
-        .as_str()
-}
-
-fn rfkill_blocked(kind: &str) -> Option<bool> {
-    let out = Command::new("rfkill").args(&["list", kind]).output().ok()?;
-    let text = String::from_utf8_lossy(&out.stdout);
-    for line in text.lines() {
-        if let Some(rest) = line.trim().strip_prefix("Soft blocked:") {
-            return Some(rest.trim() == "yes");
-        }
-    }
-    None
-}
-
 fn find_aynec_hwmon() -> Option<String> {
     eprintln!("Scanning /sys/class/hwmon for aynec...");
     let dir_iter = match fs::read_dir("/sys/class/hwmon") {
@@ -191,21 +117,17 @@ fn write_to_sysfs(path: &str, value: impl AsRef<str>) {
     daemon_send(json!({"cmd":"write","path":path,"value":value.as_ref()}));
 }
 
-const RGB_BASE: &str = "/sys/class/leds/ayn:rgb:joystick_rings";
+const RGB_LED: &str = "ayn:rgb:joystick_rings";
 
-fn rgb_set_mode(mode: u8) {
-    write_to_sysfs(&format!("{}/led_mode", RGB_BASE), mode.to_string());
+/// Set the LEDs directly; the daemon stops any running effect first.
+fn rgb_set(mode: u8, brightness: u8, (r, g, b): (u8, u8, u8)) {
+    daemon_send(json!({"cmd":"set_rgb","mode":mode,"brightness":brightness,"color":[r, g, b]}));
 }
 
-fn rgb_set_brightness(val: u8) {
-    write_to_sysfs(&format!("{}/brightness", RGB_BASE), val.to_string());
-}
-
-fn rgb_set_intensity(r: u8, g: u8, b: u8) {
-    write_to_sysfs(
-        &format!("{}/multi_intensity", RGB_BASE),
-        format!("{} {} {}", r, g, b),
-    );
+/// Hand the LEDs to the firmware breathing effect. It keeps its own colour,
+/// so the daemon writes only `led_mode` for mode 0.
+fn rgb_breathe() {
+    rgb_set(0, 0, (0, 0, 0));
 }
 
 fn hsv_to_rgb(h: f64, s: f64, v: f64) -> (u8, u8, u8) {
@@ -263,6 +185,1138 @@ fn eval_curve(curve: &[FanPoint; 5], temp: f32) -> f32 {
     curve[curve.len() - 1].percent
 }
 
+/// Run `work` and re-read the output's modes on a worker thread, since
+/// `wlr-randr` and `xprop` can stall; `then` gets the modes back on the GTK
+/// thread.
+fn display_task(
+    backend: &display::Backend,
+    work: impl FnOnce(&display::Backend) + Send + 'static,
+    then: impl Fn(Vec<Mode>) + 'static,
+) {
+    let fetched = Arc::new(Mutex::new(None));
+    {
+        let fetched = fetched.clone();
+        let backend = backend.clone();
+        client::tokio_rt().spawn_blocking(move || {
+            work(&backend);
+            *fetched.lock().unwrap() = Some(backend.modes());
+        });
+    }
+    glib::timeout_add_local(Duration::from_millis(50), move || {
+        match fetched.lock().unwrap().take() {
+            Some(modes) => {
+                then(modes);
+                glib::ControlFlow::Break
+            }
+            None => glib::ControlFlow::Continue,
+        }
+    });
+}
+
+/// Replace the refresh rate buttons with the modes available at
+/// `resolution`, highlighting the current one. `on_refresh` is told the
+/// current rate after every rebuild.
+fn fill_refresh_row(
+    row: &gtk::Box,
+    backend: &Rc<display::Backend>,
+    modes: &[Mode],
+    resolution: (u32, u32),
+    on_refresh: &Rc<dyn Fn(f32)>,
+) {
+    while let Some(child) = row.first_child() {
+        row.remove(&child);
+    }
+    let rates = display::rates_for(modes, resolution);
+    if let Some(current) = rates.iter().find(|m| m.current) {
+        on_refresh(current.refresh_hz);
+    }
+    for mode in rates {
+        let button = gtk::Button::with_label(&mode.refresh_label());
+        if mode.current {
+            button.add_css_class("suggested-action");
+        }
+        let backend = backend.clone();
+        let on_refresh = on_refresh.clone();
+        button.connect_clicked(move |clicked| {
+            let Some(row) = clicked.parent().and_downcast::<gtk::Box>() else {
+                return;
+            };
+            row.set_sensitive(false);
+            let backend2 = backend.clone();
+            let on_refresh = on_refresh.clone();
+            let res = mode.resolution();
+            let mode = mode.clone();
+            let set = move |backend: &display::Backend| {
+                if let Err(e) = backend.apply(&mode) {
+                    eprintln!("Failed to set display mode: {}", e);
+                }
+            };
+            display_task(&backend, set, move |modes| {
+                row.set_sensitive(true);
+                fill_refresh_row(&row, &backend2, &modes, res, &on_refresh);
+            });
+        });
+        row.append(&button);
+    }
+}
+
+/// Replace the FPS cap buttons with "Off" plus the full, half and third of
+/// `refresh_hz`, highlighting the active cap.
+fn fill_fps_row(row: &gtk::Box, limiter: &Rc<Limiter>, refresh_hz: f32) {
+    while let Some(child) = row.first_child() {
+        row.remove(&child);
+    }
+    row.append(&gtk::Label::new(Some("FPS limit:")));
+    let current = limiter.current();
+    for fps in std::iter::once(0).chain(fps::presets(refresh_hz)) {
+        let label = if fps == 0 { "Off".to_string() } else { fps.to_string() };
+        let button = gtk::Button::with_label(&label);
+        if current == Some(fps) {
+            button.add_css_class("suggested-action");
+        }
+        let limiter = limiter.clone();
+        button.connect_clicked(move |clicked| {
+            if let Err(e) = limiter.set(fps) {
+                eprintln!("Failed to set FPS limit: {}", e);
+                return;
+            }
+            // Move the highlight to the clicked preset.
+            let mut child = clicked.parent().and_then(|p| p.first_child());
+            while let Some(widget) = child {
+                if widget == *clicked.upcast_ref::<gtk::Widget>() {
+                    widget.add_css_class("suggested-action");
+                } else {
+                    widget.remove_css_class("suggested-action");
+                }
+                child = widget.next_sibling();
+            }
+        });
+        row.append(&button);
+    }
+}
+
+/// Night light switch, temperature slider and schedule. Settings are saved
+/// to the user's config; a schedule is followed by the background scheduler.
+fn build_nightlight_section(backend: nightlight::Backend) -> gtk::Box {
+    let section = gtk::Box::new(Orientation::Vertical, 8);
+    let config = Rc::new(RefCell::new(NightLightConfig::load()));
+
+    // Hand a schedule to the scheduler, restarting it so it picks up the
+    // change when `restart` is set; otherwise apply the setting directly.
+    let update: Rc<dyn Fn(bool)> = {
+        let config = config.clone();
+        Rc::new(move |restart| {
+            let config = config.borrow();
+            let result = if config.enabled && config.scheduled {
+                nightlight::start_scheduler(restart)
+            } else {
+                nightlight::stop_scheduler();
+                backend.apply(config.target())
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to set night light: {}", e);
+            }
+        })
+    };
+    // Save and apply once the controls settle, so dragging the slider
+    // doesn't restart the gamma helper for every step.
+    let pending = Rc::new(RefCell::new(None::<SourceId>));
+    let changed: Rc<dyn Fn()> = {
+        let config = config.clone();
+        let update = update.clone();
+        Rc::new(move || {
+            if pending.borrow().is_none() {
+                let config = config.clone();
+                let update = update.clone();
+                let pending_clone = pending.clone();
+                let id = glib::timeout_add_local(Duration::from_millis(300), move || {
+                    config.borrow().save();
+                    update(true);
+                    pending_clone.borrow_mut().take();
+                    glib::ControlFlow::Break
+                });
+                *pending.borrow_mut() = Some(id);
+            }
+        })
+    };
+
+    // On/off and temperature
+    let row = gtk::Box::new(Orientation::Horizontal, 8);
+    row.append(&gtk::Label::new(Some("Night light:")));
+    let temp = gtk::Scale::with_range(
+        Orientation::Horizontal,
+        nightlight::MIN_K as f64,
+        nightlight::NEUTRAL_K as f64,
+        100.0,
+    );
+    // Warmer to the right.
+    temp.set_inverted(true);
+    temp.set_hexpand(true);
+    temp.set_draw_value(true);
+    temp.set_digits(0);
+    temp.set_value(config.borrow().temperature as f64);
+    {
+        let config = config.clone();
+        let changed = changed.clone();
+        temp.connect_value_changed(move |s| {
+            config.borrow_mut().temperature = s.value().round() as u32;
+            changed();
+        });
+    }
+    let switch = gtk::Switch::new();
+    switch.set_active(config.borrow().enabled);
+    switch.set_valign(Align::Center);
+    {
+        let config = config.clone();
+        let changed = changed.clone();
+        switch.connect_active_notify(move |sw| {
+            config.borrow_mut().enabled = sw.is_active();
+            changed();
+        });
+    }
+    row.append(&temp);
+    row.append(&switch);
+    section.append(&row);
+
+    // Schedule: sunset to sunrise with a configured location, otherwise
+    // fixed local times.
+    let row = gtk::Box::new(Orientation::Horizontal, 8);
+    let scheduled = gtk::CheckButton::with_label("Schedule");
+    scheduled.set_active(config.borrow().scheduled);
+    {
+        let config = config.clone();
+        let changed = changed.clone();
+        scheduled.connect_toggled(move |b| {
+            config.borrow_mut().scheduled = b.is_active();
+            changed();
+        });
+    }
+    row.append(&scheduled);
+    let has_location = {
+        let c = config.borrow();
+        c.latitude.is_some() && c.longitude.is_some()
+    };
+    if has_location {
+        row.append(&gtk::Label::new(Some("Sunset to sunrise")));
+    } else {
+        let start = gtk::Entry::new();
+        let end = gtk::Entry::new();
+        start.set_text(&nightlight::format_hhmm(config.borrow().start_min));
+        end.set_text(&nightlight::format_hhmm(config.borrow().end_min));
+        for (entry, is_start) in [(&start, true), (&end, false)] {
+            entry.set_width_chars(5);
+            entry.set_max_length(5);
+            let config = config.clone();
+            let changed = changed.clone();
+            entry.connect_changed(move |e| {
+                let Some(min) = nightlight::parse_hhmm(&e.text()) else {
+                    e.add_css_class("error");
+                    return;
+                };
+                e.remove_css_class("error");
+                {
+                    let mut c = config.borrow_mut();
+                    if is_start {
+                        c.start_min = min;
+                    } else {
+                        c.end_min = min;
+                    }
+                }
+                changed();
+            });
+        }
+        row.append(&start);
+        row.append(&gtk::Label::new(Some("to")));
+        row.append(&end);
+    }
+    section.append(&row);
+
+    update(false);
+    section
+}
+
+/// A dropdown of sinks or sources, kept in step with the mixer and showing
+/// the current default. Returns the row and its refresh function.
+fn build_device_picker(
+    title: &str,
+    devices: fn(&Mixer) -> Vec<&AudioDevice>,
+    default: fn(&Mixer) -> Option<&str>,
+    on_pick: impl Fn(&str) + 'static,
+) -> (gtk::Box, Box<dyn Fn(Option<&Mixer>)>) {
+    let row = gtk::Box::new(Orientation::Horizontal, 8);
+    let labels = gtk::StringList::new(&[]);
+    let dropdown = gtk::DropDown::new(Some(labels.clone()), None::<gtk::Expression>);
+    dropdown.set_hexpand(true);
+    dropdown.set_sensitive(false);
+    row.append(&gtk::Label::new(Some(title)));
+    row.append(&dropdown);
+
+    // Name and description behind each entry.
+    let listed: Rc<RefCell<Vec<(String, String)>>> = Rc::default();
+    // Set while the list is rebuilt, so it isn't taken as a pick.
+    let following = Rc::new(Cell::new(false));
+    {
+        let listed = listed.clone();
+        let following = following.clone();
+        dropdown.connect_selected_notify(move |dd| {
+            if following.get() {
+                return;
+            }
+            if let Some((name, _)) = listed.borrow().get(dd.selected() as usize) {
+                on_pick(name);
+            }
+        });
+    }
+    let update = {
+        let dropdown = dropdown.clone();
+        move |mixer: Option<&Mixer>| {
+            dropdown.set_sensitive(mixer.is_some());
+            let Some(mixer) = mixer else {
+                return;
+            };
+            let entries: Vec<(String, String)> = devices(mixer)
+                .iter()
+                .map(|d| (d.name.clone(), d.description.clone()))
+                .collect();
+            following.set(true);
+            if *listed.borrow() != entries {
+                let names: Vec<&str> = entries.iter().map(|(_, desc)| desc.as_str()).collect();
+                labels.splice(0, labels.n_items(), &names);
+                *listed.borrow_mut() = entries;
+            }
+            let selected = default(mixer)
+                .and_then(|name| listed.borrow().iter().position(|(n, _)| n == name));
+            dropdown.set_selected(selected.map_or(gtk::INVALID_LIST_POSITION, |i| i as u32));
+            following.set(false);
+        }
+    };
+    (row, Box::new(update))
+}
+
+/// One application's row in the mixer.
+struct StreamRow {
+    row: gtk::Box,
+    name: gtk::Label,
+    volume: gtk::Scale,
+    mute: gtk::ToggleButton,
+    /// When the user last moved the slider, so echoes don't pull it back.
+    last_input: Rc<Cell<Option<std::time::Instant>>>,
+}
+
+/// Per-application volume for playback streams. Rows come and go with the
+/// streams. Returns the section and its refresh function.
+fn build_mixer_section(audio: Rc<Audio>) -> (gtk::Expander, Box<dyn Fn(Option<&Mixer>)>) {
+    let expander = gtk::Expander::new(Some("Mixer"));
+    let section = gtk::Box::new(Orientation::Vertical, 8);
+    let empty = gtk::Label::new(Some("Nothing is playing"));
+    empty.set_halign(Align::Start);
+    section.append(&empty);
+    expander.set_child(Some(&section));
+
+    let rows: Rc<RefCell<Vec<(u32, StreamRow)>>> = Rc::default();
+    // Set while rows follow the server, so it isn't written back.
+    let following = Rc::new(Cell::new(false));
+    let update = move |mixer: Option<&Mixer>| {
+        let streams = mixer.map_or(&[][..], |m| m.sink_inputs.as_slice());
+        let mut rows = rows.borrow_mut();
+        rows.retain(|(index, r)| {
+            let live = streams.iter().any(|s| s.index == *index);
+            if !live {
+                section.remove(&r.row);
+            }
+            live
+        });
+        for stream in streams {
+            let at = match rows.iter().position(|(index, _)| *index == stream.index) {
+                Some(at) => at,
+                None => {
+                    rows.push((stream.index, new_stream_row(&audio, stream.index, &following)));
+                    section.append(&rows.last().unwrap().1.row);
+                    rows.len() - 1
+                }
+            };
+            let r = &rows[at].1;
+            following.set(true);
+            r.name.set_text(&stream.name);
+            r.volume.set_sensitive(stream.volume_writable);
+            let dragging = r
+                .last_input
+                .get()
+                .is_some_and(|t| t.elapsed() < Duration::from_millis(300));
+            if !dragging && (stream.percent() - r.volume.value()).abs() >= 1.0 {
+                r.volume.set_value(stream.percent());
+            }
+            r.mute.set_active(stream.muted);
+            following.set(false);
+        }
+        empty.set_visible(rows.is_empty());
+    };
+    (expander, Box::new(update))
+}
+
+fn new_stream_row(audio: &Rc<Audio>, index: u32, following: &Rc<Cell<bool>>) -> StreamRow {
+    let row = gtk::Box::new(Orientation::Horizontal, 8);
+    let name = gtk::Label::new(None);
+    name.set_width_chars(12);
+    name.set_max_width_chars(12);
+    name.set_ellipsize(gtk::pango::EllipsizeMode::End);
+    name.set_xalign(0.0);
+    let volume = gtk::Scale::with_range(Orientation::Horizontal, 0.0, 100.0, 1.0);
+    volume.set_hexpand(true);
+    let mute = gtk::ToggleButton::with_label("Mute");
+    let last_input = Rc::new(Cell::new(None::<std::time::Instant>));
+    {
+        let audio = audio.clone();
+        let following = following.clone();
+        let last_input = last_input.clone();
+        volume.connect_value_changed(move |s| {
+            if following.get() {
+                return;
+            }
+            last_input.set(Some(std::time::Instant::now()));
+            audio.set_stream_volume(index, s.value());
+        });
+    }
+    {
+        let audio = audio.clone();
+        let following = following.clone();
+        mute.connect_toggled(move |btn| {
+            if !following.get() {
+                audio.set_stream_mute(index, btn.is_active());
+            }
+        });
+    }
+    row.append(&name);
+    row.append(&volume);
+    row.append(&mute);
+    StreamRow { row, name, volume, mute, last_input }
+}
+
+/// Visible and saved Wi-Fi networks. Secured networks that aren't saved ask
+/// for a password before joining.
+fn build_wifi_section(wifi: Wifi) -> gtk::Expander {
+    let expander = gtk::Expander::new(Some("Wi-Fi networks"));
+    let section = gtk::Box::new(Orientation::Vertical, 8);
+    let controls = gtk::Box::new(Orientation::Horizontal, 8);
+    let status = gtk::Label::new(Some("Looking for Wi-Fi…"));
+    status.set_hexpand(true);
+    status.set_halign(Align::Start);
+    let scan = gtk::Button::with_label("Scan");
+    let disconnect = gtk::Button::with_label("Disconnect");
+    disconnect.set_sensitive(false);
+    controls.append(&status);
+    controls.append(&scan);
+    controls.append(&disconnect);
+    let error = gtk::Label::new(None);
+    error.add_css_class("error");
+    error.set_wrap(true);
+    error.set_halign(Align::Start);
+    error.set_visible(false);
+    let list = gtk::Box::new(Orientation::Vertical, 4);
+    section.append(&controls);
+    section.append(&error);
+    section.append(&list);
+    expander.set_child(Some(&section));
+
+    // Password prompts open in the list. Rebuilding the rows would throw
+    // away a half-typed password, so new lists wait until none are open.
+    let prompting = Rc::new(Cell::new(0usize));
+    // Greys out the section until NetworkManager has answered. Any action
+    // replaces the open prompts.
+    let busy: Rc<dyn Fn()> = {
+        let controls = controls.clone();
+        let list = list.clone();
+        let error = error.clone();
+        let prompting = prompting.clone();
+        Rc::new(move || {
+            prompting.set(0);
+            error.set_visible(false);
+            controls.set_sensitive(false);
+            list.set_sensitive(false);
+        })
+    };
+    {
+        let wifi = wifi.clone();
+        let busy = busy.clone();
+        scan.connect_clicked(move |_| {
+            busy();
+            wifi.scan();
+        });
+    }
+    {
+        let wifi = wifi.clone();
+        let busy = busy.clone();
+        disconnect.connect_clicked(move |_| {
+            busy();
+            wifi.disconnect();
+        });
+    }
+    {
+        let wifi = wifi.clone();
+        expander.connect_expanded_notify(move |e| {
+            if e.is_expanded() {
+                wifi.refresh();
+            }
+        });
+    }
+
+    // The radio can be switched elsewhere, so an open section re-reads the
+    // list now and then.
+    let mut last_refresh = std::time::Instant::now();
+    let mut shown: Option<Option<wifi::Status>> = None;
+    let mut pending: Option<Option<wifi::Status>> = None;
+    let expander_ref = expander.clone();
+    glib::timeout_add_local(Duration::from_millis(200), move || {
+        let prompt_open = prompting.get() > 0;
+        if expander_ref.is_expanded()
+            && !prompt_open
+            && last_refresh.elapsed() >= Duration::from_secs(10)
+        {
+            last_refresh = std::time::Instant::now();
+            wifi.refresh();
+        }
+        if let Some(update) = wifi.take_update() {
+            if let Some(e) = update.error {
+                error.set_text(&e);
+                error.set_visible(true);
+            }
+            if update.idle {
+                controls.set_sensitive(true);
+                list.set_sensitive(true);
+            }
+            if update.status.is_some() {
+                pending = update.status;
+            }
+        }
+        if prompt_open {
+            return glib::ControlFlow::Continue;
+        }
+        let Some(current) = pending.take() else {
+            return glib::ControlFlow::Continue;
+        };
+        if shown.as_ref() == Some(&current) {
+            return glib::ControlFlow::Continue;
+        }
+        let active = current.as_ref().and_then(|s| s.networks.iter().find(|n| n.active));
+        status.set_text(&match (&current, active) {
+            (None, _) => "No Wi-Fi device".to_string(),
+            (Some(s), _) if !s.enabled => "Wi-Fi is off".to_string(),
+            (Some(_), Some(n)) => format!("Connected to {}", n.ssid),
+            (Some(_), None) => "Not connected".to_string(),
+        });
+        disconnect.set_sensitive(active.is_some());
+        scan.set_sensitive(current.as_ref().is_some_and(|s| s.enabled));
+        while let Some(child) = list.first_child() {
+            list.remove(&child);
+        }
+        for network in current.iter().flat_map(|s| &s.networks) {
+            list.append(&new_network_row(network, &wifi, &busy, &prompting));
+        }
+        shown = Some(current);
+        glib::ControlFlow::Continue
+    });
+    expander
+}
+
+fn new_network_row(
+    network: &wifi::Network,
+    wifi: &Wifi,
+    busy: &Rc<dyn Fn()>,
+    prompting: &Rc<Cell<usize>>,
+) -> gtk::Box {
+    let row = gtk::Box::new(Orientation::Vertical, 4);
+    let top = gtk::Box::new(Orientation::Horizontal, 8);
+    let name = gtk::Label::new(Some(&network.ssid));
+    name.set_hexpand(true);
+    name.set_xalign(0.0);
+    name.set_ellipsize(gtk::pango::EllipsizeMode::End);
+    if network.active {
+        name.add_css_class("heading");
+    }
+    let mut detail = if network.in_range {
+        format!("{}% · {}", network.strength, network.security_label())
+    } else {
+        "Out of range".to_string()
+    };
+    if network.known {
+        detail.push_str(" · saved");
+    }
+    let detail = gtk::Label::new(Some(&detail));
+    detail.add_css_class("dim-label");
+    top.append(&name);
+    top.append(&detail);
+
+    // Password prompt for secured networks that aren't saved yet.
+    let prompt = gtk::Box::new(Orientation::Horizontal, 8);
+    let password = gtk::PasswordEntry::new();
+    password.set_show_peek_icon(true);
+    password.set_hexpand(true);
+    let join = gtk::Button::with_label("Join");
+    prompt.append(&password);
+    prompt.append(&join);
+    prompt.set_visible(false);
+    {
+        let ssid = network.ssid.clone();
+        let wifi = wifi.clone();
+        let busy = busy.clone();
+        let password_ref = password.clone();
+        let submit = move || {
+            let text = password_ref.text();
+            if text.is_empty() {
+                return;
+            }
+            busy();
+            wifi.connect(&ssid, Some(text.as_str()));
+        };
+        let submit = Rc::new(submit);
+        let on_click = submit.clone();
+        join.connect_clicked(move |_| on_click());
+        password.connect_activate(move |_| submit());
+    }
+
+    if !network.active {
+        let connect = gtk::Button::with_label("Connect");
+        connect.set_sensitive(network.joinable());
+        if !network.known && network.security == "enterprise" {
+            connect.set_tooltip_text(Some("Set up enterprise networks in NetworkManager"));
+        }
+        let ssid = network.ssid.clone();
+        let needs_password = network.needs_password();
+        let wifi = wifi.clone();
+        let busy = busy.clone();
+        let prompt = prompt.clone();
+        let password = password.clone();
+        let prompting = prompting.clone();
+        connect.connect_clicked(move |_| {
+            if needs_password {
+                let open = !prompt.is_visible();
+                prompt.set_visible(open);
+                if open {
+                    prompting.set(prompting.get() + 1);
+                    password.grab_focus();
+                } else {
+                    prompting.set(prompting.get().saturating_sub(1));
+                }
+            } else {
+                busy();
+                wifi.connect(&ssid, None);
+            }
+        });
+        top.append(&connect);
+    }
+    if network.known {
+        let forget = gtk::Button::with_label("Forget");
+        let ssid = network.ssid.clone();
+        let wifi = wifi.clone();
+        let busy = busy.clone();
+        forget.connect_clicked(move |_| {
+            busy();
+            wifi.forget(&ssid);
+        });
+        top.append(&forget);
+    }
+    row.append(&top);
+    row.append(&prompt);
+    row
+}
+
+/// Paired and discovered Bluetooth devices. Pairing connects the device
+/// without trusting it.
+fn build_bluetooth_section(bt: Bluetooth) -> gtk::Expander {
+    let expander = gtk::Expander::new(Some("Bluetooth devices"));
+    let section = gtk::Box::new(Orientation::Vertical, 8);
+    let controls = gtk::Box::new(Orientation::Horizontal, 8);
+    let status = gtk::Label::new(Some("Looking for Bluetooth…"));
+    status.set_hexpand(true);
+    status.set_halign(Align::Start);
+    let scan = gtk::Button::with_label("Scan");
+    controls.append(&status);
+    controls.append(&scan);
+    let error = gtk::Label::new(None);
+    error.add_css_class("error");
+    error.set_wrap(true);
+    error.set_halign(Align::Start);
+    error.set_visible(false);
+    let list = gtk::Box::new(Orientation::Vertical, 4);
+    section.append(&controls);
+    section.append(&error);
+    section.append(&list);
+    expander.set_child(Some(&section));
+
+    // Greys out the section until BlueZ has answered.
+    let busy: Rc<dyn Fn()> = {
+        let controls = controls.clone();
+        let list = list.clone();
+        let error = error.clone();
+        Rc::new(move || {
+            error.set_visible(false);
+            controls.set_sensitive(false);
+            list.set_sensitive(false);
+        })
+    };
+    {
+        let bt = bt.clone();
+        let busy = busy.clone();
+        scan.connect_clicked(move |_| {
+            busy();
+            bt.scan();
+        });
+    }
+    {
+        let bt = bt.clone();
+        expander.connect_expanded_notify(move |e| {
+            if e.is_expanded() {
+                bt.refresh();
+            }
+        });
+    }
+
+    // Re-read often while discovering so new devices show up, and now and
+    // then otherwise for connections made elsewhere.
+    let mut last_refresh = std::time::Instant::now();
+    let mut shown: Option<Option<bluetooth::Status>> = None;
+    let expander_ref = expander.clone();
+    glib::timeout_add_local(Duration::from_millis(200), move || {
+        let discovering = shown
+            .as_ref()
+            .is_some_and(|s| s.as_ref().is_some_and(|s| s.discovering));
+        let every = Duration::from_secs(if discovering { 2 } else { 10 });
+        if expander_ref.is_expanded() && last_refresh.elapsed() >= every {
+            last_refresh = std::time::Instant::now();
+            bt.refresh();
+        }
+        let Some(update) = bt.take_update() else {
+            return glib::ControlFlow::Continue;
+        };
+        if let Some(e) = update.error {
+            error.set_text(&e);
+            error.set_visible(true);
+        }
+        if update.idle {
+            controls.set_sensitive(true);
+            list.set_sensitive(true);
+        }
+        let Some(current) = update.status else {
+            return glib::ControlFlow::Continue;
+        };
+        if shown.as_ref() == Some(&current) {
+            return glib::ControlFlow::Continue;
+        }
+        let connected = current
+            .iter()
+            .flat_map(|s| &s.devices)
+            .filter(|d| d.connected)
+            .count();
+        status.set_text(&match &current {
+            None => "Bluetooth unavailable".to_string(),
+            Some(s) if s.adapter.is_none() => "No Bluetooth adapter".to_string(),
+            Some(s) if !s.powered => "Bluetooth is off".to_string(),
+            Some(s) if s.discovering => "Scanning…".to_string(),
+            Some(_) if connected > 0 => format!("{connected} connected"),
+            Some(_) => "Nothing connected".to_string(),
+        });
+        scan.set_sensitive(current.as_ref().is_some_and(|s| s.powered));
+        while let Some(child) = list.first_child() {
+            list.remove(&child);
+        }
+        for device in current.iter().flat_map(|s| &s.devices) {
+            list.append(&new_bluetooth_row(device, &bt, &busy));
+        }
+        shown = Some(current);
+        glib::ControlFlow::Continue
+    });
+    expander
+}
+
+fn new_bluetooth_row(device: &bluetooth::Device, bt: &Bluetooth, busy: &Rc<dyn Fn()>) -> gtk::Box {
+    let row = gtk::Box::new(Orientation::Horizontal, 8);
+    let icon = gtk::Image::from_icon_name(device.icon.as_deref().unwrap_or("bluetooth"));
+    let labels = gtk::Box::new(Orientation::Vertical, 0);
+    labels.set_hexpand(true);
+    let name = gtk::Label::new(Some(&device.name));
+    name.set_xalign(0.0);
+    name.set_ellipsize(gtk::pango::EllipsizeMode::End);
+    let detail = gtk::Label::new(Some(&device.describe()));
+    detail.set_xalign(0.0);
+    detail.add_css_class("dim-label");
+    labels.append(&name);
+    labels.append(&detail);
+    row.append(&icon);
+    row.append(&labels);
+
+    let action = |label: &str, act: fn(&Bluetooth, &str)| {
+        let button = gtk::Button::with_label(label);
+        let address = device.address.clone();
+        let bt = bt.clone();
+        let busy = busy.clone();
+        button.connect_clicked(move |_| {
+            busy();
+            act(&bt, &address);
+        });
+        button
+    };
+    if !device.paired {
+        row.append(&action("Pair", Bluetooth::pair));
+    } else if device.connected {
+        row.append(&action("Disconnect", Bluetooth::disconnect));
+    } else {
+        row.append(&action("Connect", Bluetooth::connect));
+    }
+    if device.paired {
+        row.append(&action("Forget", Bluetooth::forget));
+    }
+    row
+}
+
+fn build_cpu_section(freq: Option<&CpuFreq>, cores: Option<&CpuCores>) -> gtk::Expander {
+    let expander = gtk::Expander::new(Some("Advanced CPU"));
+    let section = gtk::Box::new(Orientation::Vertical, 8);
+    let freq = freq.cloned().unwrap_or_default();
+
+    // Governor
+    if !freq.available_governors.is_empty() {
+        let row = gtk::Box::new(Orientation::Horizontal, 8);
+        row.append(&gtk::Label::new(Some("Governor:")));
+        let names: Vec<&str> = freq.available_governors.iter().map(String::as_str).collect();
+        let dropdown = gtk::DropDown::from_strings(&names);
+        if let Some(i) = cpu::position(&freq.available_governors, freq.governor.as_deref()) {
+            dropdown.set_selected(i);
+        }
+        let governors = freq.available_governors.clone();
+        dropdown.connect_selected_notify(move |dd| {
+            if let Some(g) = governors.get(dd.selected() as usize) {
+                daemon_send(json!({"cmd":"set_cpu_governor","governor":g}));
+            }
+        });
+        row.append(&dropdown);
+        section.append(&row);
+    }
+
+    // Energy performance preference (amd-pstate-epp)
+    if !freq.available_epp.is_empty() {
+        let row = gtk::Box::new(Orientation::Horizontal, 8);
+        row.append(&gtk::Label::new(Some("Energy preference:")));
+        let names: Vec<&str> = freq.available_epp.iter().map(String::as_str).collect();
+        let dropdown = gtk::DropDown::from_strings(&names);
+        if let Some(i) = cpu::position(&freq.available_epp, freq.epp.as_deref()) {
+            dropdown.set_selected(i);
+        }
+        let prefs = freq.available_epp.clone();
+        dropdown.connect_selected_notify(move |dd| {
+            if let Some(p) = prefs.get(dd.selected() as usize) {
+                daemon_send(json!({"cmd":"set_cpu_epp","preference":p}));
+            }
+        });
+        row.append(&dropdown);
+        section.append(&row);
+    }
+
+    // Boost
+    if let Some(enabled) = freq.boost {
+        let row = gtk::Box::new(Orientation::Horizontal, 8);
+        let label = gtk::Label::new(Some("Boost:"));
+        label.set_hexpand(true);
+        label.set_halign(Align::Start);
+        let switch = gtk::Switch::new();
+        switch.set_active(enabled);
+        switch.connect_active_notify(|sw| {
+            daemon_send(json!({"cmd":"set_cpu_boost","enabled":sw.is_active()}));
+        });
+        row.append(&label);
+        row.append(&switch);
+        section.append(&row);
+    }
+
+    // Frequency limits in MHz
+    if let (Some(hw_min), Some(hw_max)) = (freq.hw_min_khz, freq.hw_max_khz) {
+        // Round inwards, so neither end falls outside the hardware range.
+        let lo = hw_min.div_ceil(1000) as f64;
+        let hi = (hw_max / 1000) as f64;
+        let min_scale = gtk::Scale::with_range(Orientation::Horizontal, lo, hi, 100.0);
+        let max_scale = gtk::Scale::with_range(Orientation::Horizontal, lo, hi, 100.0);
+        min_scale.set_value(freq.min_khz.map_or(lo, |k| (k / 1000) as f64));
+        max_scale.set_value(freq.max_khz.map_or(hi, |k| (k / 1000) as f64));
+        for scale in [&min_scale, &max_scale] {
+            scale.set_hexpand(true);
+            scale.set_draw_value(true);
+            scale.set_digits(0);
+        }
+
+        // Each change writes every policy, so wait for the slider to settle
+        // and send only the latest limits.
+        let pending = Rc::new(RefCell::new(None::<SourceId>));
+        let send_limits = {
+            let min_scale = min_scale.clone();
+            let max_scale = max_scale.clone();
+            move || {
+                if let Some(id) = pending.borrow_mut().take() {
+                    id.remove();
+                }
+                let min_scale = min_scale.clone();
+                let max_scale = max_scale.clone();
+                let pending_clone = pending.clone();
+                let id = glib::timeout_add_local_once(Duration::from_millis(250), move || {
+                    pending_clone.borrow_mut().take();
+                    daemon_send(json!({
+                        "cmd":"set_cpu_freq_limits",
+                        "min_khz":min_scale.value().round() as u32 * 1000,
+                        "max_khz":max_scale.value().round() as u32 * 1000,
+                    }));
+                });
+                *pending.borrow_mut() = Some(id);
+            }
+        };
+        {
+            let max_scale = max_scale.clone();
+            let send = send_limits.clone();
+            min_scale.connect_value_changed(move |s| {
+                // Drag the other end along rather than sending min > max.
+                if s.value() > max_scale.value() {
+                    max_scale.set_value(s.value());
+                }
+                send();
+            });
+        }
+        {
+            let min_scale = min_scale.clone();
+            let send = send_limits.clone();
+            max_scale.connect_value_changed(move |s| {
+                if s.value() < min_scale.value() {
+                    min_scale.set_value(s.value());
+                }
+                send();
+            });
+        }
+
+        for (title, scale) in [("Min MHz:", &min_scale), ("Max MHz:", &max_scale)] {
+            let row = gtk::Box::new(Orientation::Horizontal, 8);
+            row.append(&gtk::Label::new(Some(title)));
+            row.append(scale);
+            section.append(&row);
+        }
+    }
+
+    if let Some(cores) = cores {
+        // Online CPU count; CPU0 always stays online
+        let count_row = gtk::Box::new(Orientation::Horizontal, 8);
+        count_row.append(&gtk::Label::new(Some("CPU cores:")));
+        let count = gtk::Scale::with_range(Orientation::Horizontal, 1.0, 2.0, 1.0);
+        count.set_hexpand(true);
+        count.set_draw_value(true);
+        count.set_digits(0);
+        // Last count sent or shown, so programmatic updates aren't sent back.
+        let last = Rc::new(Cell::new(0u32));
+        let show_cores = Rc::new({
+            let count_row = count_row.clone();
+            let count = count.clone();
+            let last = last.clone();
+            move |cores: &CpuCores| {
+                let total = cores.max_online();
+                let online = cores.online.len().max(1) as u32;
+                count_row.set_visible(total > 1);
+                last.set(online);
+                count.set_range(1.0, total.max(2) as f64);
+                count.set_value(online as f64);
+            }
+        });
+        show_cores(cores);
+        count.connect_value_changed(move |s| {
+            let count = s.value().round() as u32;
+            if last.replace(count) != count {
+                daemon_send(json!({"cmd":"set_online_cpus","count":count}));
+            }
+        });
+        count_row.append(&count);
+
+        // SMT. Switching it takes sibling threads on- or offline, so the
+        // count is read back afterwards.
+        if cores.smt_switchable() {
+            let row = gtk::Box::new(Orientation::Horizontal, 8);
+            let label = gtk::Label::new(Some("SMT:"));
+            label.set_hexpand(true);
+            label.set_halign(Align::Start);
+            let switch = gtk::Switch::new();
+            switch.set_active(cores.smt.as_deref() == Some("on"));
+            switch.connect_active_notify(move |sw| {
+                let enabled = sw.is_active();
+                let fetched = Arc::new(Mutex::new(None));
+                {
+                    let fetched = fetched.clone();
+                    client::tokio_rt().spawn(async move {
+                        client::request_async(json!({"cmd":"set_smt","enabled":enabled})).await;
+                        let state = client::request_async(json!({"cmd":"get_state"})).await;
+                        *fetched.lock().unwrap() =
+                            Some(state.and_then(|s| CpuCores::from_state(&s)));
+                    });
+                }
+                let show_cores = show_cores.clone();
+                glib::timeout_add_local(Duration::from_millis(100), move || {
+                    match fetched.lock().unwrap().take() {
+                        Some(cores) => {
+                            if let Some(cores) = cores {
+                                show_cores(&cores);
+                            }
+                            glib::ControlFlow::Break
+                        }
+                        None => glib::ControlFlow::Continue,
+                    }
+                });
+            });
+            row.append(&label);
+            row.append(&switch);
+            section.append(&row);
+        }
+        section.append(&count_row);
+    }
+
+    expander.set_child(Some(&section));
+    expander
+}
+
+fn build_gpu_section(gpu: &Gpu, monitor: Arc<Mutex<Monitor>>) -> gtk::Expander {
+    let expander = gtk::Expander::new(Some("GPU"));
+    let section = gtk::Box::new(Orientation::Vertical, 8);
+
+    // Live shader clock from the telemetry poller
+    let row = gtk::Box::new(Orientation::Horizontal, 8);
+    let label = gtk::Label::new(Some("Clock:"));
+    label.set_hexpand(true);
+    label.set_halign(Align::Start);
+    let clock = gtk::Label::new(Some(
+        &gpu.sclk_mhz.map_or("–".to_string(), |m| format!("{m} MHz")),
+    ));
+    row.append(&label);
+    row.append(&clock);
+    section.append(&row);
+    glib::timeout_add_local(telemetry::POLL_INTERVAL, move || {
+        if let Some(v) = monitor.lock().unwrap().latest(Metric::GpuClock) {
+            clock.set_text(&Metric::GpuClock.format(v));
+        }
+        glib::ControlFlow::Continue
+    });
+
+    // Performance level
+    let levels: Vec<String> = gpu::PERFORMANCE_LEVELS
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+    let level_row = gtk::Box::new(Orientation::Horizontal, 8);
+    level_row.append(&gtk::Label::new(Some("Performance level:")));
+    let level_dropdown = gtk::DropDown::from_strings(&gpu::PERFORMANCE_LEVELS);
+    if let Some(i) = cpu::position(&levels, gpu.performance_level.as_deref()) {
+        level_dropdown.set_selected(i);
+    }
+    {
+        let card = gpu.card.clone();
+        level_dropdown.connect_selected_notify(move |dd| {
+            if let Some(level) = levels.get(dd.selected() as usize) {
+                daemon_send(json!({"cmd":"set_gpu_performance_level","level":level,"card":card}));
+            }
+        });
+    }
+    level_row.append(&level_dropdown);
+    section.append(&level_row);
+
+    // Power profile; the daemon switches the level to manual for it
+    if !gpu.available_profiles.is_empty() {
+        let row = gtk::Box::new(Orientation::Horizontal, 8);
+        row.append(&gtk::Label::new(Some("Power profile:")));
+        let labels: Vec<String> = gpu
+            .available_profiles
+            .iter()
+            .map(|p| Gpu::profile_label(p))
+            .collect();
+        let names: Vec<&str> = labels.iter().map(String::as_str).collect();
+        let dropdown = gtk::DropDown::from_strings(&names);
+        if let Some(i) = cpu::position(&gpu.available_profiles, gpu.power_profile.as_deref()) {
+            dropdown.set_selected(i);
+        }
+        let profiles = gpu.available_profiles.clone();
+        let card = gpu.card.clone();
+        let level_dropdown = level_dropdown.clone();
+        dropdown.connect_selected_notify(move |dd| {
+            if let Some(p) = profiles.get(dd.selected() as usize) {
+                daemon_send(json!({"cmd":"set_gpu_power_profile","profile":p,"card":card}));
+                level_dropdown.set_selected(3);
+            }
+        });
+        row.append(&dropdown);
+        section.append(&row);
+    }
+
+    // Manual GFX clock limits, only with overdrive enabled
+    if let (Some(lo), Some(hi)) = (gpu.range_min_mhz, gpu.range_max_mhz) {
+        let (lo, hi) = (lo as f64, hi as f64);
+        let min_scale = gtk::Scale::with_range(Orientation::Horizontal, lo, hi, 50.0);
+        let max_scale = gtk::Scale::with_range(Orientation::Horizontal, lo, hi, 50.0);
+        min_scale.set_value(gpu.min_mhz.map_or(lo, |m| m as f64));
+        max_scale.set_value(gpu.max_mhz.map_or(hi, |m| m as f64));
+        for scale in [&min_scale, &max_scale] {
+            scale.set_hexpand(true);
+            scale.set_draw_value(true);
+            scale.set_digits(0);
+        }
+
+        // Each change is several overdrive writes, so wait for the slider to
+        // settle and send only the latest limits.
+        let pending = Rc::new(RefCell::new(None::<SourceId>));
+        let send_limits = {
+            let min_scale = min_scale.clone();
+            let max_scale = max_scale.clone();
+            let card = gpu.card.clone();
+            move || {
+                if let Some(id) = pending.borrow_mut().take() {
+                    id.remove();
+                }
+                let min_scale = min_scale.clone();
+                let max_scale = max_scale.clone();
+                let card = card.clone();
+                let pending_clone = pending.clone();
+                let id = glib::timeout_add_local_once(Duration::from_millis(250), move || {
+                    pending_clone.borrow_mut().take();
+                    daemon_send(json!({
+                        "cmd":"set_gpu_clock_limits",
+                        "min_mhz":min_scale.value().round() as u32,
+                        "max_mhz":max_scale.value().round() as u32,
+                        "card":card,
+                    }));
+                });
+                *pending.borrow_mut() = Some(id);
+            }
+        };
+        {
+            let max_scale = max_scale.clone();
+            let send = send_limits.clone();
+            min_scale.connect_value_changed(move |s| {
+                if s.value() > max_scale.value() {
+                    max_scale.set_value(s.value());
+                }
+                send();
+            });
+        }
+        {
+            let min_scale = min_scale.clone();
+            let send = send_limits.clone();
+            max_scale.connect_value_changed(move |s| {
+                if s.value() < min_scale.value() {
+                    min_scale.set_value(s.value());
+                }
+                send();
+            });
+        }
+
+        for (title, scale) in [("Min MHz:", &min_scale), ("Max MHz:", &max_scale)] {
+            let row = gtk::Box::new(Orientation::Horizontal, 8);
+            row.append(&gtk::Label::new(Some(title)));
+            row.append(scale);
+            section.append(&row);
+        }
+    }
+
+    expander.set_child(Some(&section));
+    expander
+}
+
 fn build_ui(app: &Application) {
     // Main window setup
     let window = ApplicationWindow::builder()
@@ -288,6 +1342,29 @@ fn build_ui(app: &Application) {
         }
     }
 
+    // Ask the daemon what this device supports. Without an answer every
+    // control is shown and falls back to local probing. A daemon that
+    // refuses this panel's protocol gets no controls at all.
+    let caps = match client::hello() {
+        Handshake::Ready(caps) => Some(caps),
+        Handshake::Unsupported(reason) => {
+            eprintln!("Daemon refused the handshake: {reason}");
+            let label = gtk::Label::new(Some(&format!(
+                "This control panel is too old for the running daemon ({reason}). \
+                 Update loki-control-ui."
+            )));
+            label.set_wrap(true);
+            window.set_child(Some(&label));
+            window.present();
+            return;
+        }
+        Handshake::Unknown => {
+            eprintln!("No capability information from daemon; showing all controls");
+            None
+        }
+    };
+    let state = client::get_state();
+
     // Dark theme
     if let Some(settings) = gtk::Settings::default() {
         settings.set_gtk_application_prefer_dark_theme(true);
@@ -309,70 +1386,159 @@ fn build_ui(app: &Application) {
     let row1 = gtk::Box::new(Orientation::Horizontal, 8);
     row1.set_halign(Align::Center);
 
-    // Wi-Fi toggle
+    // Radio toggles show whether the radio is on. They send explicit block
+    // states and all three follow rfkill events, whoever made the change.
     let wifi_btn = gtk::ToggleButton::with_label("Wi-Fi");
-    wifi_btn.add_css_class("circular");
-    if let Some(blocked) = rfkill_blocked("wifi") {
-        wifi_btn.set_active(blocked);
-    }
-    {
-        wifi_btn.connect_toggled(|_| {
-            daemon_send(json!({"cmd":"run","program":"rfkill","args":["toggle","wifi"]}));
-        });
-    }
-    row1.append(&wifi_btn);
-
-    // Bluetooth toggle
     let bt_btn = gtk::ToggleButton::with_label("Bluetooth");
-    bt_btn.add_css_class("circular");
-    if let Some(blocked) = rfkill_blocked("bluetooth") {
-        bt_btn.set_active(blocked);
-    }
-    {
-        bt_btn.connect_toggled(|_| {
-            daemon_send(json!({"cmd":"run","program":"rfkill","args":["toggle","bluetooth"]}));
+    let airplane_btn = gtk::ToggleButton::with_label("Airplane");
+    // Set while the buttons follow rfkill, so they don't send anything.
+    let following = Rc::new(Cell::new(false));
+    for (btn, kind) in [(&wifi_btn, "wlan"), (&bt_btn, "bluetooth")] {
+        btn.add_css_class("circular");
+        btn.set_sensitive(false);
+        let following = following.clone();
+        btn.connect_toggled(move |btn| {
+            if !following.get() {
+                daemon_send(json!({"cmd":"set_rfkill","kind":kind,"blocked":!btn.is_active()}));
+            }
         });
+        row1.append(btn);
     }
-    row1.append(&bt_btn);
-
-    // Airplane mode toggle
-    let airplane_btn = gtk::ToggleButton::with_label("Airplane");
     airplane_btn.add_css_class("circular");
-    if rfkill_blocked("wifi") == Some(true) && rfkill_blocked("bluetooth") == Some(true) {
-        airplane_btn.set_active(true);
+    airplane_btn.set_sensitive(false);
+    {
+        let following = following.clone();
+        airplane_btn.connect_toggled(move |btn| {
+            if !following.get() {
+                daemon_send(json!({"cmd":"set_rfkill","kind":"all","blocked":btn.is_active()}));
+            }
+        });
     }
+    row1.append(&airplane_btn);
     {
-        airplane_btn.connect_toggled(|btn| {
-            let args: Vec<String> = if btn.is_active() {
-                vec!["block".into(), "all".into()]
-            } else {
-                vec!["unblock".into(), "all".into()]
+        let latest = Arc::new(Mutex::new(None));
+        rfkill::spawn_watcher(latest.clone());
+        let wifi_btn = wifi_btn.clone();
+        let bt_btn = bt_btn.clone();
+        let airplane_btn = airplane_btn.clone();
+        glib::timeout_add_local(Duration::from_millis(100), move || {
+            let Some(radios) = latest.lock().unwrap().take() else {
+                return glib::ControlFlow::Continue;
             };
-            daemon_send(json!({"cmd":"run","program":"rfkill","args":args}));
+            following.set(true);
+            for (btn, kind) in [(&wifi_btn, "wlan"), (&bt_btn, "bluetooth")] {
+                let hard = radios.hard_blocked(kind);
+                btn.set_active(radios.enabled(kind));
+                btn.set_sensitive(radios.has(kind) && !hard);
+                btn.set_tooltip_text(hard.then_some("Blocked by a hardware switch"));
+            }
+            airplane_btn.set_active(radios.airplane());
+            airplane_btn.set_sensitive(!radios.0.is_empty());
+            following.set(false);
+            glib::ControlFlow::Continue
         });
     }
-    row1.append(&airplane_btn);
 
     vbox.append(&row1);
+    vbox.append(&build_wifi_section(Wifi::default()));
+    vbox.append(&build_bluetooth_section(Bluetooth::default()));
 
     // Row 2: Brightness slider + label
     let row2 = gtk::Box::new(Orientation::Horizontal, 8);
     row2.set_valign(Align::Center);
     let bright_label = gtk::Label::new(Some("Brightness:"));
     let brightness = gtk::Scale::with_range(Orientation::Horizontal, 0.0, 100.0, 1.0);
-    brightness.set_value(50.0);
     brightness.set_hexpand(true);
-    let max_brightness = read_max_brightness();
-    {
-        let max_brightness = max_brightness;
-        brightness.connect_value_changed(move |s| {
-            let pct = s.value() / 100.0;
-            let val = (pct * max_brightness as f64).round() as u32;
-            write_brightness(val);
+    if let Some(dev) = state.as_ref().and_then(Backlight::from_state) {
+        brightness.set_value(dev.percent);
+
+        // Set while the slider follows an external change, so it isn't
+        // written straight back.
+        let following = Rc::new(Cell::new(false));
+        // When the user last moved the slider. The daemon's fade steps are
+        // not followed until it has finished.
+        let last_input = Rc::new(Cell::new(None::<std::time::Instant>));
+        {
+            let following = following.clone();
+            let last_input = last_input.clone();
+            brightness.connect_value_changed(move |s| {
+                if following.get() {
+                    return;
+                }
+                last_input.set(Some(std::time::Instant::now()));
+                // The daemon maps the position onto its perceptual curve and
+                // fades to it.
+                daemon_send(json!({"cmd":"set_brightness_percent","percent":s.value()}));
+            });
+        }
+
+        let latest = Arc::new(Mutex::new(None));
+        backlight::spawn_watcher(&dev.device, latest.clone());
+        let brightness = brightness.clone();
+        glib::timeout_add_local(Duration::from_millis(100), move || {
+            let fading = last_input
+                .get()
+                .is_some_and(|t| t.elapsed() < Duration::from_millis(600));
+            if let Some(pct) = latest.lock().unwrap().take().filter(|_| !fading) {
+                // Ignore the echo of our own write.
+                if (pct - brightness.value()).abs() >= 1.0 {
+                    following.set(true);
+                    brightness.set_value(pct);
+                    following.set(false);
+                }
+            }
+            glib::ControlFlow::Continue
         });
+    } else {
+        eprintln!("No backlight reported by the daemon; disabling brightness control");
+        brightness.set_sensitive(false);
     }
     row2.append(&bright_label);
     row2.append(&brightness);
+
+    // Auto brightness from the ambient light sensor. Moving the slider while
+    // it is on teaches the daemon's curve.
+    if caps.as_ref().is_some_and(|c| c.light_sensor) {
+        let auto = gtk::ToggleButton::with_label("Auto");
+        // Set while the button shows the daemon's state, so it isn't sent
+        // straight back.
+        let following = Rc::new(Cell::new(false));
+        {
+            let following = following.clone();
+            auto.connect_toggled(move |btn| {
+                if !following.get() {
+                    daemon_send(json!({"cmd":"set_auto_brightness","enabled":btn.is_active()}));
+                }
+            });
+        }
+        let fetched = Arc::new(Mutex::new(None));
+        {
+            let fetched = fetched.clone();
+            client::tokio_rt().spawn(async move {
+                let reply = client::request_async(json!({"cmd":"get_auto_brightness"})).await;
+                *fetched.lock().unwrap() =
+                    Some(reply.and_then(|d| d.get("enabled").and_then(|e| e.as_bool())));
+            });
+        }
+        {
+            let auto = auto.clone();
+            glib::timeout_add_local(Duration::from_millis(100), move || {
+                match fetched.lock().unwrap().take() {
+                    Some(enabled) => {
+                        following.set(true);
+                        auto.set_active(enabled.unwrap_or(false));
+                        following.set(false);
+                        glib::ControlFlow::Break
+                    }
+                    None => glib::ControlFlow::Continue,
+                }
+            });
+        }
+        row2.append(&auto);
+    }
+    if caps.as_ref().is_some_and(|c| !c.backlight) {
+        row2.set_visible(false);
+    }
     vbox.append(&row2);
 
     // Row 3: Volume slider + label + mute
@@ -382,50 +1548,216 @@ fn build_ui(app: &Application) {
     let volume = gtk::Scale::with_range(Orientation::Horizontal, 0.0, 100.0, 1.0);
     volume.set_hexpand(true);
     let mute = gtk::ToggleButton::with_label("Mute");
-    let sink = default_sink().to_string();
+    // Nothing to control until the sound server reports the default sink.
+    volume.set_sensitive(false);
+    mute.set_sensitive(false);
+    let audio = Rc::new(Audio::spawn());
+    // Set while the widgets follow the server, so it isn't written back.
+    let following = Rc::new(Cell::new(false));
+    let last_input = Rc::new(Cell::new(None::<std::time::Instant>));
     {
-        let sink = sink.clone();
+        let audio = audio.clone();
+        let following = following.clone();
+        let last_input = last_input.clone();
         volume.connect_value_changed(move |s| {
-            let val = s.value() as i32;
-            if let Err(e) = Command::new("pactl")
-                .args(&["set-sink-volume", &sink, &format!("{}%", val)])
-                .spawn()
-            {
-                eprintln!("Failed to set volume: {}", e);
+            if following.get() {
+                return;
             }
+            last_input.set(Some(std::time::Instant::now()));
+            audio.set_volume(s.value());
         });
     }
     {
-        let sink = sink.clone();
-        mute.connect_toggled(move |_| {
-            if let Err(e) = Command::new("pactl")
-                .args(&["set-sink-mute", &sink, "toggle"])
-                .spawn()
-            {
-                eprintln!("Failed to toggle mute: {}", e);
+        let audio = audio.clone();
+        let following = following.clone();
+        mute.connect_toggled(move |btn| {
+            if !following.get() {
+                audio.set_mute(btn.is_active());
             }
         });
     }
+    // Every audio widget refreshes from the same mixer snapshot.
+    let mut audio_views: Vec<Box<dyn Fn(Option<&Mixer>)>> = Vec::new();
+    {
+        let volume = volume.clone();
+        let mute = mute.clone();
+        let following = following.clone();
+        audio_views.push(Box::new(move |mixer| {
+            let sink = mixer.and_then(Mixer::default_sink);
+            volume.set_sensitive(sink.is_some());
+            mute.set_sensitive(sink.is_some());
+            let Some(sink) = sink else {
+                return;
+            };
+            volume.set_tooltip_text(Some(&sink.description));
+            // Echoes of a drag in progress would pull the slider back.
+            let dragging = last_input
+                .get()
+                .is_some_and(|t| t.elapsed() < Duration::from_millis(300));
+            following.set(true);
+            if !dragging && (sink.percent() - volume.value()).abs() >= 1.0 {
+                volume.set_value(sink.percent());
+            }
+            mute.set_active(sink.muted);
+            following.set(false);
+        }));
+    }
     row3.append(&volume_label);
     row3.append(&volume);
     row3.append(&mute);
     vbox.append(&row3);
 
+    // Output and input devices. Picking one makes it the default and moves
+    // running streams onto it.
+    let (output_row, output_view) = {
+        let audio = audio.clone();
+        build_device_picker(
+            "Output:",
+            |m| m.sinks.iter().collect(),
+            |m| m.default_sink.as_deref(),
+            move |name| audio.set_default_sink(name),
+        )
+    };
+    audio_views.push(output_view);
+    vbox.append(&output_row);
+
+    let (input_row, input_view) = {
+        let audio = audio.clone();
+        build_device_picker(
+            "Input:",
+            |m| m.inputs().collect(),
+            |m| m.default_source.as_deref(),
+            move |name| audio.set_default_source(name),
+        )
+    };
+    audio_views.push(input_view);
+    let mic_mute = gtk::ToggleButton::with_label("Mute mic");
+    {
+        let audio = audio.clone();
+        let following = following.clone();
+        mic_mute.connect_toggled(move |btn| {
+            if !following.get() {
+                audio.set_source_mute(btn.is_active());
+            }
+        });
+    }
+    {
+        let mic_mute = mic_mute.clone();
+        audio_views.push(Box::new(move |mixer| {
+            let source = mixer.and_then(Mixer::default_source);
+            mic_mute.set_sensitive(source.is_some());
+            following.set(true);
+            mic_mute.set_active(source.is_some_and(|s| s.muted));
+            following.set(false);
+        }));
+    }
+    input_row.append(&mic_mute);
+    vbox.append(&input_row);
+
+    let (mixer_section, mixer_view) = build_mixer_section(audio.clone());
+    audio_views.push(mixer_view);
+    vbox.append(&mixer_section);
+
+    glib::timeout_add_local(Duration::from_millis(50), move || {
+        if let Some(mixer) = audio.take_update() {
+            for view in &audio_views {
+                view(mixer.as_ref());
+            }
+        }
+        glib::ControlFlow::Continue
+    });
+
     vbox.append(&gtk::Separator::new(Orientation::Horizontal));
 
-    // Row 4: Resolution dropdown
+    // Rows 4 and 5: resolution and refresh rate, from the compositor's modes
+    let display_backend = display::Backend::detect().map(Rc::new);
+    let modes = display_backend.as_ref().map(|d| d.modes()).unwrap_or_default();
+    let resolutions = display::resolutions(&modes);
+    let current_res = modes
+        .iter()
+        .find(|m| m.current)
+        .map(Mode::resolution)
+        .or(resolutions.first().copied());
+
     let row4 = gtk::Box::new(Orientation::Horizontal, 8);
-    let res_combo = gtk::DropDown::from_strings(&["1080p", "720p"]);
+    let res_names: Vec<String> = resolutions
+        .iter()
+        .map(|(w, h)| format!("{w}×{h}"))
+        .collect();
+    let res_strs: Vec<&str> = res_names.iter().map(String::as_str).collect();
+    let res_combo = gtk::DropDown::from_strings(&res_strs);
+    if let Some(i) = current_res.and_then(|r| resolutions.iter().position(|&x| x == r)) {
+        res_combo.set_selected(i as u32);
+    }
     row4.append(&gtk::Label::new(Some("Resolution:")));
     row4.append(&res_combo);
+    row4.set_visible(display_backend.as_ref().is_some_and(|d| d.can_set_resolution()));
     vbox.append(&row4);
 
-    // Row 5: Refresh rate buttons
+    // FPS cap presets follow the current refresh rate
+    let fps_row = gtk::Box::new(Orientation::Horizontal, 8);
+    let on_refresh: Rc<dyn Fn(f32)> = match Limiter::detect() {
+        Some(limiter) => {
+            let limiter = Rc::new(limiter);
+            let fps_row = fps_row.clone();
+            Rc::new(move |hz| fill_fps_row(&fps_row, &limiter, hz))
+        }
+        None => {
+            fps_row.set_visible(false);
+            Rc::new(|_| {})
+        }
+    };
+
     let row5 = gtk::Box::new(Orientation::Horizontal, 8);
-    for hz in ["40Hz", "50Hz", "60Hz"] {
-        row5.append(&gtk::Button::with_label(hz));
+    if let (Some(backend), Some(res)) = (&display_backend, current_res) {
+        fill_refresh_row(&row5, backend, &modes, res, &on_refresh);
+
+        let backend = backend.clone();
+        let row5 = row5.clone();
+        res_combo.connect_selected_notify(move |dd| {
+            let Some(&res) = resolutions.get(dd.selected() as usize) else {
+                return;
+            };
+            // Keep the current refresh rate if the new resolution has it,
+            // otherwise take the fastest.
+            let set = move |backend: &display::Backend| {
+                let modes = backend.modes();
+                let current_hz = modes.iter().find(|m| m.current).map(|m| m.refresh_hz);
+                let rates = display::rates_for(&modes, res);
+                let mode = rates
+                    .iter()
+                    .find(|m| Some(m.refresh_hz) == current_hz)
+                    .or(rates.last());
+                if let Some(mode) = mode {
+                    if let Err(e) = backend.apply(mode) {
+                        eprintln!("Failed to set display mode: {}", e);
+                    }
+                }
+            };
+            dd.set_sensitive(false);
+            let dd = dd.clone();
+            let backend2 = backend.clone();
+            let row5 = row5.clone();
+            let on_refresh = on_refresh.clone();
+            display_task(&backend, set, move |modes| {
+                dd.set_sensitive(true);
+                fill_refresh_row(&row5, &backend2, &modes, res, &on_refresh);
+            });
+        });
+    } else {
+        eprintln!("No display backend (wlr-randr or gamescope); hiding display modes");
+        row5.set_visible(false);
+        // Without a known refresh rate, offer the common 60 Hz presets.
+        on_refresh(60.0);
     }
     vbox.append(&row5);
+    vbox.append(&fps_row);
+
+    // Night light through the compositor's gamma control
+    match nightlight::Backend::detect() {
+        Some(backend) => vbox.append(&build_nightlight_section(backend)),
+        None => eprintln!("No night light backend (gammastep, wlsunset or gamescope)"),
+    }
 
     vbox.append(&gtk::Separator::new(Orientation::Horizontal));
 
@@ -451,6 +1783,9 @@ fn build_ui(app: &Application) {
     row6.append(&tdp_label);
     row6.append(&tdp);
     row6.append(&tdp_value);
+    if caps.as_ref().is_some_and(|c| c.tdp_backend.is_none()) {
+        row6.set_visible(false);
+    }
     vbox.append(&row6);
 
     // Row 7: Fan profile radio‐style
@@ -466,6 +1801,9 @@ fn build_ui(app: &Application) {
     row7.append(&quiet);
     row7.append(&aggressive);
     row7.append(&manual);
+    if caps.as_ref().is_some_and(|c| !c.fan_control) {
+        row7.set_visible(false);
+    }
     vbox.append(&row7);
 
     // Row 8: Manual fan speed
@@ -572,6 +1910,92 @@ fn build_ui(app: &Application) {
     }
     vbox.append(&manual_speed);
 
+    let monitor = Arc::new(Mutex::new(Monitor::default()));
+    telemetry::spawn_poller(monitor.clone());
+
+    // Advanced CPU: governor, EPP, boost, frequency limits, SMT and core count
+    let freq = state
+        .as_ref()
+        .and_then(CpuFreq::from_state)
+        .filter(|_| caps.as_ref().is_none_or(|c| c.cpufreq));
+    let cores = state
+        .as_ref()
+        .and_then(CpuCores::from_state)
+        .filter(|_| caps.as_ref().is_none_or(|c| c.cpu_hotplug || c.smt));
+    if freq.is_some() || cores.is_some() {
+        vbox.append(&build_cpu_section(freq.as_ref(), cores.as_ref()));
+    }
+
+    // GPU: performance level, power profile and clock limits
+    if caps.as_ref().is_none_or(|c| !c.gpus.is_empty()) {
+        if let Some(gpu) = state.as_ref().and_then(Gpu::from_state) {
+            vbox.append(&build_gpu_section(&gpu, monitor.clone()));
+        }
+    }
+
+    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
+
+    // Monitoring section: sparklines fed from the daemon's telemetry history
+    let monitor_section = gtk::Box::new(Orientation::Vertical, 8);
+    let monitor_label = gtk::Label::new(Some("Monitoring"));
+    monitor_label.add_css_class("heading");
+    monitor_section.append(&monitor_label);
+    monitor_section.append(&gtk::Separator::new(Orientation::Horizontal));
+
+    let mut graphs = Vec::new();
+    for metric in Metric::ALL {
+        let header = gtk::Box::new(Orientation::Horizontal, 8);
+        let title = gtk::Label::new(Some(metric.title()));
+        title.set_hexpand(true);
+        title.set_halign(Align::Start);
+        let value = gtk::Label::new(Some("–"));
+        header.append(&title);
+        header.append(&value);
+        monitor_section.append(&header);
+
+        let graph = gtk::DrawingArea::new();
+        graph.set_content_height(36);
+        graph.set_hexpand(true);
+        {
+            let monitor = monitor.clone();
+            graph.set_draw_func(move |_w, cr, width, height| {
+                let points = monitor
+                    .lock()
+                    .unwrap()
+                    .points(metric, width as f64, height as f64);
+                cr.set_source_rgba(1.0, 1.0, 1.0, 0.08);
+                cr.rectangle(0.0, 0.0, width as f64, height as f64);
+                let _ = cr.fill();
+                let Some(&(x0, y0)) = points.first() else {
+                    return;
+                };
+                cr.set_source_rgb(0.35, 0.7, 1.0);
+                cr.set_line_width(1.5);
+                cr.move_to(x0, y0);
+                for &(x, y) in &points[1..] {
+                    cr.line_to(x, y);
+                }
+                let _ = cr.stroke();
+            });
+        }
+        monitor_section.append(&graph);
+        graphs.push((metric, value, graph));
+    }
+    {
+        let monitor = monitor.clone();
+        glib::timeout_add_local(telemetry::POLL_INTERVAL, move || {
+            let m = monitor.lock().unwrap();
+            for (metric, value, graph) in &graphs {
+                match m.latest(*metric) {
+                    Some(v) => value.set_text(&metric.format(v)),
+                    None => value.set_text("–"),
+                }
+                graph.queue_draw();
+            }
+            glib::ControlFlow::Continue
+        });
+    }
+    vbox.append(&monitor_section);
     vbox.append(&gtk::Separator::new(Orientation::Horizontal));
 
     // RGB Lighting section
@@ -590,9 +2014,12 @@ fn build_ui(app: &Application) {
     breathe_btn.set_group(Some(&off_btn));
     let manual_btn = gtk::CheckButton::with_label("Manual");
     manual_btn.set_group(Some(&off_btn));
+    let effect_btn = gtk::CheckButton::with_label("Effect");
+    effect_btn.set_group(Some(&off_btn));
     mode_row.append(&off_btn);
     mode_row.append(&breathe_btn);
     mode_row.append(&manual_btn);
+    mode_row.append(&effect_btn);
     rgb_section.append(&mode_row);
 
     // Manual controls
@@ -631,9 +2058,7 @@ fn build_ui(app: &Application) {
         move || {
             let h = hue.get();
             let b = brightness.value() as u8;
-            let (r, g, bl) = hsv_to_rgb(h, 1.0, 1.0);
-            rgb_set_brightness(b);
-            rgb_set_intensity(r, g, bl);
+            rgb_set(1, b, hsv_to_rgb(h, 1.0, 1.0));
             preview.queue_draw();
         }
     });
@@ -745,39 +2170,204 @@ fn build_ui(app: &Application) {
         });
     }
 
-    // Mode handler
-    {
-        let manual_box = manual_box.clone();
-        off_btn.connect_toggled(move |btn| {
-            if btn.is_active() {
-                manual_box.set_visible(false);
-                rgb_set_mode(1);
-                rgb_set_brightness(0);
-                rgb_set_intensity(0, 0, 0);
+    // Software effects, animated by the daemon. The controls start from the
+    // defaults and follow the daemon's effect once it has answered.
+    let effect_box = gtk::Box::new(Orientation::Vertical, 8);
+    let defaults = Effect::default();
+
+    let kind_row = gtk::Box::new(Orientation::Horizontal, 4);
+    kind_row.append(&gtk::Label::new(Some("Effect:")));
+    let kind = gtk::DropDown::from_strings(&effects::KINDS.map(|(_, label)| label));
+    kind_row.append(&kind);
+    effect_box.append(&kind_row);
+
+    let speed_row = gtk::Box::new(Orientation::Horizontal, 4);
+    speed_row.append(&gtk::Label::new(Some("Speed:")));
+    let (min_speed, max_speed) = effects::SPEED_RANGE;
+    let speed = gtk::Scale::with_range(Orientation::Horizontal, min_speed, max_speed, 0.05);
+    speed.set_hexpand(true);
+    speed.set_value(defaults.speed);
+    speed_row.append(&speed);
+    effect_box.append(&speed_row);
+
+    let effect_bright_row = gtk::Box::new(Orientation::Horizontal, 4);
+    effect_bright_row.append(&gtk::Label::new(Some("Brightness:")));
+    let effect_bright = gtk::Scale::with_range(Orientation::Horizontal, 0.0, 255.0, 1.0);
+    effect_bright.set_hexpand(true);
+    effect_bright.set_value(defaults.brightness as f64);
+    effect_bright_row.append(&effect_bright);
+    effect_box.append(&effect_bright_row);
+
+    // Two colours to alternate between; the daemon takes up to eight.
+    let colors_row = gtk::Box::new(Orientation::Horizontal, 4);
+    colors_row.append(&gtk::Label::new(Some("Colours:")));
+    let color_btns: Vec<gtk::ColorDialogButton> = (0..2)
+        .map(|_| {
+            let dialog = gtk::ColorDialog::new();
+            dialog.set_with_alpha(false);
+            let btn = gtk::ColorDialogButton::new(Some(dialog));
+            colors_row.append(&btn);
+            btn
+        })
+        .collect();
+    effect_box.append(&colors_row);
+
+    // Set while the controls show the daemon's effect, so it isn't sent
+    // straight back.
+    let following_effect = Rc::new(Cell::new(false));
+    let show_effect = {
+        let kind = kind.clone();
+        let speed = speed.clone();
+        let effect_bright = effect_bright.clone();
+        let color_btns = color_btns.clone();
+        let colors_row = colors_row.clone();
+        let following = following_effect.clone();
+        move |effect: &Effect| {
+            following.set(true);
+            kind.set_selected(effect.kind_index().unwrap_or(0) as u32);
+            speed.set_value(effect.speed);
+            effect_bright.set_value(effect.brightness as f64);
+            for (i, btn) in color_btns.iter().enumerate() {
+                let [r, g, b] = effect
+                    .colors
+                    .get(i)
+                    .or(effect.colors.last())
+                    .copied()
+                    .unwrap_or([255, 255, 255]);
+                btn.set_rgba(&gdk::RGBA::new(
+                    r as f32 / 255.0,
+                    g as f32 / 255.0,
+                    b as f32 / 255.0,
+                    1.0,
+                ));
             }
-        });
-    }
-    {
-        let manual_box = manual_box.clone();
-        breathe_btn.connect_toggled(move |btn| {
-            if btn.is_active() {
-                manual_box.set_visible(false);
-                rgb_set_mode(0);
+            colors_row.set_visible(effect.uses_colors());
+            following.set(false);
+        }
+    };
+    show_effect(&defaults);
+
+    effect_box.set_visible(false);
+    rgb_section.append(&effect_box);
+
+    let read_effect = Rc::new({
+        let kind = kind.clone();
+        let speed = speed.clone();
+        let effect_bright = effect_bright.clone();
+        let color_btns = color_btns.clone();
+        move || Effect {
+            effect: effects::KINDS[kind.selected() as usize].0.to_string(),
+            speed: speed.value(),
+            colors: color_btns
+                .iter()
+                .map(|btn| {
+                    let c = btn.rgba();
+                    [c.red(), c.green(), c.blue()].map(|v| (v * 255.0).round() as u8)
+                })
+                .collect(),
+            brightness: effect_bright.value() as u8,
+        }
+    });
+
+    let effect_pending = Rc::new(RefCell::new(None::<SourceId>));
+    let schedule_effect = Rc::new({
+        let read_effect = read_effect.clone();
+        let pending_ref = effect_pending.clone();
+        let following = following_effect.clone();
+        move || {
+            if !following.get() && pending_ref.borrow().is_none() {
+                let read_effect = read_effect.clone();
+                let pending_clone = pending_ref.clone();
+                let id = glib::timeout_add_local(Duration::from_millis(100), move || {
+                    effects::apply(&read_effect());
+                    pending_clone.borrow_mut().take();
+                    glib::ControlFlow::Break
+                });
+                *pending_ref.borrow_mut() = Some(id);
             }
+        }
+    });
+
+    {
+        let schedule = schedule_effect.clone();
+        let colors_row = colors_row.clone();
+        kind.connect_selected_notify(move |dd| {
+            colors_row.set_visible(effects::uses_colors(effects::KINDS[dd.selected() as usize].0));
+            schedule();
         });
     }
+    for scale in [&speed, &effect_bright] {
+        let schedule = schedule_effect.clone();
+        scale.connect_value_changed(move |_| schedule());
+    }
+    for btn in &color_btns {
+        let schedule = schedule_effect.clone();
+        btn.connect_rgba_notify(move |_| schedule());
+    }
+
+    // Mode handlers. Only the effect mode leaves the daemon animating; the
+    // others set the LEDs directly, which stops it.
+    off_btn.connect_toggled(|btn| {
+        if btn.is_active() {
+            rgb_set(1, 0, (0, 0, 0));
+        }
+    });
+    breathe_btn.connect_toggled(|btn| {
+        if btn.is_active() {
+            rgb_breathe();
+        }
+    });
     {
         let manual_box = manual_box.clone();
         let apply = apply_settings.clone();
         manual_btn.connect_toggled(move |btn| {
+            manual_box.set_visible(btn.is_active());
             if btn.is_active() {
-                manual_box.set_visible(true);
-                rgb_set_mode(1);
                 apply();
             }
         });
     }
+    {
+        let effect_box = effect_box.clone();
+        let read_effect = read_effect.clone();
+        let following = following_effect.clone();
+        effect_btn.connect_toggled(move |btn| {
+            effect_box.set_visible(btn.is_active());
+            if btn.is_active() && !following.get() {
+                effects::apply(&read_effect());
+            }
+        });
+    }
+
+    // Show a running effect, unless another mode has been picked meanwhile.
+    let fetched = Arc::new(Mutex::new(None));
+    {
+        let fetched = fetched.clone();
+        client::tokio_rt().spawn(async move {
+            *fetched.lock().unwrap() = Some(effects::current().await);
+        });
+    }
+    glib::timeout_add_local(Duration::from_millis(100), move || {
+        let Some(effect) = fetched.lock().unwrap().take() else {
+            return glib::ControlFlow::Continue;
+        };
+        if let Some(effect) = effect {
+            show_effect(&effect);
+            if effect.is_on() && off_btn.is_active() {
+                following_effect.set(true);
+                effect_btn.set_active(true);
+                following_effect.set(false);
+            }
+        }
+        glib::ControlFlow::Break
+    });
 
+    if caps
+        .as_ref()
+        .is_some_and(|c| !c.rgb_zones.iter().any(|z| z == RGB_LED))
+    {
+        rgb_section.set_visible(false);
+    }
     vbox.append(&rgb_section);
     vbox.append(&gtk::Separator::new(Orientation::Horizontal));
 
@@ -793,33 +2383,3 @@ pub fn run() {
     app.connect_activate(build_ui);
     app.run();
 }
-
-fn tokio_rt() -> &'static Runtime {
-    static RT: OnceLock<Runtime> = OnceLock::new();
-    RT.get_or_init(|| Runtime::new().expect("tokio runtime"))
-}
-
-fn daemon_send(val: serde_json::Value) {
-    let rt = tokio_rt();
-    rt.spawn(async move {
-        let msg = val.to_string() + "\n";
-        for _ in 0..3 {
-            match UnixStream::connect("/run/loki-master.sock").await {
-                Ok(stream) => {
-                    let mut stream = stream;
-                    if stream.write_all(msg.as_bytes()).await.is_ok() {
-                        let mut reader = BufReader::new(stream);
-                        let mut resp = String::new();
-                        let _ = reader.read_line(&mut resp).await;
-                    }
-                    return;
-                }
-                Err(e) => {
-                    eprintln!("connect failed: {e}");
-                    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-                }
-            }
-        }
-    });
-}
-
@@ -1,8 +1,42 @@
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod audio;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod backlight;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod bluetooth;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod client;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod cpu;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod display;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod effects;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod fps;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod gpu;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod nightlight;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod pulse;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod rfkill;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod telemetry;
+#[cfg(test)]
+mod testbus;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+mod wifi;
 #[cfg(feature = "gui")]
 mod gui;
 
 #[cfg(feature = "gui")]
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some(nightlight::SCHEDULER_ARG) {
+        nightlight::run_scheduler();
+        return;
+    }
     gui::run();
 }
 
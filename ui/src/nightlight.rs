@@ -0,0 +1,345 @@
+//! Night light: display colour temperature through the compositor. On
+//! wlroots compositors a gamma helper (`gammastep` or `wlsunset`) holds the
+//! gamma ramps for as long as it runs; under gamescope its night mode
+//! property is set. The schedule is evaluated locally, from fixed times or
+//! from sunset and sunrise at a configured location, by a scheduler process
+//! that outlives the panel.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::display;
+
+/// Neutral white; night light is off at this temperature.
+pub const NEUTRAL_K: u32 = 6500;
+pub const MIN_K: u32 = 1900;
+/// Hue and saturation for gamescope's night mode, a warm orange.
+const GAMESCOPE_HUE: f32 = 0.05;
+const GAMESCOPE_SATURATION: f32 = 1.0;
+/// Runs the panel binary as the night light scheduler.
+pub const SCHEDULER_ARG: &str = "--nightlight-scheduler";
+/// How often the scheduler re-evaluates the schedule.
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub enabled: bool,
+    /// Temperature used while night light is on.
+    pub temperature: u32,
+    /// Follow the schedule instead of staying on.
+    pub scheduled: bool,
+    /// Fixed schedule as minutes after midnight, local time.
+    pub start_min: u32,
+    pub end_min: u32,
+    /// With a location the schedule runs from sunset to sunrise instead.
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            enabled: false,
+            temperature: 4000,
+            scheduled: false,
+            start_min: 20 * 60,
+            end_min: 7 * 60,
+            latitude: None,
+            longitude: None,
+        }
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("loki/nightlight.json"))
+}
+
+impl Config {
+    pub fn load() -> Config {
+        config_path()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|t| serde_json::from_str(&t).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let Some(path) = config_path() else {
+            return;
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_vec_pretty(self)?));
+        if let Err(e) = result {
+            eprintln!("Failed to save {}: {}", path.display(), e);
+        }
+    }
+
+    /// Temperature to show now: [`NEUTRAL_K`] when off or outside the
+    /// schedule.
+    pub fn target(&self) -> u32 {
+        let (now, day, offset) = local_now();
+        if !self.enabled || (self.scheduled && !self.active_at(now, day, offset)) {
+            NEUTRAL_K
+        } else {
+            self.temperature
+        }
+    }
+
+    /// Whether the schedule puts night light on at `now` (minutes after
+    /// local midnight) on `day_of_year`.
+    pub fn active_at(&self, now: u32, day_of_year: u32, utc_offset_min: i32) -> bool {
+        let (start, end) = match (self.latitude, self.longitude) {
+            (Some(lat), Some(lon)) => match sun_times(day_of_year, lat, lon, utc_offset_min) {
+                Some((sunrise, sunset)) => (sunset, sunrise),
+                // Polar day or night: on only while the sun stays down.
+                None => return polar_night(day_of_year, lat),
+            },
+            _ => (self.start_min, self.end_min),
+        };
+        in_window(now, start, end)
+    }
+}
+
+/// `now` lies in `[start, end)`, wrapping past midnight when `end < start`.
+pub fn in_window(now: u32, start: u32, end: u32) -> bool {
+    if start <= end {
+        (start..end).contains(&now)
+    } else {
+        now >= start || now < end
+    }
+}
+
+/// Parse `HH:MM` into minutes after midnight.
+pub fn parse_hhmm(text: &str) -> Option<u32> {
+    let (h, m) = text.trim().split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+pub fn format_hhmm(min: u32) -> String {
+    format!("{:02}:{:02}", min / 60 % 24, min % 60)
+}
+
+fn solar_declination(gamma: f64) -> f64 {
+    0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin()
+}
+
+fn polar_night(day_of_year: u32, latitude: f64) -> bool {
+    let gamma = 2.0 * std::f64::consts::PI / 365.0 * (day_of_year as f64 - 1.0);
+    // Sun below the horizon all day when declination and latitude are on
+    // opposite sides.
+    solar_declination(gamma).signum() != latitude.signum()
+}
+
+/// Sunrise and sunset as minutes after local midnight, from the NOAA
+/// approximation. `None` during polar day or night.
+pub fn sun_times(
+    day_of_year: u32,
+    latitude: f64,
+    longitude: f64,
+    utc_offset_min: i32,
+) -> Option<(u32, u32)> {
+    let gamma = 2.0 * std::f64::consts::PI / 365.0 * (day_of_year as f64 - 1.0);
+    let eqtime = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+    let decl = solar_declination(gamma);
+    let lat = latitude.to_radians();
+    let cos_ha = 90.833f64.to_radians().cos() / (lat.cos() * decl.cos()) - lat.tan() * decl.tan();
+    if !(-1.0..=1.0).contains(&cos_ha) {
+        return None;
+    }
+    let ha = cos_ha.acos().to_degrees();
+    let local = |utc: f64| (utc + utc_offset_min as f64).rem_euclid(1440.0) as u32;
+    let sunrise = 720.0 - 4.0 * (longitude + ha) - eqtime;
+    let sunset = 720.0 - 4.0 * (longitude - ha) - eqtime;
+    Some((local(sunrise), local(sunset)))
+}
+
+/// Local time as (minutes after midnight, day of year starting at 1, UTC
+/// offset in minutes).
+pub fn local_now() -> (u32, u32, i32) {
+    // SAFETY: time and localtime_r only write to the tm we pass in.
+    unsafe {
+        let t = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&t, &mut tm);
+        (
+            (tm.tm_hour * 60 + tm.tm_min) as u32,
+            tm.tm_yday as u32 + 1,
+            (tm.tm_gmtoff / 60) as i32,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Gamescope,
+    Gammastep,
+    Wlsunset,
+}
+
+fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR").map_or_else(std::env::temp_dir, PathBuf::from)
+}
+
+/// Where the running gamma helper's pid is kept, so a restarted panel
+/// replaces it instead of starting a second one.
+fn pid_path() -> PathBuf {
+    runtime_dir().join("loki-nightlight.pid")
+}
+
+fn scheduler_pid_path() -> PathBuf {
+    runtime_dir().join("loki-nightlight-scheduler.pid")
+}
+
+fn recorded_pid(path: &Path) -> Option<i32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn is_helper(pid: i32) -> bool {
+    fs::read_to_string(format!("/proc/{pid}/comm"))
+        .is_ok_and(|c| matches!(c.trim(), "gammastep" | "wlsunset"))
+}
+
+fn is_scheduler(pid: i32) -> bool {
+    fs::read(format!("/proc/{pid}/cmdline"))
+        .is_ok_and(|c| c.split(|&b| b == 0).any(|arg| arg == SCHEDULER_ARG.as_bytes()))
+}
+
+/// Stop the process recorded in `path` and forget it. The pid is only
+/// signalled if `is_ours` still recognises it, not a reused pid.
+fn stop_recorded(path: &Path, is_ours: fn(i32) -> bool) {
+    if let Some(pid) = recorded_pid(path).filter(|&p| is_ours(p)) {
+        // SAFETY: kill takes no pointers; the pid was just checked to be
+        // one of our processes.
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+    }
+    let _ = fs::remove_file(path);
+}
+
+fn stop_helper() {
+    stop_recorded(&pid_path(), is_helper);
+}
+
+pub fn stop_scheduler() {
+    stop_recorded(&scheduler_pid_path(), is_scheduler);
+}
+
+/// Start the scheduler in the background, unless one is already running and
+/// `restart` is false. A new scheduler applies the saved config straight
+/// away and keeps following the schedule after the panel closes.
+pub fn start_scheduler(restart: bool) -> Result<(), String> {
+    let path = scheduler_pid_path();
+    if !restart && recorded_pid(&path).is_some_and(is_scheduler) {
+        return Ok(());
+    }
+    stop_scheduler();
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let mut child = Command::new(exe)
+        .arg(SCHEDULER_ARG)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Out of the panel's process group, so closing its terminal does
+        // not stop the schedule.
+        .process_group(0)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    fs::write(&path, child.id().to_string()).map_err(|e| e.to_string())?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// The scheduler's main loop: apply the saved config's target every
+/// [`SCHEDULE_INTERVAL`] until night light is no longer scheduled. The config
+/// is re-read each time, so edits made without a restart still apply.
+pub fn run_scheduler() {
+    let Some(backend) = Backend::detect() else {
+        eprintln!("No night light backend (gammastep, wlsunset or gamescope)");
+        return;
+    };
+    let mut applied = None;
+    loop {
+        let config = Config::load();
+        let target = config.target();
+        if applied != Some(target) {
+            match backend.apply(target) {
+                Ok(()) => applied = Some(target),
+                Err(e) => eprintln!("Failed to set night light: {}", e),
+            }
+        }
+        if !(config.enabled && config.scheduled) {
+            return;
+        }
+        std::thread::sleep(SCHEDULE_INTERVAL);
+    }
+}
+
+impl Backend {
+    pub fn detect() -> Option<Backend> {
+        if display::under_gamescope() && display::find_program("xprop") {
+            Some(Backend::Gamescope)
+        } else if display::find_program("gammastep") {
+            Some(Backend::Gammastep)
+        } else if display::find_program("wlsunset") {
+            Some(Backend::Wlsunset)
+        } else {
+            None
+        }
+    }
+
+    /// Tint the display to `kelvin`; [`NEUTRAL_K`] or above turns night
+    /// light off.
+    pub fn apply(self, kelvin: u32) -> Result<(), String> {
+        let kelvin = kelvin.clamp(MIN_K, NEUTRAL_K);
+        if self == Backend::Gamescope {
+            let amount = (NEUTRAL_K - kelvin) as f32 / (NEUTRAL_K - MIN_K) as f32;
+            let values = [amount, GAMESCOPE_HUE, GAMESCOPE_SATURATION].map(f32::to_bits);
+            return display::xprop_set_values("GAMESCOPE_COLOR_NIGHTMODE", &values);
+        }
+        stop_helper();
+        if kelvin >= NEUTRAL_K {
+            return Ok(());
+        }
+        let mut cmd = match self {
+            Backend::Gammastep => {
+                let mut cmd = Command::new("gammastep");
+                cmd.args(["-m", "wayland", "-P", "-O", &kelvin.to_string()]);
+                cmd
+            }
+            // wlsunset always runs a day/night cycle; one kelvin apart the
+            // two are indistinguishable.
+            _ => {
+                let mut cmd = Command::new("wlsunset");
+                cmd.args(["-t", &kelvin.to_string(), "-T", &(kelvin + 1).to_string()]);
+                cmd.args(["-S", "06:00", "-s", "18:00"]);
+                cmd
+            }
+        };
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        fs::write(pid_path(), child.id().to_string()).map_err(|e| e.to_string())?;
+        // Reap the helper when it is replaced.
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+//! A small client for the PulseAudio native protocol, which PipeWire also
+//! serves through pipewire-pulse. Only the control channel is spoken: no
+//! audio streams and no shared memory, just introspection, volume commands
+//! and subscription events.
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Protocol version we speak. Replies are laid out for the lower of ours
+/// and the server's.
+const PROTOCOL_VERSION: u32 = 32;
+/// Channel number of control packets; anything else is audio data.
+const CONTROL_CHANNEL: u32 = u32::MAX;
+const COOKIE_LEN: usize = 256;
+
+pub const INVALID_INDEX: u32 = u32::MAX;
+/// 100% volume.
+pub const VOLUME_NORM: u32 = 0x10000;
+
+pub mod command {
+    pub const ERROR: u32 = 0;
+    pub const REPLY: u32 = 2;
+    pub const AUTH: u32 = 8;
+    pub const SET_CLIENT_NAME: u32 = 9;
+    pub const GET_SERVER_INFO: u32 = 20;
+    pub const GET_SINK_INFO: u32 = 21;
+    pub const GET_SINK_INFO_LIST: u32 = 22;
+    pub const GET_SOURCE_INFO: u32 = 23;
+    pub const GET_SOURCE_INFO_LIST: u32 = 24;
+    pub const GET_SINK_INPUT_INFO: u32 = 29;
+    pub const GET_SINK_INPUT_INFO_LIST: u32 = 30;
+    pub const GET_SOURCE_OUTPUT_INFO: u32 = 31;
+    pub const GET_SOURCE_OUTPUT_INFO_LIST: u32 = 32;
+    pub const SUBSCRIBE: u32 = 35;
+    pub const SET_SINK_VOLUME: u32 = 36;
+    pub const SET_SINK_INPUT_VOLUME: u32 = 37;
+    pub const SET_SINK_MUTE: u32 = 39;
+    pub const SET_SOURCE_MUTE: u32 = 40;
+    pub const SET_DEFAULT_SINK: u32 = 44;
+    pub const SET_DEFAULT_SOURCE: u32 = 45;
+    pub const SUBSCRIBE_EVENT: u32 = 66;
+    pub const MOVE_SINK_INPUT: u32 = 67;
+    pub const MOVE_SOURCE_OUTPUT: u32 = 68;
+    pub const SET_SINK_INPUT_MUTE: u32 = 69;
+}
+
+/// Subscription masks and event fields.
+pub mod subscription {
+    pub const MASK_SINK: u32 = 0x0001;
+    pub const MASK_SOURCE: u32 = 0x0002;
+    pub const MASK_SINK_INPUT: u32 = 0x0004;
+    pub const MASK_SOURCE_OUTPUT: u32 = 0x0008;
+    pub const MASK_SERVER: u32 = 0x0080;
+
+    pub const FACILITY_MASK: u32 = 0x0f;
+    pub const FACILITY_SINK: u32 = 0x00;
+    pub const FACILITY_SOURCE: u32 = 0x01;
+    pub const FACILITY_SINK_INPUT: u32 = 0x02;
+    pub const FACILITY_SOURCE_OUTPUT: u32 = 0x03;
+    pub const FACILITY_SERVER: u32 = 0x07;
+
+    pub const TYPE_MASK: u32 = 0x30;
+    pub const TYPE_REMOVE: u32 = 0x20;
+}
+
+/// Properties as key and raw value; string values keep their NUL.
+pub type Proplist = Vec<(String, Vec<u8>)>;
+
+fn bad(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Builds a command packet: the command and its tag followed by typed
+/// arguments.
+pub struct TagWriter {
+    buf: Vec<u8>,
+}
+
+impl TagWriter {
+    pub fn new(command: u32, tag: u32) -> TagWriter {
+        let mut w = TagWriter { buf: Vec::new() };
+        w.u32(command).u32(tag);
+        w
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.push(b'L');
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn bool(&mut self, v: bool) -> &mut Self {
+        self.buf.push(if v { b'1' } else { b'0' });
+        self
+    }
+
+    pub fn string(&mut self, v: Option<&str>) -> &mut Self {
+        match v {
+            Some(s) => {
+                self.buf.push(b't');
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+            }
+            None => self.buf.push(b'N'),
+        }
+        self
+    }
+
+    pub fn arbitrary(&mut self, v: &[u8]) -> &mut Self {
+        self.buf.push(b'x');
+        self.buf.extend_from_slice(&(v.len() as u32).to_be_bytes());
+        self.buf.extend_from_slice(v);
+        self
+    }
+
+    pub fn cvolume(&mut self, v: &[u32]) -> &mut Self {
+        self.buf.push(b'v');
+        self.buf.push(v.len() as u8);
+        for c in v {
+            self.buf.extend_from_slice(&c.to_be_bytes());
+        }
+        self
+    }
+
+    /// String properties, stored NUL-terminated as libpulse does.
+    pub fn proplist(&mut self, props: &[(&str, &str)]) -> &mut Self {
+        self.buf.push(b'P');
+        for (key, value) in props {
+            let mut data = value.as_bytes().to_vec();
+            data.push(0);
+            self.string(Some(key)).u32(data.len() as u32).arbitrary(&data);
+        }
+        self.string(None)
+    }
+}
+
+/// Reads typed values from a received packet.
+pub struct TagReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl TagReader {
+    pub fn new(data: Vec<u8>) -> TagReader {
+        TagReader { data, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.data.len());
+        let end = end.ok_or_else(|| bad("truncated packet"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn tag(&mut self, expected: u8) -> io::Result<()> {
+        let tag = self.take(1)?[0];
+        if tag != expected {
+            return Err(bad(format!("expected tag {:?}, got {:?}", expected as char, tag as char)));
+        }
+        Ok(())
+    }
+
+    fn be32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        self.tag(b'L')?;
+        self.be32()
+    }
+
+    /// Whether everything in the packet has been read.
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        self.tag(b'B')?;
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        match self.take(1)?[0] {
+            b'1' => Ok(true),
+            b'0' => Ok(false),
+            t => Err(bad(format!("expected boolean, got {:?}", t as char))),
+        }
+    }
+
+    pub fn string(&mut self) -> io::Result<Option<String>> {
+        match self.take(1)?[0] {
+            b'N' => Ok(None),
+            b't' => {
+                let rest = &self.data[self.pos..];
+                let len = rest.iter().position(|&b| b == 0).ok_or_else(|| bad("unterminated string"))?;
+                let s = String::from_utf8_lossy(&rest[..len]).into_owned();
+                self.pos += len + 1;
+                Ok(Some(s))
+            }
+            t => Err(bad(format!("expected string, got {:?}", t as char))),
+        }
+    }
+
+    pub fn arbitrary(&mut self) -> io::Result<Vec<u8>> {
+        self.tag(b'x')?;
+        let len = self.be32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Sample format, channel count and rate.
+    pub fn sample_spec(&mut self) -> io::Result<(u8, u8, u32)> {
+        self.tag(b'a')?;
+        let b = self.take(2)?;
+        let (format, channels) = (b[0], b[1]);
+        Ok((format, channels, self.be32()?))
+    }
+
+    pub fn channel_map(&mut self) -> io::Result<Vec<u8>> {
+        self.tag(b'm')?;
+        let n = self.take(1)?[0] as usize;
+        Ok(self.take(n)?.to_vec())
+    }
+
+    pub fn cvolume(&mut self) -> io::Result<Vec<u32>> {
+        self.tag(b'v')?;
+        let n = self.take(1)?[0];
+        (0..n).map(|_| self.be32()).collect()
+    }
+
+    pub fn volume(&mut self) -> io::Result<u32> {
+        self.tag(b'V')?;
+        self.be32()
+    }
+
+    pub fn usec(&mut self) -> io::Result<u64> {
+        self.tag(b'U')?;
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn proplist(&mut self) -> io::Result<Proplist> {
+        self.tag(b'P')?;
+        let mut props = Vec::new();
+        while let Some(key) = self.string()? {
+            let len = self.u32()? as usize;
+            let value = self.arbitrary()?;
+            if value.len() != len {
+                return Err(bad("proplist length mismatch"));
+            }
+            props.push((key, value));
+        }
+        Ok(props)
+    }
+
+    /// Encoding and properties of a stream or device format.
+    pub fn format_info(&mut self) -> io::Result<(u8, Proplist)> {
+        self.tag(b'f')?;
+        let encoding = self.u8()?;
+        Ok((encoding, self.proplist()?))
+    }
+}
+
+/// A string property, without its trailing NUL.
+pub fn prop<'a>(props: &'a [(String, Vec<u8>)], key: &str) -> Option<&'a str> {
+    let (_, value) = props.iter().find(|(k, _)| k == key)?;
+    std::str::from_utf8(value.strip_suffix(&[0]).unwrap_or(value)).ok()
+}
+
+/// A control packet from the server.
+pub struct Packet {
+    pub command: u32,
+    pub tag: u32,
+    pub body: TagReader,
+}
+
+/// `$PULSE_SERVER` when it names a unix socket, otherwise
+/// `$XDG_RUNTIME_DIR/pulse/native`.
+fn socket_path() -> Option<PathBuf> {
+    if let Ok(server) = std::env::var("PULSE_SERVER") {
+        if let Some(path) = server.strip_prefix("unix:") {
+            return Some(PathBuf::from(path));
+        }
+        if server.starts_with('/') {
+            return Some(PathBuf::from(server));
+        }
+    }
+    let runtime = std::env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(runtime).join("pulse/native"))
+}
+
+/// The auth cookie from `$PULSE_COOKIE` or the usual places. PipeWire does
+/// not check it, so a missing cookie is sent as zeros.
+fn cookie() -> Vec<u8> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+    std::env::var_os("PULSE_COOKIE")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(config.map(|c| c.join("pulse/cookie")))
+        .chain(home.map(|h| h.join(".pulse-cookie")))
+        .find_map(|p| std::fs::read(p).ok().filter(|c| c.len() == COOKIE_LEN))
+        .unwrap_or_else(|| vec![0; COOKIE_LEN])
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    next_tag: u32,
+    /// Negotiated protocol version.
+    pub version: u32,
+}
+
+impl Connection {
+    /// Connect, authenticate and name the client.
+    pub fn connect(client_name: &str) -> io::Result<Connection> {
+        let path = socket_path().ok_or_else(|| bad("no PulseAudio socket"))?;
+        let stream = UnixStream::connect(&path)?;
+        let mut conn = Connection { stream, next_tag: 0, version: PROTOCOL_VERSION };
+
+        let tag = conn.send(command::AUTH, |w| {
+            w.u32(PROTOCOL_VERSION).arbitrary(&cookie());
+        })?;
+        let mut reply = conn.wait_reply(tag)?;
+        // The high bits carry shared memory flags.
+        let server_version = reply.u32()? & 0xffff;
+        conn.version = server_version.min(PROTOCOL_VERSION);
+
+        let tag = conn.send(command::SET_CLIENT_NAME, |w| {
+            w.proplist(&[
+                ("application.name", client_name),
+                ("application.id", "loki-control-center"),
+                ("application.process.id", &std::process::id().to_string()),
+            ]);
+        })?;
+        conn.wait_reply(tag)?;
+        Ok(conn)
+    }
+
+    /// Send a command; returns its tag, which the reply carries.
+    pub fn send(&mut self, command: u32, args: impl FnOnce(&mut TagWriter)) -> io::Result<u32> {
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1) & 0x7fff_ffff;
+        let mut w = TagWriter::new(command, tag);
+        args(&mut w);
+        let mut header = [0u8; 20];
+        header[0..4].copy_from_slice(&(w.buf.len() as u32).to_be_bytes());
+        header[4..8].copy_from_slice(&CONTROL_CHANNEL.to_be_bytes());
+        self.stream.write_all(&header)?;
+        self.stream.write_all(&w.buf)?;
+        Ok(tag)
+    }
+
+    /// Read the next control packet, skipping audio data.
+    pub fn recv(&mut self) -> io::Result<Packet> {
+        loop {
+            let mut header = [0u8; 20];
+            self.stream.read_exact(&mut header)?;
+            let len = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+            let channel = u32::from_be_bytes(header[4..8].try_into().unwrap());
+            let mut data = vec![0; len];
+            self.stream.read_exact(&mut data)?;
+            if channel != CONTROL_CHANNEL {
+                continue;
+            }
+            let mut body = TagReader::new(data);
+            let command = body.u32()?;
+            let tag = body.u32()?;
+            return Ok(Packet { command, tag, body });
+        }
+    }
+
+    /// Wait up to `timeout` for a packet to arrive. `recv` after `true`
+    /// reads a whole packet without blocking for long.
+    pub fn poll(&self, timeout: Duration) -> io::Result<bool> {
+        let mut pfd = libc::pollfd { fd: self.stream.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: one pollfd on a descriptor we own.
+        let n = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::Interrupted { Ok(false) } else { Err(err) };
+        }
+        Ok(n > 0)
+    }
+
+    /// Block until the reply to `tag`, dropping anything else. Only used
+    /// during setup, before there are events to lose.
+    fn wait_reply(&mut self, tag: u32) -> io::Result<TagReader> {
+        loop {
+            let mut packet = self.recv()?;
+            if packet.tag != tag {
+                continue;
+            }
+            return match packet.command {
+                command::REPLY => Ok(packet.body),
+                command::ERROR => Err(bad(format!("server error {}", packet.body.u32()?))),
+                c => Err(bad(format!("unexpected command {c}"))),
+            };
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Radio kill switch state, followed live through `/dev/rfkill` events.
+//! Changes are made by the daemon, which sets explicit soft blocks.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEVICE: &str = "/dev/rfkill";
+const RFKILL_CLASS: &str = "/sys/class/rfkill";
+/// How often sysfs is re-read when `/dev/rfkill` cannot be opened.
+const FALLBACK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Radio types from `linux/rfkill.h`, by their sysfs names.
+const TYPES: &[(u8, &str)] = &[
+    (1, "wlan"),
+    (2, "bluetooth"),
+    (3, "ultrawideband"),
+    (4, "wimax"),
+    (5, "wwan"),
+    (6, "gps"),
+    (7, "fm"),
+    (8, "nfc"),
+];
+
+const OP_ADD: u8 = 0;
+const OP_DEL: u8 = 1;
+const OP_CHANGE: u8 = 2;
+/// Size of the original event; newer kernels append fields.
+const EVENT_SIZE_V1: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Radio {
+    /// Kernel type name, `wlan`, `bluetooth`, ...
+    pub kind: &'static str,
+    pub soft: bool,
+    pub hard: bool,
+}
+
+/// Every radio by rfkill index.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Radios(pub BTreeMap<u32, Radio>);
+
+impl Radios {
+    fn of<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a Radio> {
+        self.0.values().filter(move |r| r.kind == kind)
+    }
+
+    pub fn has(&self, kind: &str) -> bool {
+        self.of(kind).next().is_some()
+    }
+
+    /// Some radio of `kind` is neither soft- nor hard-blocked.
+    pub fn enabled(&self, kind: &str) -> bool {
+        self.of(kind).any(|r| !r.soft && !r.hard)
+    }
+
+    /// Every radio of `kind` is held off by a hardware switch.
+    pub fn hard_blocked(&self, kind: &str) -> bool {
+        self.has(kind) && self.of(kind).all(|r| r.hard)
+    }
+
+    /// Airplane mode: there are radios and all of them are blocked.
+    pub fn airplane(&self) -> bool {
+        !self.0.is_empty() && self.0.values().all(|r| r.soft || r.hard)
+    }
+}
+
+fn type_name(kind: u8) -> Option<&'static str> {
+    TYPES.iter().find(|(t, _)| *t == kind).map(|(_, n)| *n)
+}
+
+/// Apply one `struct rfkill_event`: index, type, op, soft, hard.
+pub fn apply_event(radios: &mut Radios, event: &[u8]) {
+    if event.len() < EVENT_SIZE_V1 {
+        return;
+    }
+    let index = u32::from_ne_bytes(event[0..4].try_into().unwrap());
+    let (kind, op, soft, hard) = (event[4], event[5], event[6] != 0, event[7] != 0);
+    match op {
+        OP_ADD | OP_CHANGE => {
+            if let Some(kind) = type_name(kind) {
+                radios.0.insert(index, Radio { kind, soft, hard });
+            }
+        }
+        OP_DEL => {
+            radios.0.remove(&index);
+        }
+        _ => {}
+    }
+}
+
+fn read_sysfs() -> Radios {
+    let Ok(entries) = std::fs::read_dir(RFKILL_CLASS) else {
+        return Radios::default();
+    };
+    let read = |dir: &Path, name: &str| {
+        std::fs::read_to_string(dir.join(name)).ok().map(|s| s.trim().to_string())
+    };
+    let mut radios = Radios::default();
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let index = entry.file_name().to_str().and_then(|n| n.strip_prefix("rfkill")?.parse().ok());
+        let kind = read(&dir, "type").and_then(|t| TYPES.iter().find(|(_, n)| *n == t).map(|(_, n)| *n));
+        if let (Some(index), Some(kind)) = (index, kind) {
+            let soft = read(&dir, "soft").as_deref() == Some("1");
+            let hard = read(&dir, "hard").as_deref() == Some("1");
+            radios.0.insert(index, Radio { kind, soft, hard });
+        }
+    }
+    radios
+}
+
+/// Re-read sysfs every [`FALLBACK_INTERVAL`] and store the radios in
+/// `latest` when they change.
+fn poll_sysfs(latest: &Mutex<Option<Radios>>) -> ! {
+    let mut last = None;
+    loop {
+        let radios = read_sysfs();
+        if last.as_ref() != Some(&radios) {
+            *latest.lock().unwrap() = Some(radios.clone());
+            last = Some(radios);
+        }
+        std::thread::sleep(FALLBACK_INTERVAL);
+    }
+}
+
+/// Follow rfkill events and store the radios in `latest` after every
+/// change. Opening `/dev/rfkill` replays an add event for each existing
+/// radio. Without it, or once reading it fails, sysfs is polled instead.
+pub fn spawn_watcher(latest: Arc<Mutex<Option<Radios>>>) {
+    std::thread::spawn(move || {
+        let mut file = match File::open(DEVICE) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("Cannot open {}: {}; polling sysfs instead", DEVICE, e);
+                poll_sysfs(&latest);
+            }
+        };
+        let mut radios = Radios::default();
+        let mut buf = [0u8; 64];
+        loop {
+            // Each read returns exactly one event.
+            match file.read(&mut buf) {
+                Ok(0) => {
+                    eprintln!("{} closed; polling sysfs instead", DEVICE);
+                    break;
+                }
+                Ok(n) => {
+                    apply_event(&mut radios, &buf[..n]);
+                    *latest.lock().unwrap() = Some(radios.clone());
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    eprintln!("rfkill watch failed: {}; polling sysfs instead", e);
+                    break;
+                }
+            }
+        }
+        poll_sysfs(&latest);
+    });
+}
@@ -0,0 +1,166 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::client::{request_async, tokio_rt};
+
+/// How much history the graphs show.
+pub const WINDOW: Duration = Duration::from_secs(5 * 60);
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The subset of the daemon's telemetry sample the panel graphs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Sample {
+    pub ts_ms: u64,
+    pub cpu_temp_c: Option<f32>,
+    pub fan_rpm: Option<u32>,
+    pub package_power_w: Option<f32>,
+    pub battery_power_w: Option<f32>,
+    pub gpu_mhz: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    CpuTemp,
+    FanRpm,
+    PackagePower,
+    BatteryDischarge,
+    GpuClock,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 5] = [
+        Metric::CpuTemp,
+        Metric::FanRpm,
+        Metric::PackagePower,
+        Metric::BatteryDischarge,
+        Metric::GpuClock,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Metric::CpuTemp => "CPU temperature",
+            Metric::FanRpm => "Fan speed",
+            Metric::PackagePower => "Package power",
+            Metric::BatteryDischarge => "Battery discharge",
+            Metric::GpuClock => "GPU clock",
+        }
+    }
+
+    pub fn format(self, v: f32) -> String {
+        match self {
+            Metric::CpuTemp => format!("{v:.0} °C"),
+            Metric::FanRpm => format!("{v:.0} RPM"),
+            Metric::GpuClock => format!("{v:.0} MHz"),
+            Metric::PackagePower | Metric::BatteryDischarge => format!("{v:.1} W"),
+        }
+    }
+
+    fn value(self, s: &Sample) -> Option<f32> {
+        match self {
+            Metric::CpuTemp => s.cpu_temp_c,
+            Metric::FanRpm => s.fan_rpm.map(|r| r as f32),
+            Metric::PackagePower => s.package_power_w,
+            Metric::BatteryDischarge => s.battery_power_w,
+            Metric::GpuClock => s.gpu_mhz.map(|m| m as f32),
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Samples from the last [`WINDOW`], fed by [`spawn_poller`].
+#[derive(Default)]
+pub struct Monitor {
+    samples: VecDeque<Sample>,
+}
+
+impl Monitor {
+    fn push(&mut self, sample: Sample) {
+        if self.samples.back().is_some_and(|s| s.ts_ms >= sample.ts_ms) {
+            return;
+        }
+        self.samples.push_back(sample);
+        let cutoff = now_ms().saturating_sub(WINDOW.as_millis() as u64);
+        while self.samples.front().is_some_and(|s| s.ts_ms < cutoff) {
+            self.samples.pop_front();
+        }
+    }
+
+    fn last_ts(&self) -> Option<u64> {
+        self.samples.back().map(|s| s.ts_ms)
+    }
+
+    pub fn latest(&self, metric: Metric) -> Option<f32> {
+        self.samples.iter().rev().find_map(|s| metric.value(s))
+    }
+
+    /// Polyline for `metric` scaled into a `width` x `height` area: time runs
+    /// left to right over [`WINDOW`] and values fill the height between their
+    /// minimum and maximum.
+    pub fn points(&self, metric: Metric, width: f64, height: f64) -> Vec<(f64, f64)> {
+        let values: Vec<(u64, f32)> = self
+            .samples
+            .iter()
+            .filter_map(|s| Some((s.ts_ms, metric.value(s)?)))
+            .collect();
+        let Some(&(_, first)) = values.first() else {
+            return Vec::new();
+        };
+        let (min, max) = values
+            .iter()
+            .fold((first, first), |(lo, hi), &(_, v)| (lo.min(v), hi.max(v)));
+        let end = now_ms();
+        let window = WINDOW.as_millis() as f64;
+        let pad = 2.0;
+        values
+            .iter()
+            .map(|&(ts, v)| {
+                let age = end.saturating_sub(ts) as f64;
+                let x = width * (1.0 - age / window);
+                // Keep flat lines in the middle instead of dividing by zero.
+                let norm = if max > min {
+                    ((v - min) / (max - min)) as f64
+                } else {
+                    0.5
+                };
+                let y = pad + (height - 2.0 * pad) * (1.0 - norm);
+                (x, y)
+            })
+            .collect()
+    }
+}
+
+/// Poll the daemon's telemetry history every [`POLL_INTERVAL`] and append new
+/// samples to `monitor`.
+pub fn spawn_poller(monitor: Arc<Mutex<Monitor>>) {
+    tokio_rt().spawn(async move {
+        loop {
+            let since = monitor
+                .lock()
+                .unwrap()
+                .last_ts()
+                .map(|t| t + 1)
+                .unwrap_or_else(|| now_ms().saturating_sub(WINDOW.as_millis() as u64));
+            if let Some(data) = request_async(json!({"cmd": "telemetry", "since_ms": since})).await {
+                match serde_json::from_value::<Vec<Sample>>(data) {
+                    Ok(samples) => {
+                        let mut m = monitor.lock().unwrap();
+                        for s in samples {
+                            m.push(s);
+                        }
+                    }
+                    Err(e) => eprintln!("bad telemetry data: {e}"),
+                }
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,1293 @@
+//! Wi-Fi networks through NetworkManager's D-Bus API: visible networks,
+//! connecting to saved or new networks, and forgetting them. The panel talks
+//! to NetworkManager itself, on the user's system bus connection, so
+//! NetworkManager's polkit rules apply to the user. Requests run on the tokio
+//! runtime and their results are left for the GUI to pick up.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+use zbus::{proxy, Connection};
+
+use crate::client::{system_bus, tokio_rt};
+
+const NM_DEVICE_TYPE_WIFI: u32 = 2;
+const NM_ACTIVE_CONNECTION_STATE_ACTIVATED: u32 = 2;
+const NM_ACTIVE_CONNECTION_STATE_DEACTIVATED: u32 = 4;
+const AP_FLAGS_PRIVACY: u32 = 0x1;
+const KEY_MGMT_PSK: u32 = 0x100;
+const KEY_MGMT_802_1X: u32 = 0x200;
+const KEY_MGMT_SAE: u32 = 0x400;
+const KEY_MGMT_OWE: u32 = 0x800;
+const SECURITY: &str = "802-11-wireless-security";
+/// How long a connection attempt may take before it is reported as failed.
+const ACTIVATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long access points take to show up after a scan request.
+const SCAN_SETTLE: Duration = Duration::from_secs(3);
+
+type Settings = HashMap<String, HashMap<String, OwnedValue>>;
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager"
+)]
+trait NetworkManager {
+    fn get_devices(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    fn activate_connection(
+        &self,
+        connection: &ObjectPath<'_>,
+        device: &ObjectPath<'_>,
+        specific_object: &ObjectPath<'_>,
+    ) -> zbus::Result<OwnedObjectPath>;
+
+    fn add_and_activate_connection2(
+        &self,
+        connection: HashMap<&str, HashMap<&str, Value<'_>>>,
+        device: &ObjectPath<'_>,
+        specific_object: &ObjectPath<'_>,
+        options: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<(
+        OwnedObjectPath,
+        OwnedObjectPath,
+        HashMap<String, OwnedValue>,
+    )>;
+
+    #[zbus(property)]
+    fn wireless_enabled(&self) -> zbus::Result<bool>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Device",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait Device {
+    fn disconnect(&self) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn device_type(&self) -> zbus::Result<u32>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Device.Wireless",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait Wireless {
+    fn request_scan(&self, options: HashMap<&str, Value<'_>>) -> zbus::Result<()>;
+
+    fn get_all_access_points(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn active_access_point(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.AccessPoint",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait AccessPoint {
+    #[zbus(property)]
+    fn ssid(&self) -> zbus::Result<Vec<u8>>;
+
+    #[zbus(property)]
+    fn strength(&self) -> zbus::Result<u8>;
+
+    #[zbus(property)]
+    fn flags(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn wpa_flags(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn rsn_flags(&self) -> zbus::Result<u32>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Settings",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager/Settings"
+)]
+trait NmSettings {
+    fn list_connections(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Settings.Connection",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait SettingsConnection {
+    fn get_settings(&self) -> zbus::Result<Settings>;
+
+    fn get_secrets(&self, setting_name: &str) -> zbus::Result<Settings>;
+
+    /// Change the profile in memory only; [`Self::save`] writes it out.
+    fn update_unsaved(&self, properties: &Settings) -> zbus::Result<()>;
+
+    fn save(&self) -> zbus::Result<()>;
+
+    fn delete(&self) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Connection.Active",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait ActiveConnection {
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<u32>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Network {
+    pub ssid: String,
+    /// Signal strength of the strongest access point, in percent.
+    pub strength: u8,
+    /// `open`, `wep`, `wpa-psk`, `sae`, `owe` or `enterprise`, or `unknown`
+    /// for a saved network that is out of range.
+    pub security: String,
+    /// NetworkManager has a saved connection for it.
+    pub known: bool,
+    pub active: bool,
+    /// Saved networks are listed even when out of range, so they can be
+    /// forgotten.
+    pub in_range: bool,
+}
+
+impl Network {
+    /// Joining needs a password typed in first.
+    pub fn needs_password(&self) -> bool {
+        !self.known && !matches!(self.security.as_str(), "open" | "owe" | "enterprise")
+    }
+
+    /// Joining from here is possible at all; enterprise networks need
+    /// certificates and identities set up in NetworkManager.
+    pub fn joinable(&self) -> bool {
+        self.in_range && (self.known || self.security != "enterprise")
+    }
+
+    pub fn security_label(&self) -> &str {
+        match self.security.as_str() {
+            "open" => "Open",
+            "wep" => "WEP",
+            "wpa-psk" => "WPA",
+            "sae" => "WPA3",
+            "owe" => "Enhanced open",
+            "enterprise" => "Enterprise",
+            "unknown" => "Unknown",
+            other => other,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    pub enabled: bool,
+    /// Active network first, then saved ones, then by signal.
+    pub networks: Vec<Network>,
+}
+
+/// Security of an access point from its flags, named after the key
+/// management NetworkManager uses for it. Transition networks offering both
+/// WPA2 and WPA3 count as `wpa-psk`, which works for both.
+pub fn security(flags: u32, wpa_flags: u32, rsn_flags: u32) -> &'static str {
+    let key_mgmt = wpa_flags | rsn_flags;
+    if key_mgmt & KEY_MGMT_802_1X != 0 {
+        "enterprise"
+    } else if key_mgmt & KEY_MGMT_PSK != 0 {
+        "wpa-psk"
+    } else if key_mgmt & KEY_MGMT_SAE != 0 {
+        "sae"
+    } else if key_mgmt & KEY_MGMT_OWE != 0 {
+        "owe"
+    } else if flags & AP_FLAGS_PRIVACY != 0 {
+        "wep"
+    } else {
+        "open"
+    }
+}
+
+/// The secret that takes the password in a saved profile with this
+/// `key-mgmt`. Open and OWE profiles have none, and enterprise ones need
+/// more than a password.
+pub fn password_key(key_mgmt: Option<&str>) -> Option<&'static str> {
+    match key_mgmt {
+        Some("none") => Some("wep-key0"),
+        Some("wpa-psk" | "sae") => Some("psk"),
+        _ => None,
+    }
+}
+
+fn bus_error(e: zbus::Error) -> String {
+    match e {
+        zbus::Error::MethodError(name, msg, _) => match name.as_str() {
+            "org.freedesktop.DBus.Error.ServiceUnknown"
+            | "org.freedesktop.DBus.Error.NameHasNoOwner" => {
+                "NetworkManager is not running".to_string()
+            }
+            _ => msg.unwrap_or_else(|| name.to_string()),
+        },
+        e => format!("NetworkManager: {e}"),
+    }
+}
+
+/// The first Wi-Fi device, if there is one.
+async fn wifi_device(conn: &Connection) -> Result<Option<OwnedObjectPath>, String> {
+    let nm = NetworkManagerProxy::new(conn).await.map_err(bus_error)?;
+    for path in nm.get_devices().await.map_err(bus_error)? {
+        let dev = DeviceProxy::builder(conn)
+            .path(&path)
+            .map_err(bus_error)?
+            .build()
+            .await;
+        let is_wifi = match dev {
+            Ok(dev) => dev.device_type().await.ok() == Some(NM_DEVICE_TYPE_WIFI),
+            Err(_) => false,
+        };
+        if is_wifi {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+async fn require_device(conn: &Connection) -> Result<OwnedObjectPath, String> {
+    wifi_device(conn)
+        .await?
+        .ok_or_else(|| "no Wi-Fi device".to_string())
+}
+
+struct Ap {
+    path: OwnedObjectPath,
+    ssid: String,
+    strength: u8,
+    security: &'static str,
+}
+
+async fn access_points(conn: &Connection, device: &OwnedObjectPath) -> Result<Vec<Ap>, String> {
+    let wireless = WirelessProxy::builder(conn)
+        .path(device)
+        .map_err(bus_error)?
+        .build()
+        .await;
+    let paths = wireless
+        .map_err(bus_error)?
+        .get_all_access_points()
+        .await
+        .map_err(bus_error)?;
+    let mut aps = Vec::new();
+    for path in paths {
+        // Access points vanish between listing and reading them.
+        let Ok(ap) = AccessPointProxy::builder(conn)
+            .path(path.clone())
+            .map_err(bus_error)?
+            .build()
+            .await
+        else {
+            continue;
+        };
+        let Ok(ssid) = ap.ssid().await else {
+            continue;
+        };
+        let ssid = String::from_utf8_lossy(&ssid).into_owned();
+        if ssid.is_empty() {
+            // Hidden network.
+            continue;
+        }
+        let flags = ap.flags().await.unwrap_or(0);
+        let wpa = ap.wpa_flags().await.unwrap_or(0);
+        let rsn = ap.rsn_flags().await.unwrap_or(0);
+        aps.push(Ap {
+            path,
+            ssid,
+            strength: ap.strength().await.unwrap_or(0),
+            security: security(flags, wpa, rsn),
+        });
+    }
+    Ok(aps)
+}
+
+fn owned_string(settings: &Settings, section: &str, key: &str) -> Option<String> {
+    let value = settings.get(section)?.get(key)?;
+    String::try_from(value.try_clone().ok()?).ok()
+}
+
+fn owned_bytes(settings: &Settings, section: &str, key: &str) -> Option<Vec<u8>> {
+    let value = settings.get(section)?.get(key)?;
+    Vec::<u8>::try_from(value.try_clone().ok()?).ok()
+}
+
+/// Saved Wi-Fi connections as (SSID, settings path).
+async fn known_networks(conn: &Connection) -> Result<Vec<(String, OwnedObjectPath)>, String> {
+    let settings = NmSettingsProxy::new(conn).await.map_err(bus_error)?;
+    let mut known = Vec::new();
+    for path in settings.list_connections().await.map_err(bus_error)? {
+        let Ok(c) = SettingsConnectionProxy::builder(conn)
+            .path(&path)
+            .map_err(bus_error)?
+            .build()
+            .await
+        else {
+            continue;
+        };
+        let Ok(s) = c.get_settings().await else {
+            continue;
+        };
+        if owned_string(&s, "connection", "type").as_deref() != Some("802-11-wireless") {
+            continue;
+        }
+        if let Some(ssid) = owned_bytes(&s, "802-11-wireless", "ssid") {
+            known.push((String::from_utf8_lossy(&ssid).into_owned(), path));
+        }
+    }
+    Ok(known)
+}
+
+/// Visible and saved networks, or `None` without a Wi-Fi device.
+pub async fn status(conn: &Connection) -> Result<Option<Status>, String> {
+    let nm = NetworkManagerProxy::new(conn).await.map_err(bus_error)?;
+    let enabled = nm.wireless_enabled().await.map_err(bus_error)?;
+    let Some(device) = wifi_device(conn).await? else {
+        return Ok(None);
+    };
+    let wireless = WirelessProxy::builder(conn)
+        .path(&device)
+        .map_err(bus_error)?
+        .build()
+        .await;
+    let active_ap = wireless
+        .map_err(bus_error)?
+        .active_access_point()
+        .await
+        .ok();
+    let known = known_networks(conn).await?;
+
+    let mut networks: Vec<Network> = Vec::new();
+    for ap in access_points(conn, &device).await? {
+        let active = active_ap.as_ref() == Some(&ap.path);
+        if let Some(n) = networks.iter_mut().find(|n| n.ssid == ap.ssid) {
+            n.active |= active;
+            n.strength = n.strength.max(ap.strength);
+            continue;
+        }
+        networks.push(Network {
+            known: known.iter().any(|(ssid, _)| *ssid == ap.ssid),
+            ssid: ap.ssid,
+            strength: ap.strength,
+            security: ap.security.to_string(),
+            active,
+            in_range: true,
+        });
+    }
+    for (ssid, _) in &known {
+        if !networks.iter().any(|n| n.ssid == *ssid) {
+            networks.push(Network {
+                ssid: ssid.clone(),
+                strength: 0,
+                // Nothing is broadcasting it to tell.
+                security: "unknown".to_string(),
+                known: true,
+                active: false,
+                in_range: false,
+            });
+        }
+    }
+    networks.sort_by(|a, b| {
+        (b.active, b.in_range, b.strength).cmp(&(a.active, a.in_range, a.strength))
+    });
+    Ok(Some(Status { enabled, networks }))
+}
+
+/// Ask the device to scan. Results show up in `status` a few seconds later.
+pub async fn scan(conn: &Connection) -> Result<(), String> {
+    let device = require_device(conn).await?;
+    let wireless = WirelessProxy::builder(conn)
+        .path(&device)
+        .map_err(bus_error)?
+        .build()
+        .await;
+    wireless
+        .map_err(bus_error)?
+        .request_scan(HashMap::new())
+        .await
+        .map_err(bus_error)
+}
+
+/// Settings for a new connection to `ssid`.
+fn new_connection<'a>(
+    ssid: &'a str,
+    security: &'static str,
+    password: Option<&'a str>,
+) -> Result<HashMap<&'static str, HashMap<&'static str, Value<'a>>>, String> {
+    let mut settings = HashMap::new();
+    settings.insert(
+        "connection",
+        HashMap::from([
+            ("id", Value::from(ssid)),
+            ("type", Value::from("802-11-wireless")),
+        ]),
+    );
+    settings.insert(
+        "802-11-wireless",
+        HashMap::from([
+            ("ssid", Value::from(ssid.as_bytes().to_vec())),
+            ("mode", Value::from("infrastructure")),
+        ]),
+    );
+    let secret = || password.ok_or_else(|| format!("{ssid} needs a password"));
+    let sec = match security {
+        "open" => None,
+        "owe" => Some(HashMap::from([("key-mgmt", Value::from("owe"))])),
+        "wep" => Some(HashMap::from([
+            ("key-mgmt", Value::from("none")),
+            ("wep-key0", Value::from(secret()?)),
+            // A key rather than a passphrase.
+            ("wep-key-type", Value::from(1u32)),
+        ])),
+        "wpa-psk" | "sae" => Some(HashMap::from([
+            ("key-mgmt", Value::from(security)),
+            ("psk", Value::from(secret()?)),
+        ])),
+        _ => {
+            return Err(format!(
+                "{ssid} uses enterprise authentication; set it up in NetworkManager"
+            ))
+        }
+    };
+    if let Some(sec) = sec {
+        settings.insert(SECURITY, sec);
+    }
+    Ok(settings)
+}
+
+/// Wait for an activation to finish.
+async fn wait_activated(
+    conn: &Connection,
+    active: &OwnedObjectPath,
+    ssid: &str,
+) -> Result<(), String> {
+    let proxy = ActiveConnectionProxy::builder(conn)
+        .path(active)
+        .map_err(bus_error)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+        .map_err(bus_error)?;
+    let started = Instant::now();
+    while started.elapsed() < ACTIVATION_TIMEOUT {
+        match proxy.state().await {
+            Ok(NM_ACTIVE_CONNECTION_STATE_ACTIVATED) => return Ok(()),
+            // The object goes away once a failed activation is torn down.
+            Ok(NM_ACTIVE_CONNECTION_STATE_DEACTIVATED) | Err(_) => {
+                return Err(format!("could not connect to {ssid}"))
+            }
+            Ok(_) => tokio::time::sleep(Duration::from_millis(250)).await,
+        }
+    }
+    Err(format!("timed out connecting to {ssid}"))
+}
+
+fn copy_settings(settings: &Settings) -> Settings {
+    settings
+        .iter()
+        .map(|(section, values)| {
+            let values = values
+                .iter()
+                .filter_map(|(k, v)| Some((k.clone(), v.try_clone().ok()?)))
+                .collect();
+            (section.clone(), values)
+        })
+        .collect()
+}
+
+/// Activate the saved profile at `path`, with `password` in place of its own
+/// if given. The new password is only written to disk once it has worked;
+/// otherwise the profile's old secrets are put back.
+async fn connect_saved(
+    conn: &Connection,
+    path: &OwnedObjectPath,
+    device: &OwnedObjectPath,
+    specific: &OwnedObjectPath,
+    ssid: &str,
+    password: Option<&str>,
+) -> Result<(), String> {
+    let nm = NetworkManagerProxy::new(conn).await.map_err(bus_error)?;
+    let activate = || async {
+        let active = nm
+            .activate_connection(&path.as_ref(), &device.as_ref(), &specific.as_ref())
+            .await
+            .map_err(bus_error)?;
+        wait_activated(conn, &active, ssid).await
+    };
+    let Some(password) = password else {
+        return activate().await;
+    };
+
+    let saved = SettingsConnectionProxy::builder(conn)
+        .path(path)
+        .map_err(bus_error)?
+        .build()
+        .await;
+    let saved = saved.map_err(bus_error)?;
+    let mut original = saved.get_settings().await.map_err(bus_error)?;
+    let key_mgmt = owned_string(&original, SECURITY, "key-mgmt");
+    let Some(key) = password_key(key_mgmt.as_deref()) else {
+        return Err(format!("{ssid} does not take a password"));
+    };
+    // Secrets are not part of the settings; fetch them so they can be
+    // restored.
+    if let Ok(secrets) = saved.get_secrets(SECURITY).await {
+        for (section, values) in secrets {
+            original.entry(section).or_default().extend(values);
+        }
+    }
+    let mut updated = copy_settings(&original);
+    let value = OwnedValue::try_from(Value::from(password)).map_err(|e| e.to_string())?;
+    updated
+        .entry(SECURITY.into())
+        .or_default()
+        .insert(key.into(), value);
+    saved.update_unsaved(&updated).await.map_err(bus_error)?;
+
+    let result = activate().await;
+    match &result {
+        Ok(()) => saved.save().await.map_err(bus_error)?,
+        Err(_) => {
+            if let Err(e) = saved.update_unsaved(&original).await {
+                eprintln!(
+                    "Failed to restore {ssid}'s saved password: {}",
+                    bus_error(e)
+                );
+            }
+        }
+    }
+    result
+}
+
+/// Connect to `ssid`. A saved network is activated as is, or with a new
+/// password. Otherwise an in-memory profile is created for the visible
+/// network, saved once it connects and removed if it does not.
+pub async fn connect_network(
+    conn: &Connection,
+    ssid: &str,
+    password: Option<&str>,
+) -> Result<(), String> {
+    let nm = NetworkManagerProxy::new(conn).await.map_err(bus_error)?;
+    let device = require_device(conn).await?;
+    let ap = access_points(conn, &device)
+        .await?
+        .into_iter()
+        .filter(|ap| ap.ssid == ssid)
+        .max_by_key(|ap| ap.strength);
+    // "/" lets NetworkManager pick the access point.
+    let specific = match &ap {
+        Some(ap) => ap.path.clone(),
+        None => ObjectPath::from_static_str_unchecked("/").into(),
+    };
+
+    let known = known_networks(conn).await?;
+    if let Some((_, path)) = known.iter().find(|(s, _)| s == ssid) {
+        return connect_saved(conn, path, &device, &specific, ssid, password).await;
+    }
+
+    let ap = ap.ok_or_else(|| format!("{ssid} is not in range"))?;
+    let settings = new_connection(ssid, ap.security, password)?;
+    let options = HashMap::from([("persist", Value::from("memory"))]);
+    let (path, active, _) = nm
+        .add_and_activate_connection2(settings, &device.as_ref(), &specific.as_ref(), options)
+        .await
+        .map_err(bus_error)?;
+    let saved = SettingsConnectionProxy::builder(conn)
+        .path(&path)
+        .map_err(bus_error)?
+        .build()
+        .await;
+    let saved = saved.map_err(bus_error)?;
+    match wait_activated(conn, &active, ssid).await {
+        Ok(()) => saved.save().await.map_err(bus_error),
+        Err(e) => {
+            // Don't keep a profile with a wrong password.
+            let _ = saved.delete().await;
+            Err(e)
+        }
+    }
+}
+
+pub async fn disconnect(conn: &Connection) -> Result<(), String> {
+    let device = require_device(conn).await?;
+    let dev = DeviceProxy::builder(conn)
+        .path(&device)
+        .map_err(bus_error)?
+        .build()
+        .await;
+    dev.map_err(bus_error)?
+        .disconnect()
+        .await
+        .map_err(bus_error)
+}
+
+/// Delete every saved connection for `ssid`.
+pub async fn forget(conn: &Connection, ssid: &str) -> Result<(), String> {
+    let known = known_networks(conn).await?;
+    let paths: Vec<_> = known
+        .into_iter()
+        .filter(|(s, _)| s == ssid)
+        .map(|(_, p)| p)
+        .collect();
+    if paths.is_empty() {
+        return Err(format!("{ssid} is not saved"));
+    }
+    for path in paths {
+        let saved = SettingsConnectionProxy::builder(conn)
+            .path(&path)
+            .map_err(bus_error)?
+            .build()
+            .await;
+        saved
+            .map_err(bus_error)?
+            .delete()
+            .await
+            .map_err(bus_error)?;
+    }
+    Ok(())
+}
+
+/// What came back from NetworkManager since the last poll.
+#[derive(Debug, Default)]
+pub struct Update {
+    /// `None` when there is no Wi-Fi device or NetworkManager cannot be
+    /// reached.
+    pub status: Option<Option<Status>>,
+    /// Error from the last action, for display.
+    pub error: Option<String>,
+    /// No action is in flight.
+    pub idle: bool,
+}
+
+enum Action {
+    Scan,
+    Connect {
+        ssid: String,
+        password: Option<String>,
+    },
+    Disconnect,
+    Forget {
+        ssid: String,
+    },
+}
+
+async fn perform(action: &Action) -> Result<(), String> {
+    let conn = system_bus().await?;
+    match action {
+        Action::Scan => scan(&conn).await,
+        Action::Connect { ssid, password } => {
+            connect_network(&conn, ssid, password.as_deref()).await
+        }
+        Action::Disconnect => disconnect(&conn).await,
+        Action::Forget { ssid } => forget(&conn, ssid).await,
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Wifi {
+    latest: Arc<Mutex<Option<Update>>>,
+}
+
+impl Wifi {
+    pub fn take_update(&self) -> Option<Update> {
+        self.latest.lock().unwrap().take()
+    }
+
+    fn post(&self, f: impl FnOnce(&mut Update)) {
+        let mut latest = self.latest.lock().unwrap();
+        f(latest.get_or_insert_with(Update::default));
+    }
+
+    /// Perform `action`, then re-read the network list.
+    fn run(&self, action: Action) {
+        let wifi = self.clone();
+        tokio_rt().spawn(async move {
+            if let Err(e) = perform(&action).await {
+                wifi.post(|u| u.error = Some(e));
+            }
+            if matches!(action, Action::Scan) {
+                tokio::time::sleep(SCAN_SETTLE).await;
+            }
+            wifi.fetch().await;
+            wifi.post(|u| u.idle = true);
+        });
+    }
+
+    async fn fetch(&self) {
+        let result = match system_bus().await {
+            Ok(conn) => status(&conn).await,
+            Err(e) => Err(e),
+        };
+        let status = result.unwrap_or_else(|e| {
+            eprintln!("Wi-Fi status unavailable: {e}");
+            None
+        });
+        self.post(|u| u.status = Some(status));
+    }
+
+    pub fn refresh(&self) {
+        let wifi = self.clone();
+        tokio_rt().spawn(async move { wifi.fetch().await });
+    }
+
+    pub fn scan(&self) {
+        self.run(Action::Scan);
+    }
+
+    pub fn connect(&self, ssid: &str, password: Option<&str>) {
+        self.run(Action::Connect {
+            ssid: ssid.to_string(),
+            password: password.map(str::to_string),
+        });
+    }
+
+    pub fn disconnect(&self) {
+        self.run(Action::Disconnect);
+    }
+
+    pub fn forget(&self, ssid: &str) {
+        self.run(Action::Forget {
+            ssid: ssid.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus::{temp_dir, PrivateBus};
+    use std::collections::BTreeMap;
+    use zbus::object_server::ObjectServer;
+    use zbus::{fdo, interface};
+
+    const NM_PATH: &str = "/org/freedesktop/NetworkManager";
+    const DEVICE_PATH: &str = "/org/freedesktop/NetworkManager/Devices/1";
+    const SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
+    const SECRETS: [&str; 2] = ["psk", "wep-key0"];
+
+    fn path(p: &str) -> OwnedObjectPath {
+        ObjectPath::try_from(p).unwrap().into()
+    }
+
+    fn profile_path(id: u32) -> OwnedObjectPath {
+        path(&format!("{SETTINGS_PATH}/{id}"))
+    }
+
+    fn ap_path(ssid: &[u8]) -> Option<OwnedObjectPath> {
+        ACCESS_POINTS
+            .iter()
+            .position(|ap| ap.ssid.as_bytes() == ssid)
+            .map(|i| path(&format!("{NM_PATH}/AccessPoint/{}", i + 1)))
+    }
+
+    struct Profile {
+        /// What NetworkManager runs with, secrets included.
+        settings: Settings,
+        /// What is written to disk; `None` until saved.
+        disk: Option<Settings>,
+    }
+
+    #[derive(Default)]
+    struct Mock {
+        active_ap: Option<OwnedObjectPath>,
+        profiles: BTreeMap<u32, Profile>,
+        next_id: u32,
+    }
+
+    impl Mock {
+        fn add(&mut self, settings: Settings, saved: bool) -> u32 {
+            self.next_id += 1;
+            let disk = saved.then(|| copy_settings(&settings));
+            self.profiles
+                .insert(self.next_id, Profile { settings, disk });
+            self.next_id
+        }
+
+        fn profile(&self, ssid: &str) -> Option<&Profile> {
+            self.profiles.values().find(|p| {
+                owned_bytes(&p.settings, "802-11-wireless", "ssid").as_deref()
+                    == Some(ssid.as_bytes())
+            })
+        }
+
+        /// The secret `key` of `ssid`'s profile as saved on disk.
+        fn saved_secret(&self, ssid: &str, key: &str) -> Option<String> {
+            owned_string(self.profile(ssid)?.disk.as_ref()?, SECURITY, key)
+        }
+    }
+
+    type Shared = Arc<Mutex<Mock>>;
+
+    fn profile(ssid: &str, security: &[(&str, &str)]) -> Settings {
+        let value = |v: Value<'_>| OwnedValue::try_from(v).unwrap();
+        let mut settings = HashMap::from([
+            (
+                "connection".to_string(),
+                HashMap::from([
+                    ("id".to_string(), value(Value::from(ssid))),
+                    ("type".to_string(), value(Value::from("802-11-wireless"))),
+                ]),
+            ),
+            (
+                "802-11-wireless".to_string(),
+                HashMap::from([("ssid".to_string(), value(Value::from(ssid.as_bytes())))]),
+            ),
+        ]);
+        if !security.is_empty() {
+            let security = security
+                .iter()
+                .map(|(k, v)| (k.to_string(), value(Value::from(*v))));
+            settings.insert(SECURITY.to_string(), security.collect());
+        }
+        settings
+    }
+
+    struct MockNetworkManager {
+        mock: Shared,
+    }
+
+    impl MockNetworkManager {
+        /// Create an active connection for the profile `id`, which fails
+        /// when its password is `wrong`.
+        async fn activate(&self, server: &ObjectServer, id: u32) -> OwnedObjectPath {
+            let (seq, state) = {
+                let mut mock = self.mock.lock().unwrap();
+                let settings = &mock.profiles[&id].settings;
+                let wrong = SECRETS
+                    .iter()
+                    .any(|k| owned_string(settings, SECURITY, k).as_deref() == Some("wrong"));
+                let ssid = owned_bytes(settings, "802-11-wireless", "ssid").unwrap_or_default();
+                mock.next_id += 1;
+                if wrong {
+                    (mock.next_id, NM_ACTIVE_CONNECTION_STATE_DEACTIVATED)
+                } else {
+                    mock.active_ap = ap_path(&ssid);
+                    (mock.next_id, NM_ACTIVE_CONNECTION_STATE_ACTIVATED)
+                }
+            };
+            let active = path(&format!("{NM_PATH}/ActiveConnection/{seq}"));
+            server
+                .at(&active, MockActiveConnection { state })
+                .await
+                .unwrap();
+            active
+        }
+    }
+
+    #[interface(name = "org.freedesktop.NetworkManager")]
+    impl MockNetworkManager {
+        fn get_devices(&self) -> Vec<OwnedObjectPath> {
+            vec![path(DEVICE_PATH)]
+        }
+
+        async fn activate_connection(
+            &self,
+            connection: OwnedObjectPath,
+            _device: OwnedObjectPath,
+            _specific_object: OwnedObjectPath,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> fdo::Result<OwnedObjectPath> {
+            let id = {
+                let mock = self.mock.lock().unwrap();
+                mock.profiles
+                    .keys()
+                    .copied()
+                    .find(|id| profile_path(*id) == connection)
+            };
+            let id = id.ok_or_else(|| fdo::Error::UnknownObject(connection.to_string()))?;
+            Ok(self.activate(server, id).await)
+        }
+
+        async fn add_and_activate_connection2(
+            &self,
+            connection: Settings,
+            _device: OwnedObjectPath,
+            _specific_object: OwnedObjectPath,
+            options: HashMap<String, OwnedValue>,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> (
+            OwnedObjectPath,
+            OwnedObjectPath,
+            HashMap<String, OwnedValue>,
+        ) {
+            let persist = options
+                .get("persist")
+                .and_then(|v| String::try_from(v.try_clone().ok()?).ok());
+            let saved = persist.as_deref() != Some("memory");
+            let id = self.mock.lock().unwrap().add(connection, saved);
+            server
+                .at(
+                    profile_path(id),
+                    MockSettingsConnection {
+                        mock: self.mock.clone(),
+                        id,
+                    },
+                )
+                .await
+                .unwrap();
+            (
+                profile_path(id),
+                self.activate(server, id).await,
+                HashMap::new(),
+            )
+        }
+
+        #[zbus(property)]
+        fn wireless_enabled(&self) -> bool {
+            true
+        }
+    }
+
+    struct MockDevice {
+        mock: Shared,
+    }
+
+    #[interface(name = "org.freedesktop.NetworkManager.Device")]
+    impl MockDevice {
+        fn disconnect(&self) {
+            self.mock.lock().unwrap().active_ap = None;
+        }
+
+        #[zbus(property)]
+        fn device_type(&self) -> u32 {
+            NM_DEVICE_TYPE_WIFI
+        }
+    }
+
+    struct MockWireless {
+        mock: Shared,
+    }
+
+    #[interface(name = "org.freedesktop.NetworkManager.Device.Wireless")]
+    impl MockWireless {
+        fn request_scan(&self, _options: HashMap<String, OwnedValue>) {}
+
+        fn get_all_access_points(&self) -> Vec<OwnedObjectPath> {
+            (1..=ACCESS_POINTS.len())
+                .map(|i| path(&format!("{NM_PATH}/AccessPoint/{i}")))
+                .collect()
+        }
+
+        #[zbus(property)]
+        fn active_access_point(&self) -> OwnedObjectPath {
+            self.mock
+                .lock()
+                .unwrap()
+                .active_ap
+                .clone()
+                .unwrap_or_else(|| path("/"))
+        }
+    }
+
+    struct MockAccessPoint {
+        ssid: &'static str,
+        strength: u8,
+        flags: u32,
+        rsn_flags: u32,
+    }
+
+    const ACCESS_POINTS: &[MockAccessPoint] = &[
+        MockAccessPoint {
+            ssid: "Home",
+            strength: 80,
+            flags: 1,
+            rsn_flags: 0x188,
+        },
+        MockAccessPoint {
+            ssid: "Cafe",
+            strength: 55,
+            flags: 0,
+            rsn_flags: 0,
+        },
+        MockAccessPoint {
+            ssid: "Office",
+            strength: 40,
+            flags: 1,
+            rsn_flags: 0x288,
+        },
+        MockAccessPoint {
+            ssid: "Neighbour",
+            strength: 20,
+            flags: 1,
+            rsn_flags: 0x488,
+        },
+    ];
+
+    struct AccessPointObject(&'static MockAccessPoint);
+
+    #[interface(name = "org.freedesktop.NetworkManager.AccessPoint")]
+    impl AccessPointObject {
+        #[zbus(property)]
+        fn ssid(&self) -> Vec<u8> {
+            self.0.ssid.as_bytes().to_vec()
+        }
+
+        #[zbus(property)]
+        fn strength(&self) -> u8 {
+            self.0.strength
+        }
+
+        #[zbus(property)]
+        fn flags(&self) -> u32 {
+            self.0.flags
+        }
+
+        #[zbus(property)]
+        fn wpa_flags(&self) -> u32 {
+            0
+        }
+
+        #[zbus(property)]
+        fn rsn_flags(&self) -> u32 {
+            self.0.rsn_flags
+        }
+    }
+
+    struct MockSettings {
+        mock: Shared,
+    }
+
+    #[interface(name = "org.freedesktop.NetworkManager.Settings")]
+    impl MockSettings {
+        fn list_connections(&self) -> Vec<OwnedObjectPath> {
+            self.mock
+                .lock()
+                .unwrap()
+                .profiles
+                .keys()
+                .map(|id| profile_path(*id))
+                .collect()
+        }
+    }
+
+    struct MockSettingsConnection {
+        mock: Shared,
+        id: u32,
+    }
+
+    impl MockSettingsConnection {
+        fn with<T>(&self, f: impl FnOnce(&mut Profile) -> T) -> fdo::Result<T> {
+            let mut mock = self.mock.lock().unwrap();
+            let profile = mock.profiles.get_mut(&self.id);
+            profile
+                .map(f)
+                .ok_or_else(|| fdo::Error::UnknownObject(profile_path(self.id).to_string()))
+        }
+    }
+
+    #[interface(name = "org.freedesktop.NetworkManager.Settings.Connection")]
+    impl MockSettingsConnection {
+        /// Like NetworkManager, without the secrets.
+        fn get_settings(&self) -> fdo::Result<Settings> {
+            self.with(|p| {
+                let mut settings = copy_settings(&p.settings);
+                for values in settings.values_mut() {
+                    values.retain(|k, _| !SECRETS.contains(&k.as_str()));
+                }
+                settings
+            })
+        }
+
+        fn get_secrets(&self, setting_name: &str) -> fdo::Result<Settings> {
+            self.with(|p| {
+                let secrets = p.settings.get(setting_name).map(|values| {
+                    let secrets = values.iter().filter(|(k, _)| SECRETS.contains(&k.as_str()));
+                    secrets
+                        .map(|(k, v)| (k.clone(), v.try_clone().unwrap()))
+                        .collect()
+                });
+                HashMap::from([(setting_name.to_string(), secrets.unwrap_or_default())])
+            })
+        }
+
+        fn update_unsaved(&self, properties: Settings) -> fdo::Result<()> {
+            self.with(|p| p.settings = properties)
+        }
+
+        fn save(&self) -> fdo::Result<()> {
+            self.with(|p| p.disk = Some(copy_settings(&p.settings)))
+        }
+
+        async fn delete(&self, #[zbus(object_server)] server: &ObjectServer) -> fdo::Result<()> {
+            self.mock.lock().unwrap().profiles.remove(&self.id);
+            server
+                .remove::<MockSettingsConnection, _>(profile_path(self.id))
+                .await?;
+            Ok(())
+        }
+    }
+
+    struct MockActiveConnection {
+        state: u32,
+    }
+
+    #[interface(name = "org.freedesktop.NetworkManager.Connection.Active")]
+    impl MockActiveConnection {
+        #[zbus(property)]
+        fn state(&self) -> u32 {
+            self.state
+        }
+    }
+
+    /// Serve a NetworkManager with the access points above and saved
+    /// profiles for Home (WPA2), Cabin (WEP) and Library (open).
+    async fn serve(bus: &PrivateBus, mock: &Shared) -> Connection {
+        let saved = [
+            profile("Home", &[("key-mgmt", "wpa-psk"), ("psk", "secret")]),
+            profile("Cabin", &[("key-mgmt", "none"), ("wep-key0", "oldkey")]),
+            profile("Library", &[]),
+        ];
+        let mut builder = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.freedesktop.NetworkManager")
+            .unwrap()
+            .serve_at(NM_PATH, MockNetworkManager { mock: mock.clone() })
+            .unwrap()
+            .serve_at(DEVICE_PATH, MockDevice { mock: mock.clone() })
+            .unwrap()
+            .serve_at(DEVICE_PATH, MockWireless { mock: mock.clone() })
+            .unwrap()
+            .serve_at(SETTINGS_PATH, MockSettings { mock: mock.clone() })
+            .unwrap();
+        for settings in saved {
+            let id = mock.lock().unwrap().add(settings, true);
+            builder = builder
+                .serve_at(
+                    profile_path(id),
+                    MockSettingsConnection {
+                        mock: mock.clone(),
+                        id,
+                    },
+                )
+                .unwrap();
+        }
+        for (i, ap) in ACCESS_POINTS.iter().enumerate() {
+            builder = builder
+                .serve_at(
+                    format!("{NM_PATH}/AccessPoint/{}", i + 1),
+                    AccessPointObject(ap),
+                )
+                .unwrap();
+        }
+        builder.build().await.unwrap()
+    }
+
+    fn network<'a>(status: &'a Status, ssid: &str) -> Option<&'a Network> {
+        status.networks.iter().find(|n| n.ssid == ssid)
+    }
+
+    #[test]
+    fn security_follows_the_strongest_key_management() {
+        assert_eq!(security(0, 0, 0), "open");
+        assert_eq!(security(AP_FLAGS_PRIVACY, 0, 0), "wep");
+        assert_eq!(
+            security(AP_FLAGS_PRIVACY, 0, KEY_MGMT_PSK | KEY_MGMT_SAE),
+            "wpa-psk"
+        );
+        assert_eq!(security(AP_FLAGS_PRIVACY, 0, KEY_MGMT_SAE), "sae");
+        assert_eq!(security(AP_FLAGS_PRIVACY, KEY_MGMT_802_1X, 0), "enterprise");
+        assert_eq!(security(0, 0, KEY_MGMT_OWE), "owe");
+        assert_eq!(password_key(Some("none")), Some("wep-key0"));
+        assert_eq!(password_key(Some("sae")), Some("psk"));
+        assert_eq!(password_key(Some("wpa-eap")), None);
+        assert_eq!(password_key(None), None);
+    }
+
+    #[tokio::test]
+    async fn talks_to_networkmanager_on_a_private_bus() {
+        let dir = temp_dir("wifi");
+        let Some(bus) = PrivateBus::start(&dir) else {
+            eprintln!("dbus-daemon not available; skipping");
+            return;
+        };
+        let mock = Shared::default();
+        let _nm = serve(&bus, &mock).await;
+        let conn = bus.connect().await;
+
+        let status = status(&conn).await.unwrap().unwrap();
+        assert!(status.enabled);
+        let ssids: Vec<_> = status.networks.iter().map(|n| n.ssid.as_str()).collect();
+        assert_eq!(
+            ssids,
+            ["Home", "Cafe", "Office", "Neighbour", "Cabin", "Library"]
+        );
+        let home = network(&status, "Home").unwrap();
+        assert!(home.known && home.in_range && !home.needs_password());
+        assert_eq!(network(&status, "Neighbour").unwrap().security, "sae");
+        assert!(!network(&status, "Office").unwrap().joinable());
+        // Out of range, nothing tells what security a saved network uses.
+        let cabin = network(&status, "Cabin").unwrap();
+        assert!(!cabin.in_range);
+        assert_eq!(cabin.security_label(), "Unknown");
+
+        // A new network is only kept once its password has worked.
+        assert!(connect_network(&conn, "Neighbour", Some("wrong"))
+            .await
+            .is_err());
+        assert!(mock.lock().unwrap().profile("Neighbour").is_none());
+        connect_network(&conn, "Neighbour", Some("hunter2"))
+            .await
+            .unwrap();
+        assert_eq!(
+            mock.lock()
+                .unwrap()
+                .saved_secret("Neighbour", "psk")
+                .as_deref(),
+            Some("hunter2")
+        );
+        let status = super::status(&conn).await.unwrap().unwrap();
+        assert_eq!(status.networks[0].ssid, "Neighbour");
+        assert!(status.networks[0].active && status.networks[0].known);
+
+        // A wrong new password for a saved network puts the old one back.
+        assert!(connect_network(&conn, "Home", Some("wrong")).await.is_err());
+        {
+            let mock = mock.lock().unwrap();
+            let home = mock.profile("Home").unwrap();
+            assert_eq!(
+                owned_string(&home.settings, SECURITY, "psk").as_deref(),
+                Some("secret")
+            );
+            assert_eq!(mock.saved_secret("Home", "psk").as_deref(), Some("secret"));
+        }
+        connect_network(&conn, "Home", Some("fresh")).await.unwrap();
+        assert_eq!(
+            mock.lock().unwrap().saved_secret("Home", "psk").as_deref(),
+            Some("fresh")
+        );
+        connect_network(&conn, "Home", None).await.unwrap();
+
+        // The key follows the saved profile's key management.
+        connect_network(&conn, "Cabin", Some("newkey"))
+            .await
+            .unwrap();
+        {
+            let mock = mock.lock().unwrap();
+            assert_eq!(
+                mock.saved_secret("Cabin", "wep-key0").as_deref(),
+                Some("newkey")
+            );
+            assert_eq!(mock.saved_secret("Cabin", "psk"), None);
+        }
+        let err = connect_network(&conn, "Library", Some("anything"))
+            .await
+            .unwrap_err();
+        assert!(err.contains("does not take a password"), "{err}");
+
+        disconnect(&conn).await.unwrap();
+        assert!(mock.lock().unwrap().active_ap.is_none());
+        forget(&conn, "Neighbour").await.unwrap();
+        assert!(mock.lock().unwrap().profile("Neighbour").is_none());
+        assert!(forget(&conn, "Nowhere").await.is_err());
+
+        drop(bus);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}